@@ -0,0 +1,15 @@
+CREATE TABLE break_stack_jobs
+(
+    id            INTEGER PRIMARY KEY NOT NULL,
+    name          TEXT                NOT NULL,
+    payload       TEXT                NOT NULL,
+    attempts      INTEGER             NOT NULL DEFAULT 0,
+    max_attempts  INTEGER             NOT NULL,
+    run_at        INTEGER             NOT NULL,
+    locked_at     INTEGER,
+    failed_at     INTEGER,
+    last_error    TEXT,
+    created_at    INTEGER             NOT NULL
+);
+
+CREATE INDEX break_stack_jobs_run_at ON break_stack_jobs (run_at) WHERE failed_at IS NULL;
@@ -0,0 +1,5 @@
+CREATE TABLE break_stack_scheduled_tasks (
+    name TEXT PRIMARY KEY NOT NULL,
+    last_run_at INTEGER NOT NULL,
+    last_error TEXT
+);
@@ -0,0 +1,10 @@
+CREATE TABLE break_stack_idempotency_keys (
+    scope TEXT NOT NULL,
+    key TEXT NOT NULL,
+    -- NULL while the first request is still being handled
+    status INTEGER,
+    headers TEXT,
+    body BLOB,
+    created_at INTEGER NOT NULL,
+    PRIMARY KEY (scope, key)
+);
@@ -0,0 +1,13 @@
+CREATE TABLE break_stack_attachments (
+    id INTEGER PRIMARY KEY NOT NULL,
+    model_name TEXT NOT NULL,
+    model_id TEXT NOT NULL,
+    field TEXT NOT NULL,
+    filename TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    storage_key TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX break_stack_attachments_model ON break_stack_attachments (model_name, model_id);
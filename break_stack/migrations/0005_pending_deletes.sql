@@ -0,0 +1,10 @@
+CREATE TABLE break_stack_pending_deletes (
+    model_name TEXT NOT NULL,
+    model_id TEXT NOT NULL,
+    user_id INTEGER,
+    purge_at INTEGER NOT NULL,
+    created_at INTEGER NOT NULL,
+    PRIMARY KEY (model_name, model_id)
+);
+
+CREATE INDEX break_stack_pending_deletes_purge_at ON break_stack_pending_deletes (model_name, purge_at);
@@ -0,0 +1,9 @@
+CREATE TABLE break_stack_wizard_drafts (
+    session_id TEXT NOT NULL,
+    wizard TEXT NOT NULL,
+    user_id INTEGER,
+    step INTEGER NOT NULL,
+    data TEXT NOT NULL,
+    updated_at INTEGER NOT NULL,
+    PRIMARY KEY (session_id, wizard)
+);
@@ -1,3 +1,8 @@
+use crate::errors::{AppError, AppResult};
+use crate::htmx::HtmxRequest;
+use crate::template_reload::{self, ReloadableTemplate};
+use axum::http::header::{HeaderValue, VARY};
+use axum::response::{IntoResponse, Response};
 pub use break_stack_macros::Component;
 
 pub trait ComponentAsRef {
@@ -5,4 +10,280 @@ pub trait ComponentAsRef {
     fn as_ref(self) -> Self::Ref;
 }
 
-pub trait Component: axum::response::IntoResponse {}
+pub trait Component: axum::response::IntoResponse {
+    /// Builds a response that works both as a fragment for htmx requests and as a full page
+    /// for direct navigation. Components declaring a layout with `#[component(layout = "...")]`
+    /// are wrapped in that layout when the request isn't an htmx request, or is boosted.
+    fn into_page_response(self, _htmx: &HtmxRequest) -> Response
+    where
+        Self: Sized,
+    {
+        self.into_response()
+    }
+}
+
+/// Component used to render full pages around the fragments of components that declare
+/// it as their layout. The content is already rendered html, so it should be included in
+/// the template using the `safe` filter.
+pub trait Layout: Component {
+    fn from_content(content: String) -> Self;
+}
+
+/// `Vary` of the responses of `page_response`, which are fragments for htmx requests, except
+/// boosted ones.
+pub(crate) const VARY_HTMX: &str = "HX-Request, HX-Boosted";
+
+pub fn page_response<L: Layout, T: askama::Template + ReloadableTemplate + IntoResponse>(
+    component: T,
+    htmx: &HtmxRequest,
+) -> Response {
+    let mut response = if htmx.is_htmx && !htmx.is_boosted {
+        template_reload::into_response(component)
+    } else {
+        match template_reload::render(&component) {
+            Ok(content) => L::from_content(content).into_response(),
+            Err(e) => {
+                AppError::Internal(format!("failed to render component: {}", e)).into_response()
+            }
+        }
+    };
+    response
+        .headers_mut()
+        .append(VARY, HeaderValue::from_static(VARY_HTMX));
+    response
+}
+
+/// Swap strategy for components included out of band in an `OobResponse`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OobSwap {
+    InnerHtml,
+    OuterHtml,
+    BeforeBegin,
+    AfterBegin,
+    BeforeEnd,
+    AfterEnd,
+    Delete,
+}
+
+impl OobSwap {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OobSwap::InnerHtml => "innerHTML",
+            OobSwap::OuterHtml => "outerHTML",
+            OobSwap::BeforeBegin => "beforebegin",
+            OobSwap::AfterBegin => "afterbegin",
+            OobSwap::BeforeEnd => "beforeend",
+            OobSwap::AfterEnd => "afterend",
+            OobSwap::Delete => "delete",
+        }
+    }
+}
+
+/// Response that renders a main component, followed by any number of components that are
+/// swapped out of band using `hx-swap-oob`. Components can be added either as `*Ref` types,
+/// or as references to the owned components.
+///
+/// ```ignore
+/// OobResponse::new(TodoItemViewComponentRef::new(&item))
+///     .oob_target(TodoCountComponentRef::new(count), OobSwap::InnerHtml, "#todo-count")
+///     .oob(&flash)
+///     .into_response()
+/// ```
+#[derive(Default)]
+pub struct OobResponse {
+    body: String,
+    error: Option<AppError>,
+}
+
+impl OobResponse {
+    pub fn new(component: impl ComponentAsRef<Ref: askama::Template + ReloadableTemplate>) -> Self {
+        let mut response = Self::default();
+        if let Some(content) = response.render(component) {
+            response.body.push_str(&content);
+        }
+        response
+    }
+
+    /// Response without a main component, only containing out of band swaps.
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    fn render(
+        &mut self,
+        component: impl ComponentAsRef<Ref: askama::Template + ReloadableTemplate>,
+    ) -> Option<String> {
+        match template_reload::render(&component.as_ref()) {
+            Ok(content) => Some(content),
+            Err(e) => {
+                self.error.get_or_insert_with(|| {
+                    AppError::Internal(format!("failed to render component: {}", e))
+                });
+                None
+            }
+        }
+    }
+
+    fn push_separator(&mut self) {
+        if !self.body.is_empty() {
+            self.body.push('\n');
+        }
+    }
+
+    /// Adds a component that replaces the element with the same id as the root element of
+    /// the component, i.e. `hx-swap-oob="true"`. The root element of the component must have
+    /// an `id`, otherwise htmx can't swap it and the response is an error: use `oob_target`
+    /// for such components.
+    pub fn oob(
+        mut self,
+        component: impl ComponentAsRef<Ref: askama::Template + ReloadableTemplate>,
+    ) -> Self {
+        let name = std::any::type_name_of_val(&component);
+        if let Some(content) = self.render(component) {
+            if !root_start_tag(&content).is_some_and(|(_, attrs)| has_id_attr(attrs)) {
+                self.error.get_or_insert_with(|| {
+                    AppError::Internal(format!(
+                        "out of band component {} has no root element with an id, use oob_target",
+                        name
+                    ))
+                });
+                return self;
+            }
+            self.push_separator();
+            self.body
+                .push_str(&with_root_attr(&content, "hx-swap-oob", "true"));
+        }
+        self
+    }
+
+    /// Adds a component that is swapped into the element(s) matching `selector` using `swap`.
+    pub fn oob_target(
+        mut self,
+        component: impl ComponentAsRef<Ref: askama::Template + ReloadableTemplate>,
+        swap: OobSwap,
+        selector: &str,
+    ) -> Self {
+        if let Some(content) = self.render(component) {
+            let value = format!("{}:{}", swap.as_str(), selector);
+            self.push_separator();
+            if swap == OobSwap::OuterHtml {
+                // htmx uses the element with the hx-swap-oob attribute itself for outerHTML
+                self.body
+                    .push_str(&with_root_attr(&content, "hx-swap-oob", &value));
+            } else {
+                // for the other strategies htmx strips the element with the hx-swap-oob
+                // attribute, so wrap the component to keep its root element
+                self.body.push_str(&format!(
+                    r#"<div hx-swap-oob="{}">{}</div>"#,
+                    escape_attr(&value),
+                    content
+                ));
+            }
+        }
+        self
+    }
+
+    pub fn try_into_response(self) -> AppResult<Response> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        Ok(axum::response::Html(self.body).into_response())
+    }
+}
+
+impl IntoResponse for OobResponse {
+    fn into_response(self) -> Response {
+        match self.try_into_response() {
+            Ok(response) => response,
+            Err(err) => err.into_response(),
+        }
+    }
+}
+
+fn escape_attr(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+}
+
+/// Finds the first element in `html`, returning the position of the end of its name and its
+/// attributes.
+fn root_start_tag(html: &str) -> Option<(usize, &str)> {
+    let (start, _) = html.char_indices().find(|(i, c)| {
+        *c == '<'
+            && html[i + 1..]
+                .chars()
+                .next()
+                .map(|c| c.is_ascii_alphabetic())
+                .unwrap_or(false)
+    })?;
+    let name_end = html[start + 1..]
+        .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        .map(|i| start + 1 + i)
+        .unwrap_or(html.len());
+    let mut quote = None;
+    let tag_end = html[name_end..]
+        .char_indices()
+        .find(|&(_, c)| match quote {
+            Some(q) => {
+                if c == q {
+                    quote = None;
+                }
+                false
+            }
+            None if c == '"' || c == '\'' => {
+                quote = Some(c);
+                false
+            }
+            None => c == '>',
+        })
+        .map(|(i, _)| name_end + i)
+        .unwrap_or(html.len());
+    Some((name_end, &html[name_end..tag_end]))
+}
+
+/// Returns the names of the attributes in `attrs`, the inside of a start tag after its name.
+fn attr_names(attrs: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut rest = attrs;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '/');
+        let Some(first) = rest.chars().next() else {
+            return names;
+        };
+        // the first character is part of the name, even if it's a `=`
+        let name_end = rest[first.len_utf8()..]
+            .find(|c: char| c.is_whitespace() || c == '=' || c == '/')
+            .map(|i| first.len_utf8() + i)
+            .unwrap_or(rest.len());
+        names.push(&rest[..name_end]);
+        rest = rest[name_end..].trim_start();
+        if let Some(value) = rest.strip_prefix('=') {
+            let value = value.trim_start();
+            rest = match value.chars().next() {
+                Some(q @ ('"' | '\'')) => value[1..].find(q).map(|i| &value[i + 2..]).unwrap_or(""),
+                _ => {
+                    let end = value.find(char::is_whitespace).unwrap_or(value.len());
+                    &value[end..]
+                }
+            };
+        }
+    }
+}
+
+fn has_id_attr(attrs: &str) -> bool {
+    attr_names(attrs)
+        .iter()
+        .any(|name| name.eq_ignore_ascii_case("id"))
+}
+
+/// Adds an attribute to the first element in `html`, or wraps `html` in a `div` with the
+/// attribute if it doesn't contain any elements.
+fn with_root_attr(html: &str, name: &str, value: &str) -> String {
+    let attr = format!(r#" {}="{}""#, name, escape_attr(value));
+    match root_start_tag(html) {
+        Some((name_end, _)) => format!("{}{}{}", &html[..name_end], attr, &html[name_end..]),
+        None => format!("<div{}>{}</div>", attr, html),
+    }
+}
@@ -1,17 +1,170 @@
 use crate::auth::UserId;
-use crate::components::Component;
+use crate::components::{Component, VARY_HTMX};
+use crate::dev_toolbar;
 use crate::errors::*;
+use crate::etag::{ETag, IfNoneMatch};
+use crate::flash::FlashResponse;
+use crate::htmx::{HtmxRequest, HtmxResponse};
+use crate::idempotency::{self, IdempotentForm};
 use crate::models::DBConn;
 use crate::models::*;
-use axum::{extract::Path, http::header::HeaderValue, response::Response, Form};
+use crate::session::SessionId;
+use crate::storage::{Attachment, MultipartForm, Storage, Upload, UploadLimits};
+use crate::undo::{self, UndoToast};
+use crate::wizard::{self, Wizard, WizardStepContext, WIZARD_BACK_FIELD, WIZARD_STEP_FIELD};
+use axum::{
+    body::Bytes,
+    extract::{DefaultBodyLimit, Multipart, OriginalUri, Path},
+    http::header,
+    response::{IntoResponse, Response},
+    Form,
+};
+use serde::de::DeserializeOwned;
 
 pub trait ModelController: Send + Sync + Sized {
-    type Model: Send + Sync + Sized;
+    type Model: Model + Send + Sync + Sized;
+
+    /// Window of `model_controller_create_idempotent`: the response to a request with an
+    /// idempotency key is stored, and replayed for requests with the same key (from the same
+    /// user) within this window, instead of creating another object. Without a window it
+    /// handles requests like `model_controller_create`.
+    const IDEMPOTENCY_WINDOW: Option<std::time::Duration> = None;
+
+    /// Messages shown as success flashes (see `flash`) after the "model-based" handlers have
+    /// created/updated/deleted an object.
+    const FLASH_CREATED: Option<&'static str> = None;
+    const FLASH_UPDATED: Option<&'static str> = None;
+    const FLASH_DELETED: Option<&'static str> = None;
+
+    /// How long an object deleted with `model_controller_delete_undoable` can be restored.
+    const UNDO_WINDOW: std::time::Duration = std::time::Duration::from_secs(10);
+
     fn build_response(
-        conn: &mut DBConn,
+        conn: &mut <Self::Model as Model>::Conn,
         user_id: Option<UserId>,
         m: Self::Model,
     ) -> impl std::future::Future<Output = AppResult<Response>> + Send;
+
+    /// Called by the "model-based" controller handlers, override this to render differently
+    /// depending on the htmx request headers, e.g. to render a full page for direct navigation.
+    fn build_page_response(
+        conn: &mut <Self::Model as Model>::Conn,
+        user_id: Option<UserId>,
+        _htmx: &HtmxRequest,
+        m: Self::Model,
+    ) -> impl std::future::Future<Output = AppResult<Response>> + Send {
+        Self::build_response(conn, user_id, m)
+    }
+
+    /// Hook called by `model_controller_create` after the `AuthModelCreate` check, before
+    /// the object is created. Returning an error aborts the request with that error.
+    fn before_create(
+        _conn: &mut <Self::Model as Model>::Conn,
+        _user_id: Option<UserId>,
+        _data: &<Self::Model as ModelCreate>::Create,
+    ) -> impl std::future::Future<Output = AppResult<()>> + Send
+    where
+        Self::Model: ModelCreate,
+    {
+        async { Ok(()) }
+    }
+
+    /// Hook called by `model_controller_create` after the object is created, before the
+    /// response is built. The hooks don't run in a transaction, so if this returns an
+    /// error the object will still be created.
+    fn after_create(
+        _conn: &mut <Self::Model as Model>::Conn,
+        _user_id: Option<UserId>,
+        _item: &Self::Model,
+    ) -> impl std::future::Future<Output = AppResult<()>> + Send
+    where
+        Self::Model: ModelCreate,
+    {
+        async { Ok(()) }
+    }
+
+    /// Hook called by `model_controller_write` after the `AuthModelWrite` check, before
+    /// the object is updated. Returning an error aborts the request with that error.
+    fn before_write(
+        _conn: &mut <Self::Model as Model>::Conn,
+        _user_id: Option<UserId>,
+        _id: <Self::Model as Model>::ID,
+        _data: &<Self::Model as ModelWrite>::Write,
+    ) -> impl std::future::Future<Output = AppResult<()>> + Send
+    where
+        Self::Model: ModelWrite,
+    {
+        async { Ok(()) }
+    }
+
+    /// Hook called by `model_controller_write` after the object is updated, before the
+    /// response is built.
+    fn after_write(
+        _conn: &mut <Self::Model as Model>::Conn,
+        _user_id: Option<UserId>,
+        _item: &Self::Model,
+    ) -> impl std::future::Future<Output = AppResult<()>> + Send
+    where
+        Self::Model: ModelWrite,
+    {
+        async { Ok(()) }
+    }
+
+    /// Hook called by `model_controller_delete` after the `AuthModelDelete` check, before
+    /// the object is deleted. Returning an error aborts the request with that error.
+    fn before_delete(
+        _conn: &mut <Self::Model as Model>::Conn,
+        _user_id: Option<UserId>,
+        _id: <Self::Model as Model>::ID,
+    ) -> impl std::future::Future<Output = AppResult<()>> + Send
+    where
+        Self::Model: ModelDelete,
+    {
+        async { Ok(()) }
+    }
+
+    /// Hook called by `model_controller_delete` after the object is deleted, before the
+    /// response is built.
+    fn after_delete(
+        _conn: &mut <Self::Model as Model>::Conn,
+        _user_id: Option<UserId>,
+        _item: &Self::Model,
+    ) -> impl std::future::Future<Output = AppResult<()>> + Send
+    where
+        Self::Model: ModelDelete,
+    {
+        async { Ok(()) }
+    }
+}
+
+pub trait ModelListController: Send + Sync + Sized {
+    type Model: Model + Send + Sync + Sized;
+    fn build_response(
+        conn: &mut <Self::Model as Model>::Conn,
+        user_id: Option<UserId>,
+        items: Vec<Self::Model>,
+    ) -> impl std::future::Future<Output = AppResult<Response>> + Send;
+
+    fn build_page_response(
+        conn: &mut <Self::Model as Model>::Conn,
+        user_id: Option<UserId>,
+        _htmx: &HtmxRequest,
+        items: Vec<Self::Model>,
+    ) -> impl std::future::Future<Output = AppResult<Response>> + Send {
+        Self::build_response(conn, user_id, items)
+    }
+}
+
+/// Controller for the multipart handlers, which store the uploaded files as `Attachment`s of the
+/// created/updated object.
+pub trait UploadController: ModelController<Model: Model<ID: std::fmt::Display>> {
+    const UPLOAD_LIMITS: UploadLimits = UploadLimits::DEFAULT;
+    /// The multipart handlers read the whole form before the auth checks, so by default they
+    /// reject requests without a user before reading anything.
+    const ANONYMOUS_UPLOADS: bool = false;
+
+    /// The id of an object, used to link the uploaded files to a newly created object.
+    fn model_id(m: &Self::Model) -> <Self::Model as Model>::ID;
 }
 
 pub trait InitController {
@@ -23,91 +176,577 @@ pub trait InitController {
     ) -> impl std::future::Future<Output = AppResult<Response>> + Send;
 }
 
+/// Adds the model event, and the flash message if there is one, to the response of a handler.
+fn model_event_response(
+    response: Response,
+    htmx: &HtmxRequest,
+    event: String,
+    flash: Option<&'static str>,
+) -> AppResult<Response> {
+    let response = HtmxResponse::new(response)
+        .trigger(event)
+        .try_into_response()?;
+    match flash {
+        Some(message) => FlashResponse::new(response, htmx)
+            .success(message)
+            .try_into_response(),
+        None => Ok(response),
+    }
+}
+
+/// Records the controller handling the request and the result of its authorization check in the
+/// dev toolbar, see `dev_toolbar`.
+fn authorize<C, M: Model, T>(
+    action: &str,
+    user_id: Option<UserId>,
+    result: Result<T, AuthError>,
+) -> Result<T, AuthError> {
+    dev_toolbar::record_controller(std::any::type_name::<C>());
+    dev_toolbar::record_auth(action, M::MODEL_NAME, user_id, result.as_ref().map(|_| ()));
+    result
+}
+
 pub async fn model_controller_read<H: ModelController<Model: AuthModelRead>>(
-    mut conn: DBConn,
+    mut conn: <H::Model as Model>::Conn,
     id: Path<<H::Model as Model>::ID>,
     user_id: Option<UserId>,
+    htmx: HtmxRequest,
+    if_none_match: IfNoneMatch,
 ) -> AppResult<Response> {
-    <H::Model as AuthModelRead>::can_read(&mut conn, *id, user_id).await?;
+    authorize::<H, H::Model, _>(
+        "can_read",
+        user_id,
+        <H::Model as AuthModelRead>::can_read(&mut conn, *id, user_id).await,
+    )?;
 
     let item = <H::Model as ModelRead>::read(&mut conn, *id)
         .await?
         .ok_or_else(|| AppError::NotFound)?;
-    H::build_response(&mut conn, user_id, item).await
+
+    let Some(version) = item.version() else {
+        let response = H::build_page_response(&mut conn, user_id, &htmx, item).await?;
+        return if_none_match.respond(response).await;
+    };
+    // The rendered response also depends on the user, and on if it's rendered as a full page
+    let etag = ETag::from_version(&[
+        <H::Model as Model>::MODEL_NAME,
+        &version,
+        &user_id
+            .map(|user_id| (*user_id).to_string())
+            .unwrap_or_default(),
+        if htmx.is_htmx && !htmx.is_boosted {
+            "fragment"
+        } else {
+            "page"
+        },
+    ]);
+    // Both responses vary on the htmx headers, since the tag does
+    let mut vary = header::HeaderMap::new();
+    vary.insert(header::VARY, header::HeaderValue::from_static(VARY_HTMX));
+    if if_none_match.matches(&etag) {
+        return Ok(etag.not_modified_with(&vary));
+    }
+    let mut response = H::build_page_response(&mut conn, user_id, &htmx, item).await?;
+    if !response.headers().contains_key(header::VARY) {
+        response.headers_mut().extend(vary);
+    }
+    Ok(etag.apply(response))
 }
 
 pub async fn model_controller_write<H: ModelController<Model: AuthModelWrite>>(
-    mut conn: DBConn,
+    mut conn: <H::Model as Model>::Conn,
     id: Path<<H::Model as Model>::ID>,
     user_id: Option<UserId>,
+    htmx: HtmxRequest,
     Form(data): Form<<H::Model as ModelWrite>::Write>,
 ) -> AppResult<Response> {
-    <H::Model as AuthModelWrite>::can_write(&mut conn, *id, user_id, &data).await?;
+    authorize::<H, H::Model, _>(
+        "can_write",
+        user_id,
+        <H::Model as AuthModelWrite>::can_write(&mut conn, *id, user_id, &data).await,
+    )?;
+    H::before_write(&mut conn, user_id, *id, &data).await?;
 
     let item = <H::Model as ModelWrite>::write(&mut conn, *id, data)
         .await?
         .ok_or_else(|| AppError::NotFound)?;
-    let mut response = H::build_response(&mut conn, user_id, item).await?;
-    response.headers_mut().insert(
-        "HX-Trigger",
-        <H::Model as Model>::event_updated()
-            .parse::<HeaderValue>()
-            .map_err(|e| {
-                AppError::Internal(format!(
-                    "failed to build HX-Trigger header: {}",
-                    e.to_string()
-                ))
-            })?,
-    );
-    Ok(response)
+    H::after_write(&mut conn, user_id, &item).await?;
+    let response = H::build_page_response(&mut conn, user_id, &htmx, item).await?;
+    model_event_response(
+        response,
+        &htmx,
+        <H::Model as Model>::event_updated(),
+        H::FLASH_UPDATED,
+    )
 }
 
 pub async fn model_controller_create<H: ModelController<Model: AuthModelCreate>>(
-    mut conn: DBConn,
+    mut conn: <H::Model as Model>::Conn,
     user_id: Option<UserId>,
+    htmx: HtmxRequest,
     Form(data): Form<<H::Model as ModelCreate>::Create>,
 ) -> AppResult<Response> {
-    <H::Model as AuthModelCreate>::can_create(&mut conn, user_id, &data).await?;
+    authorize::<H, H::Model, _>(
+        "can_create",
+        user_id,
+        <H::Model as AuthModelCreate>::can_create(&mut conn, user_id, &data).await,
+    )?;
+    model_controller_create_inner::<H>(&mut conn, user_id, &htmx, data).await
+}
 
-    let item = <H::Model as ModelCreate>::create(&mut conn, data).await?;
+/// Same as `model_controller_create`, but repeated requests with the same idempotency key get
+/// the stored response of the first one, see `ModelController::IDEMPOTENCY_WINDOW`.
+pub async fn model_controller_create_idempotent<H: ModelController<Model: AuthModelCreate>>(
+    mut conn: <H::Model as Model>::Conn,
+    user_id: Option<UserId>,
+    htmx: HtmxRequest,
+    form: IdempotentForm<<H::Model as ModelCreate>::Create>,
+) -> AppResult<Response> {
+    let IdempotentForm { key, data } = form;
+    authorize::<H, H::Model, _>(
+        "can_create",
+        user_id,
+        <H::Model as AuthModelCreate>::can_create(&mut conn, user_id, &data).await,
+    )?;
 
-    let mut response = H::build_response(&mut conn, user_id, item).await?;
-    response.headers_mut().insert(
-        "HX-Trigger",
-        <H::Model as Model>::event_created()
-            .parse::<HeaderValue>()
-            .map_err(|e| {
-                AppError::Internal(format!(
-                    "failed to build HX-Trigger header: {}",
-                    e.to_string()
-                ))
-            })?,
+    let (Some(key), Some(window)) = (key, H::IDEMPOTENCY_WINDOW) else {
+        return model_controller_create_inner::<H>(&mut conn, user_id, &htmx, data).await;
+    };
+    let scope = format!(
+        "{}:create:{}",
+        <H::Model as Model>::MODEL_NAME,
+        user_id
+            .map(|user_id| user_id.to_string())
+            .unwrap_or_default()
     );
-    Ok(response)
+    if let Some(response) = idempotency::begin(conn.db()?, &scope, &key, window).await? {
+        return Ok(response);
+    }
+    let result = model_controller_create_inner::<H>(&mut conn, user_id, &htmx, data).await;
+    idempotency::finish(conn.db()?, &scope, &key, result).await
+}
+
+async fn model_controller_create_inner<H: ModelController<Model: AuthModelCreate>>(
+    conn: &mut <H::Model as Model>::Conn,
+    user_id: Option<UserId>,
+    htmx: &HtmxRequest,
+    data: <H::Model as ModelCreate>::Create,
+) -> AppResult<Response> {
+    H::before_create(conn, user_id, &data).await?;
+
+    let item = <H::Model as ModelCreate>::create(conn, data).await?;
+    H::after_create(conn, user_id, &item).await?;
+
+    let response = H::build_page_response(conn, user_id, htmx, item).await?;
+    model_event_response(
+        response,
+        htmx,
+        <H::Model as Model>::event_created(),
+        H::FLASH_CREATED,
+    )
 }
 
 pub async fn model_controller_delete<H: ModelController<Model: AuthModelDelete>>(
-    mut conn: DBConn,
+    mut conn: <H::Model as Model>::Conn,
     id: Path<<H::Model as Model>::ID>,
     user_id: Option<UserId>,
+    htmx: HtmxRequest,
 ) -> AppResult<Response> {
-    <H::Model as AuthModelDelete>::can_delete(&mut conn, *id, user_id).await?;
+    authorize::<H, H::Model, _>(
+        "can_delete",
+        user_id,
+        <H::Model as AuthModelDelete>::can_delete(&mut conn, *id, user_id).await,
+    )?;
+    H::before_delete(&mut conn, user_id, *id).await?;
 
     let item = <H::Model as ModelDelete>::delete(&mut conn, *id).await?;
+    H::after_delete(&mut conn, user_id, &item).await?;
+
+    let response = H::build_page_response(&mut conn, user_id, &htmx, item).await?;
+    model_event_response(
+        response,
+        &htmx,
+        <H::Model as Model>::event_deleted(),
+        H::FLASH_DELETED,
+    )
+}
+
+/// Same as `model_controller_delete`, but the object is only hidden, and the response is an
+/// `UndoToast` with a button restoring it using `model_controller_restore`, which should be
+/// routed at `POST {path of the delete}/restore`. The object is deleted permanently by
+/// `undo::purge_expired` once `UNDO_WINDOW` has passed, `after_delete` is only called then.
+pub async fn model_controller_delete_undoable<
+    H: ModelController<Model: AuthModelDelete + ModelSoftDelete<ID: std::fmt::Display>>,
+>(
+    mut conn: <H::Model as Model>::Conn,
+    id: Path<<H::Model as Model>::ID>,
+    user_id: Option<UserId>,
+    htmx: HtmxRequest,
+    OriginalUri(uri): OriginalUri,
+) -> AppResult<Response> {
+    authorize::<H, H::Model, _>(
+        "can_delete",
+        user_id,
+        <H::Model as AuthModelDelete>::can_delete(&mut conn, *id, user_id).await,
+    )?;
+    H::before_delete(&mut conn, user_id, *id).await?;
 
-    let mut response = H::build_response(&mut conn, user_id, item).await?;
-    response.headers_mut().insert(
-        "HX-Trigger",
-        <H::Model as Model>::event_deleted()
-            .parse::<HeaderValue>()
-            .map_err(|e| {
-                AppError::Internal(format!(
-                    "failed to build HX-Trigger header: {}",
-                    e.to_string()
-                ))
-            })?,
+    undo::schedule_purge::<H::Model>(conn.db()?, *id, user_id, H::UNDO_WINDOW).await?;
+    if let Err(err) = <H::Model as ModelSoftDelete>::hide(&mut conn, *id).await {
+        undo::cancel_purge::<H::Model>(conn.db()?, *id).await?;
+        return Err(err.into());
+    }
+
+    let toast = UndoToast::new(
+        H::FLASH_DELETED.unwrap_or("Deleted"),
+        format!("{}/restore", uri.path().trim_end_matches('/')),
+        H::UNDO_WINDOW,
     );
-    Ok(response)
+    model_event_response(
+        toast.into_response(),
+        &htmx,
+        <H::Model as Model>::event_deleted(),
+        None,
+    )
+}
+
+/// Restores an object deleted with `model_controller_delete_undoable`, if its undo window
+/// hasn't passed, and the user passes the `AuthModelDelete` check. Responds with the restored
+/// object and the `{Model}Restored` event.
+pub async fn model_controller_restore<
+    H: ModelController<Model: AuthModelDelete + ModelSoftDelete<ID: std::fmt::Display>>,
+>(
+    mut conn: <H::Model as Model>::Conn,
+    id: Path<<H::Model as Model>::ID>,
+    user_id: Option<UserId>,
+    htmx: HtmxRequest,
+) -> AppResult<Response> {
+    authorize::<H, H::Model, _>(
+        "can_delete",
+        user_id,
+        <H::Model as AuthModelDelete>::can_delete(&mut conn, *id, user_id).await,
+    )?;
+    if !undo::is_purge_pending::<H::Model>(conn.db()?, *id).await? {
+        return Err(AppError::NotFound);
+    }
+
+    // The purge is only cancelled once the object is restored, so it's still deleted if the
+    // restore fails
+    let item = <H::Model as ModelSoftDelete>::restore(&mut conn, *id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    undo::cancel_purge::<H::Model>(conn.db()?, *id).await?;
+    let response = H::build_page_response(&mut conn, user_id, &htmx, item).await?;
+    model_event_response(response, &htmx, <H::Model as Model>::event_restored(), None)
+}
+
+/// Handler rendering the form of the current step of a `Wizard`, continuing the draft of the
+/// session if there is one.
+pub async fn wizard_controller_show<W: Wizard>(
+    mut conn: <W::Model as Model>::Conn,
+    user_id: Option<UserId>,
+    htmx: HtmxRequest,
+    session: SessionId,
+) -> AppResult<Response> {
+    dev_toolbar::record_controller(std::any::type_name::<W>());
+    let steps = W::steps();
+    if steps.is_empty() {
+        return Err(AppError::Internal(format!(
+            "wizard '{}' has no steps",
+            W::NAME
+        )));
+    }
+    let state = wizard::load::<W>(conn.db()?, &session, user_id, steps.len()).await?;
+    let context = WizardStepContext {
+        step: state.step,
+        steps: steps.len(),
+        errors: Vec::new(),
+    };
+    Ok(session.apply(steps[state.step].render(&state.draft, context, &htmx)))
+}
+
+/// Handler for the form of the current step of a `Wizard`. If the form is valid it's stored in
+/// the draft, and the form of the next step is rendered, otherwise the form of the step is
+/// rendered again with the errors. Forms without the `wizard_step` field of the current step
+/// (see `WizardStepContext::step_input`) aren't applied, the current step is rendered again
+/// instead. After the last step the object is created like with
+/// `model_controller_create`, including the `AuthModelCreate` check and the hooks, and the draft
+/// is discarded.
+pub async fn wizard_controller_submit<W: Wizard>(
+    mut conn: <W::Model as Model>::Conn,
+    user_id: Option<UserId>,
+    htmx: HtmxRequest,
+    session: SessionId,
+    body: Bytes,
+) -> AppResult<Response> {
+    dev_toolbar::record_controller(std::any::type_name::<W>());
+    let steps = W::steps();
+    if steps.is_empty() {
+        return Err(AppError::Internal(format!(
+            "wizard '{}' has no steps",
+            W::NAME
+        )));
+    }
+    let mut state = wizard::load::<W>(conn.db()?, &session, user_id, steps.len()).await?;
+    let render = |state: &wizard::WizardState<W::Draft>, errors: Vec<String>| {
+        let context = WizardStepContext {
+            step: state.step,
+            steps: steps.len(),
+            errors,
+        };
+        session.apply(steps[state.step].render(&state.draft, context, &htmx))
+    };
+
+    let fields = serde_urlencoded::from_bytes::<Vec<(String, String)>>(&body).unwrap_or_default();
+    let posted_step = fields
+        .iter()
+        .find(|(name, _)| name == WIZARD_STEP_FIELD)
+        .and_then(|(_, step)| step.parse::<usize>().ok());
+    if posted_step != Some(state.step) {
+        // A form of another step, e.g. submitted again from another tab, would be applied to the
+        // wrong step of the draft
+        return Ok(render(
+            &state,
+            vec!["This form is out of date, please fill in this step".to_string()],
+        ));
+    }
+    if fields.iter().any(|(name, _)| name == WIZARD_BACK_FIELD) {
+        state.step = state.step.saturating_sub(1);
+        wizard::save::<W>(conn.db()?, &session, user_id, &state).await?;
+        return Ok(render(&state, Vec::new()));
+    }
+
+    if let Err(errors) = steps[state.step].submit(&body, &mut state.draft) {
+        return Ok(render(&state, errors));
+    }
+    if state.step + 1 < steps.len() {
+        state.step += 1;
+        wizard::save::<W>(conn.db()?, &session, user_id, &state).await?;
+        return Ok(render(&state, Vec::new()));
+    }
+
+    // Keep the last step, in case the object can't be created
+    wizard::save::<W>(conn.db()?, &session, user_id, &state).await?;
+    let data = match W::create_data(&state.draft) {
+        Ok(data) => data,
+        Err(errors) => return Ok(render(&state, errors)),
+    };
+    authorize::<W, W::Model, _>(
+        "can_create",
+        user_id,
+        <W::Model as AuthModelCreate>::can_create(&mut conn, user_id, &data).await,
+    )?;
+    let response = model_controller_create_inner::<W>(&mut conn, user_id, &htmx, data).await?;
+    wizard::discard::<W>(conn.db()?, &session).await?;
+    Ok(session.apply(response))
+}
+
+pub async fn model_controller_list<H: ModelListController<Model: AuthModelList>>(
+    mut conn: <H::Model as Model>::Conn,
+    user_id: Option<UserId>,
+    htmx: HtmxRequest,
+) -> AppResult<Response> {
+    let items = authorize::<H, H::Model, _>(
+        "list",
+        user_id,
+        <H::Model as AuthModelList>::list(&mut conn, user_id).await,
+    )?;
+
+    H::build_page_response(&mut conn, user_id, &htmx, items).await
+}
+
+/// `DefaultBodyLimit` matching the `UPLOAD_LIMITS` of `H`, for the routes of the multipart
+/// handlers, since axum's default limit (2MB) applies to the whole request:
+///
+/// ```ignore
+/// post(model_controller_create_multipart::<C>).layer(upload_body_limit::<C>())
+/// ```
+pub fn upload_body_limit<H: UploadController>() -> DefaultBodyLimit {
+    DefaultBodyLimit::max(H::UPLOAD_LIMITS.max_body_size())
+}
+
+/// Rejects requests without a user before the multipart form is read, unless the controller
+/// allows anonymous uploads.
+fn check_upload_user<H: UploadController>(action: &str, user_id: Option<UserId>) -> AppResult<()> {
+    if user_id.is_none() && !H::ANONYMOUS_UPLOADS {
+        authorize::<H, H::Model, _>(action, user_id, Err(AuthError::Unauthenticated))?;
+    }
+    Ok(())
+}
+
+/// Stores the uploads as attachments of `id`. If one of them can't be stored, the ones already
+/// stored are deleted.
+async fn create_attachments<M: Model<ID: std::fmt::Display>>(
+    conn: &mut DBConn,
+    storage: &Storage,
+    id: M::ID,
+    uploads: Vec<Upload>,
+) -> AppResult<Vec<Attachment>> {
+    let mut attachments = Vec::new();
+    for upload in uploads {
+        match Attachment::create::<M>(conn, storage, id, upload).await {
+            Ok(attachment) => attachments.push(attachment),
+            Err(err) => {
+                delete_attachments(conn, storage, attachments).await;
+                return Err(err);
+            }
+        }
+    }
+    Ok(attachments)
+}
+
+/// Deletes the attachments stored by a request that failed afterwards.
+async fn delete_attachments(conn: &mut DBConn, storage: &Storage, attachments: Vec<Attachment>) {
+    for attachment in attachments {
+        // Report the original error, even if the files can't be cleaned up
+        let _ = attachment.delete(conn, storage).await;
+    }
+}
+
+/// Same as `model_controller_create`, but for `multipart/form-data` forms. The uploaded files
+/// are stored before `after_create` is called, and deleted again if it fails. If the files
+/// can't be stored, the created object is deleted with `ModelDelete::delete`, so it isn't left
+/// without its attachments.
+pub async fn model_controller_create_multipart<
+    H: UploadController<Model: AuthModelCreate + ModelDelete>,
+>(
+    mut conn: <H::Model as Model>::Conn,
+    user_id: Option<UserId>,
+    htmx: HtmxRequest,
+    storage: Storage,
+    multipart: Multipart,
+) -> AppResult<Response>
+where
+    <H::Model as ModelCreate>::Create: DeserializeOwned,
+{
+    check_upload_user::<H>("can_create", user_id)?;
+    let MultipartForm { data, uploads } =
+        MultipartForm::parse(multipart, &H::UPLOAD_LIMITS).await?;
+    authorize::<H, H::Model, _>(
+        "can_create",
+        user_id,
+        <H::Model as AuthModelCreate>::can_create(&mut conn, user_id, &data).await,
+    )?;
+    H::before_create(&mut conn, user_id, &data).await?;
+
+    let item = <H::Model as ModelCreate>::create(&mut conn, data).await?;
+    let id = H::model_id(&item);
+    let attachments = match create_attachments::<H::Model>(conn.db()?, &storage, id, uploads).await
+    {
+        Ok(attachments) => attachments,
+        Err(err) => {
+            // Report the original error, even if the object can't be deleted
+            let _ = <H::Model as ModelDelete>::delete(&mut conn, id).await;
+            return Err(err);
+        }
+    };
+    if let Err(err) = H::after_create(&mut conn, user_id, &item).await {
+        delete_attachments(conn.db()?, &storage, attachments).await;
+        return Err(err);
+    }
+
+    let response = H::build_page_response(&mut conn, user_id, &htmx, item).await?;
+    model_event_response(
+        response,
+        &htmx,
+        <H::Model as Model>::event_created(),
+        H::FLASH_CREATED,
+    )
+}
+
+/// Same as `model_controller_write`, but for `multipart/form-data` forms. The uploaded files are
+/// added to the existing attachments before `after_write` is called, and deleted again if it
+/// fails.
+pub async fn model_controller_write_multipart<H: UploadController<Model: AuthModelWrite>>(
+    mut conn: <H::Model as Model>::Conn,
+    id: Path<<H::Model as Model>::ID>,
+    user_id: Option<UserId>,
+    htmx: HtmxRequest,
+    storage: Storage,
+    multipart: Multipart,
+) -> AppResult<Response>
+where
+    <H::Model as ModelWrite>::Write: DeserializeOwned,
+{
+    check_upload_user::<H>("can_write", user_id)?;
+    let MultipartForm { data, uploads } =
+        MultipartForm::parse(multipart, &H::UPLOAD_LIMITS).await?;
+    authorize::<H, H::Model, _>(
+        "can_write",
+        user_id,
+        <H::Model as AuthModelWrite>::can_write(&mut conn, *id, user_id, &data).await,
+    )?;
+    H::before_write(&mut conn, user_id, *id, &data).await?;
+
+    let item = <H::Model as ModelWrite>::write(&mut conn, *id, data)
+        .await?
+        .ok_or_else(|| AppError::NotFound)?;
+    let attachments = create_attachments::<H::Model>(conn.db()?, &storage, *id, uploads).await?;
+    if let Err(err) = H::after_write(&mut conn, user_id, &item).await {
+        delete_attachments(conn.db()?, &storage, attachments).await;
+        return Err(err);
+    }
+
+    let response = H::build_page_response(&mut conn, user_id, &htmx, item).await?;
+    model_event_response(
+        response,
+        &htmx,
+        <H::Model as Model>::event_updated(),
+        H::FLASH_UPDATED,
+    )
+}
+
+/// Handler downloading an attachment of `M` by the attachment id, if the user can read the
+/// object it's attached to.
+pub async fn attachment_controller_download<M: AuthModelRead<ID: std::str::FromStr>>(
+    mut conn: <M as Model>::Conn,
+    Path(id): Path<i64>,
+    user_id: Option<UserId>,
+    storage: Storage,
+) -> AppResult<Response> {
+    let attachment = Attachment::read(conn.db()?, id)
+        .await?
+        .filter(|attachment| attachment.model_name == M::MODEL_NAME)
+        .ok_or(AppError::NotFound)?;
+    let model_id = attachment
+        .model_id
+        .parse::<M::ID>()
+        .map_err(|_| AppError::NotFound)?;
+    authorize::<M, M, _>(
+        "can_read",
+        user_id,
+        M::can_read(&mut conn, model_id, user_id).await,
+    )?;
+
+    let data = attachment.data(&storage).await?;
+    // Only keep characters that are safe in a quoted header value
+    let filename: String = attachment
+        .filename
+        .chars()
+        .map(|c| match c {
+            ' ' | '-' | '_' | '.' => c,
+            c if c.is_ascii_alphanumeric() => c,
+            _ => '_',
+        })
+        .collect();
+    Ok((
+        [
+            (header::CONTENT_TYPE, attachment.content_type),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        data,
+    )
+        .into_response())
+}
+
+/// Handler rendering a component that doesn't need any data, like an empty form.
+pub async fn component_controller_default<C: Component + Default>(htmx: HtmxRequest) -> Response {
+    dev_toolbar::record_controller(std::any::type_name::<C>());
+    C::default().into_page_response(&htmx)
 }
 
 pub async fn init_controller_from_query<C: InitController>(
@@ -115,6 +754,7 @@ pub async fn init_controller_from_query<C: InitController>(
     user_id: Option<UserId>,
     Form(data): Form<<C as InitController>::Init>,
 ) -> AppResult<Response> {
+    dev_toolbar::record_controller(std::any::type_name::<C>());
     <C as InitController>::build_response(&mut conn, data, user_id).await
 }
 
@@ -122,16 +762,67 @@ pub struct ComponentFromModelController<
     Model: Send + Sync + Sized,
     Comp: Component + From<Model> + Send + Sync + Sized,
 >(Model, Comp);
-impl<Model: Send + Sync + Sized, Comp: Component + From<Model> + Send + Sync + Sized>
-    ModelController for ComponentFromModelController<Model, Comp>
+impl<M: Model + Send + Sync + Sized, Comp: Component + From<M> + Send + Sync + Sized>
+    ModelController for ComponentFromModelController<M, Comp>
 {
-    type Model = Model;
+    type Model = M;
 
     async fn build_response(
-        _conn: &mut DBConn,
+        _conn: &mut M::Conn,
+        _user_id: Option<UserId>,
+        m: Self::Model,
+    ) -> AppResult<Response> {
+        Ok(<Comp as From<M>>::from(m).into_response())
+    }
+
+    async fn build_page_response(
+        _conn: &mut M::Conn,
         _user_id: Option<UserId>,
+        htmx: &HtmxRequest,
         m: Self::Model,
     ) -> AppResult<Response> {
-        Ok(<Comp as From<Model>>::from(m).into_response())
+        Ok(<Comp as From<M>>::from(m).into_page_response(htmx))
+    }
+}
+
+pub struct ComponentFromModelsController<
+    Model: Send + Sync + Sized,
+    Comp: Component + From<Vec<Model>> + Send + Sync + Sized,
+>(Model, Comp);
+impl<M: Model + Send + Sync + Sized, Comp: Component + From<Vec<M>> + Send + Sync + Sized>
+    ModelListController for ComponentFromModelsController<M, Comp>
+{
+    type Model = M;
+
+    async fn build_response(
+        _conn: &mut M::Conn,
+        _user_id: Option<UserId>,
+        items: Vec<Self::Model>,
+    ) -> AppResult<Response> {
+        Ok(<Comp as From<Vec<M>>>::from(items).into_response())
+    }
+
+    async fn build_page_response(
+        _conn: &mut M::Conn,
+        _user_id: Option<UserId>,
+        htmx: &HtmxRequest,
+        items: Vec<Self::Model>,
+    ) -> AppResult<Response> {
+        Ok(<Comp as From<Vec<M>>>::from(items).into_page_response(htmx))
+    }
+}
+
+/// Controller responding with an empty body, htmx will swap in the empty content, so this can
+/// be used to remove the element that triggered e.g. a delete.
+pub struct EmptyModelController<Model: Send + Sync + Sized>(Model);
+impl<M: Model + Send + Sync + Sized> ModelController for EmptyModelController<M> {
+    type Model = M;
+
+    async fn build_response(
+        _conn: &mut M::Conn,
+        _user_id: Option<UserId>,
+        _m: Self::Model,
+    ) -> AppResult<Response> {
+        Ok(().into_response())
     }
 }
@@ -0,0 +1,246 @@
+//! Panel showing what the recent requests did, for dev builds (see
+//! `hot_reload::hot_reload_enabled`): the statements they executed with their timings, the
+//! controllers and components involved, the authorization checks and the htmx events triggered.
+//!
+//! `record_requests` records the requests into an in-memory ring buffer, the panel is served by
+//! `hot_reload::reload_router` and shown by the hot reload script:
+//!
+//! ```ignore
+//! let mut app = routes::router().nest("/reload", hot_reload::reload_router());
+//! if hot_reload::hot_reload_enabled() {
+//!     app = app.layer(axum::middleware::from_fn(dev_toolbar::record_requests));
+//! }
+//! ```
+//!
+//! The statements are only recorded when the `DBConn` extractor of the app attaches the log of
+//! the request, with `queries::attach`.
+
+use crate::auth::UserId;
+use crate::components::*;
+use crate::errors::AuthError;
+use crate::htmx::{
+    HtmxTriggers, HX_REQUEST, HX_TRIGGER, HX_TRIGGER_AFTER_SETTLE, HX_TRIGGER_AFTER_SWAP,
+};
+use crate::queries::{self, Query};
+use axum::{extract::Request, middleware::Next, response::Response};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How many requests are kept.
+pub const RECENT_REQUESTS: usize = 20;
+
+/// What a request did, recorded by `record_requests`.
+#[derive(Debug, Clone)]
+pub struct RequestInfo {
+    pub id: u64,
+    pub method: String,
+    pub uri: String,
+    pub is_htmx: bool,
+    pub status: u16,
+    pub duration: Duration,
+    pub queries: Vec<Query>,
+    /// Type names of the controllers which handled the request.
+    pub controllers: Vec<String>,
+    /// Type names of the components rendered.
+    pub components: Vec<String>,
+    /// Authorization checks, like "can_read Note for user 1: Ok(())".
+    pub auth: Vec<String>,
+    pub triggers: Vec<String>,
+}
+
+#[derive(Default)]
+struct Recorded {
+    controllers: Vec<String>,
+    components: Vec<String>,
+    auth: Vec<String>,
+}
+
+static REQUESTS: Mutex<VecDeque<RequestInfo>> = Mutex::new(VecDeque::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+tokio::task_local! {
+    static CURRENT: Mutex<Recorded>;
+}
+
+/// Response extension making `record_requests` skip the request, set by `skip_recording`.
+#[derive(Debug, Clone, Copy)]
+pub struct NotRecorded;
+
+/// Middleware for routes whose requests `record_requests` shouldn't record, like the routes of
+/// `hot_reload::reload_router` (which include the toolbar itself).
+pub async fn skip_recording(request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    response.extensions_mut().insert(NotRecorded);
+    response
+}
+
+/// Middleware recording each request (except the ones marked by `skip_recording`), see the
+/// module docs. It gives the request a `QueryLog` like `queries::log_queries`.
+pub async fn record_requests(mut request: Request, next: Next) -> Response {
+    let log = queries::request_log(&mut request);
+    let method = request.method().to_string();
+    let uri = request.uri().to_string();
+    let is_htmx = request.headers().contains_key(HX_REQUEST);
+
+    let start = Instant::now();
+    let (response, recorded) = CURRENT
+        .scope(Mutex::new(Recorded::default()), async move {
+            let response = next.run(request).await;
+            let recorded = CURRENT.with(|current| std::mem::take(&mut *current.lock().unwrap()));
+            (response, recorded)
+        })
+        .await;
+    if response.extensions().get::<NotRecorded>().is_some() {
+        return response;
+    }
+
+    let triggers = [HX_TRIGGER, HX_TRIGGER_AFTER_SETTLE, HX_TRIGGER_AFTER_SWAP]
+        .into_iter()
+        .filter_map(|header| response.headers().get(header))
+        .filter_map(|value| HtmxTriggers::from_header(value).ok())
+        .flat_map(|triggers| triggers.0.into_iter().map(|(event, _)| event))
+        .collect();
+    let info = RequestInfo {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        method,
+        uri,
+        is_htmx,
+        status: response.status().as_u16(),
+        duration: start.elapsed(),
+        queries: log.queries(),
+        controllers: recorded.controllers,
+        components: recorded.components,
+        auth: recorded.auth,
+        triggers,
+    };
+
+    let mut requests = REQUESTS.lock().unwrap();
+    if requests.len() == RECENT_REQUESTS {
+        requests.pop_front();
+    }
+    requests.push_back(info);
+    response
+}
+
+fn record(f: impl FnOnce(&mut Recorded)) {
+    // Outside of a request recorded by `record_requests` there's nothing to do
+    let _ = CURRENT.try_with(|current| f(&mut current.lock().unwrap()));
+}
+
+/// Records the controller handling the current request, called by the handlers of `controllers`.
+pub fn record_controller(name: &str) {
+    record(|recorded| {
+        if recorded.controllers.last().map(String::as_str) != Some(name) {
+            recorded.controllers.push(name.to_string());
+        }
+    });
+}
+
+/// Records a component rendered for the current request, called by `template_reload::render`
+/// and `template_reload::into_response`, which render the components deriving `Component` in
+/// responses, page responses (with their layout) and out of band swaps.
+pub fn record_component(name: &str) {
+    record(|recorded| recorded.components.push(name.to_string()));
+}
+
+/// Records the result of an authorization check of the current request.
+pub fn record_auth(
+    action: &str,
+    model: &str,
+    user_id: Option<UserId>,
+    result: Result<(), &AuthError>,
+) {
+    record(|recorded| {
+        recorded.auth.push(format!(
+            "{} {} for {}: {:?}",
+            action,
+            model,
+            match user_id {
+                Some(user_id) => format!("user {}", *user_id),
+                None => "no user".to_string(),
+            },
+            result
+        ))
+    });
+}
+
+/// The recorded requests, the most recent first.
+pub fn recent_requests() -> Vec<RequestInfo> {
+    REQUESTS.lock().unwrap().iter().rev().cloned().collect()
+}
+
+impl RequestInfo {
+    fn duration_ms(&self) -> f64 {
+        self.duration.as_secs_f64() * 1000.0
+    }
+
+    /// Time spent running the statements.
+    fn queries_ms(&self) -> f64 {
+        let total: Duration = self.queries.iter().filter_map(|q| q.duration).sum();
+        total.as_secs_f64() * 1000.0
+    }
+
+    fn lists(&self) -> [(&'static str, &[String]); 4] {
+        [
+            ("Controllers", &self.controllers),
+            ("Components", &self.components),
+            ("Authorization", &self.auth),
+            ("Events", &self.triggers),
+        ]
+    }
+
+    /// Whether the statement of `query` was executed several times with different parameters.
+    fn is_repeated(&self, query: &Query) -> bool {
+        queries::repeated(&self.queries)
+            .iter()
+            .any(|(sql, _)| *sql == query.sql)
+    }
+}
+
+fn query_ms(query: &Query) -> Option<f64> {
+    query
+        .duration
+        .map(|duration| duration.as_secs_f64() * 1000.0)
+}
+
+/// The panel, with the recent requests, the first one expanded.
+#[derive(Component)]
+#[template(
+    source = r#"
+        <div class="break-stack-toolbar">
+            {%- for request in requests.clone() %}
+            <details{% if loop.first %} open{% endif %}>
+                <summary>#{{ request.id }} {{ request.method }} {{ request.uri }} {{ request.status }}{% if request.is_htmx %} htmx{% endif %} &middot; {{ "{:.1}"|format(request.duration_ms()) }} ms &middot; {{ request.queries.len() }} queries ({{ "{:.1}"|format(request.queries_ms()) }} ms)</summary>
+                {%- for (title, items) in request.lists() %}
+                {%- if !items.is_empty() %}
+                <h4>{{ title }}</h4>
+                <ul>{% for item in items.iter() %}<li>{{ item }}</li>{% endfor %}</ul>
+                {%- endif %}
+                {%- endfor %}
+                {%- if !request.queries.is_empty() %}
+                <h4>SQL</h4>
+                <ol>
+                    {%- for query in request.queries.iter() %}
+                    <li{% if request.is_repeated(query) %} class="repeated" title="executed several times with different parameters"{% endif %}><code>{{ query.expanded }}</code> {% match self::query_ms(query) %}{% when Some with (ms) %}{{ "{:.3}"|format(ms) }} ms{% when None %}-{% endmatch %}</li>
+                    {%- endfor %}
+                </ol>
+                {%- endif %}
+            </details>
+            {%- else %}
+            <p>No requests recorded yet</p>
+            {%- endfor %}
+        </div>
+    "#,
+    ext = "html"
+)]
+pub struct ToolbarComponent {
+    pub requests: Vec<RequestInfo>,
+}
+
+/// The html of the panel, with the recent requests.
+pub fn render_toolbar() -> String {
+    let requests = recent_requests();
+    ToolbarComponentRef::new(&requests).to_string()
+}
@@ -0,0 +1,159 @@
+use crate::errors::*;
+use crate::hot_reload::hot_reload_build_id;
+use axum::{
+    async_trait,
+    body::{Body, HttpBody},
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+/// An entity tag for a response, including the quotes (and the `W/` prefix for weak tags).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ETag(String);
+
+impl ETag {
+    /// Strong tag from the bytes of the response body.
+    pub fn from_body(body: &[u8]) -> Self {
+        Self(format!("\"{:016x}\"", fnv1a(FNV_OFFSET_BASIS, body)))
+    }
+
+    /// Weak tag from a version (see `Model::version`), and anything else the rendered
+    /// response depends on, like the user or whether a full page is rendered. The tags also
+    /// change with the build (see `hot_reload::hot_reload_build_id`) and the break_stack
+    /// version, since the templates might have changed.
+    pub fn from_version(parts: &[&str]) -> Self {
+        let mut hash = FNV_OFFSET_BASIS;
+        for part in [hot_reload_build_id(), env!("CARGO_PKG_VERSION")]
+            .iter()
+            .chain(parts)
+        {
+            hash = fnv1a(hash, part.as_bytes());
+            // Separator, so that e.g. ["ab", "c"] and ["a", "bc"] get different tags
+            hash = fnv1a(hash, &[0xff]);
+        }
+        Self(format!("W/\"{:016x}\"", hash))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    fn opaque_tag(&self) -> &str {
+        self.0.trim_start_matches("W/")
+    }
+
+    /// Response with `304 Not Modified` and this tag.
+    pub fn not_modified(&self) -> Response {
+        let mut response = StatusCode::NOT_MODIFIED.into_response();
+        self.insert(&mut response);
+        response
+    }
+
+    /// Response with `304 Not Modified`, this tag, and the headers of `headers` that RFC 9110
+    /// requires on a 304 when the `200 OK` response would have them, like `Vary`.
+    pub fn not_modified_with(&self, headers: &HeaderMap) -> Response {
+        let mut response = self.not_modified();
+        for name in [
+            header::CACHE_CONTROL,
+            header::CONTENT_LOCATION,
+            header::EXPIRES,
+            header::VARY,
+        ] {
+            for value in headers.get_all(&name) {
+                response.headers_mut().append(name.clone(), value.clone());
+            }
+        }
+        response
+    }
+
+    fn insert(&self, response: &mut Response) {
+        if let Ok(value) = HeaderValue::from_str(&self.0) {
+            response.headers_mut().insert(header::ETAG, value);
+        }
+    }
+
+    /// Sets the `ETag` header on successful responses that don't already have one.
+    pub fn apply(&self, mut response: Response) -> Response {
+        if !response.status().is_success() || response.headers().contains_key(header::ETAG) {
+            return response;
+        }
+        self.insert(&mut response);
+        response
+    }
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// 64 bit FNV-1a, which unlike `DefaultHasher` gives the same tags across Rust releases.
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+/// Largest body `IfNoneMatch::respond` buffers to tag it, larger bodies are sent untagged.
+pub const MAX_TAGGED_BODY_SIZE: u64 = 1024 * 1024;
+
+/// Extractor for the `If-None-Match` request header. Extracting this never fails, a missing or
+/// invalid header matches nothing.
+#[derive(Debug, Clone, Default)]
+pub struct IfNoneMatch(Option<String>);
+
+impl IfNoneMatch {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(Some(value.into()))
+    }
+
+    /// Checks if the tag is in the header, using the weak comparison from RFC 9110.
+    pub fn matches(&self, etag: &ETag) -> bool {
+        let Some(value) = &self.0 else {
+            return false;
+        };
+        value
+            .split(',')
+            .map(str::trim)
+            .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag.opaque_tag())
+    }
+
+    /// Buffers the response to tag it with a hash of the body, and replaces it with
+    /// `304 Not Modified` if the client already has it. Responses that aren't successful,
+    /// already have an `ETag`, or have a streaming body or one larger than
+    /// `MAX_TAGGED_BODY_SIZE`, are returned as they are.
+    pub async fn respond(&self, response: Response) -> AppResult<Response> {
+        if !response.status().is_success() || response.headers().contains_key(header::ETAG) {
+            return Ok(response);
+        }
+        let size = response.body().size_hint().exact();
+        if size.is_none_or(|size| size > MAX_TAGGED_BODY_SIZE) {
+            return Ok(response);
+        }
+        let (parts, body) = response.into_parts();
+        let body = axum::body::to_bytes(body, MAX_TAGGED_BODY_SIZE as usize)
+            .await
+            .map_err(|e| AppError::Internal(format!("failed to read response body: {}", e)))?;
+        let etag = ETag::from_body(&body);
+        if self.matches(&etag) {
+            return Ok(etag.not_modified_with(&parts.headers));
+        }
+        Ok(etag.apply(Response::from_parts(parts, Body::from(body))))
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for IfNoneMatch {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self(
+            parts
+                .headers
+                .get(header::IF_NONE_MATCH)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string()),
+        ))
+    }
+}
@@ -0,0 +1,415 @@
+use crate::components::*;
+use crate::errors::*;
+use crate::htmx::{HtmxRequest, HtmxResponse, HX_TRIGGER};
+use axum::{
+    async_trait,
+    body::Body,
+    extract::{FromRequestParts, Request},
+    http::{header, request::Parts, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::fmt;
+use std::sync::Mutex;
+
+/// Name of the htmx event the flashes are sent with, the detail is `{"messages": [...]}`.
+pub const FLASH_EVENT: &str = "flash";
+/// Cookie the flashes are stored in for requests not sent by htmx, until the next page load. The
+/// value is `<signature>.<percent encoded json>`, see `set_signing_key`.
+pub const FLASH_COOKIE: &str = "break_stack_flash";
+
+/// Longest error message `flash_errors` shows, longer response bodies are replaced with a generic
+/// message.
+const MAX_ERROR_MESSAGE_LEN: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FlashLevel {
+    Success,
+    Info,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for FlashLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            FlashLevel::Success => "success",
+            FlashLevel::Info => "info",
+            FlashLevel::Warning => "warning",
+            FlashLevel::Error => "error",
+        })
+    }
+}
+
+/// A message shown to the user as a toast by the script of `flash_toasts_tag`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Flash {
+    pub level: FlashLevel,
+    pub message: String,
+}
+
+impl Flash {
+    pub fn new(level: FlashLevel, message: impl Into<String>) -> Self {
+        Self {
+            level,
+            message: message.into(),
+        }
+    }
+
+    pub fn success(message: impl Into<String>) -> Self {
+        Self::new(FlashLevel::Success, message)
+    }
+
+    pub fn info(message: impl Into<String>) -> Self {
+        Self::new(FlashLevel::Info, message)
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self::new(FlashLevel::Warning, message)
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::new(FlashLevel::Error, message)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct FlashMessages {
+    messages: Vec<Flash>,
+}
+
+impl FlashMessages {
+    /// Flashes already added to the `HX-Trigger` header of a response.
+    fn from_trigger_header(value: Option<&HeaderValue>) -> Self {
+        value
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| value.trim_start().starts_with('{'))
+            .and_then(|value| {
+                serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(value).ok()
+            })
+            .and_then(|mut events| events.remove(FLASH_EVENT))
+            .and_then(|detail| serde_json::from_value(detail).ok())
+            .unwrap_or_default()
+    }
+
+    /// Flashes stored in the cookie by `FlashResponse::try_into_response`, nothing if the
+    /// signature doesn't match.
+    fn from_cookie(value: &str) -> Self {
+        verify(value)
+            .and_then(percent_decode)
+            .and_then(|value| serde_json::from_str(&value).ok())
+            .unwrap_or_default()
+    }
+}
+
+static SIGNING_KEY: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+
+/// Sets the key the flash cookie is signed with. Without a key, a random one is generated when
+/// the first flash is stored, so flashes stored by another instance of the app (or before a
+/// restart) are ignored by `CookieFlashes`.
+pub fn set_signing_key(key: impl Into<Vec<u8>>) {
+    *SIGNING_KEY.lock().unwrap() = Some(key.into());
+}
+
+fn mac() -> Hmac<Sha256> {
+    let mut key = SIGNING_KEY.lock().unwrap();
+    let key = key.get_or_insert_with(|| {
+        [uuid::Uuid::new_v4(), uuid::Uuid::new_v4()]
+            .iter()
+            .flat_map(|uuid| *uuid.as_bytes())
+            .collect()
+    });
+    Hmac::new_from_slice(key).expect("HMAC accepts keys of any length")
+}
+
+/// Hex encoded signature of a cookie value.
+fn sign(value: &str) -> String {
+    let mut mac = mac();
+    mac.update(value.as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Returns the value of a signed cookie if its signature is valid.
+fn verify(signed: &str) -> Option<&str> {
+    let (signature, value) = signed.split_once('.')?;
+    let signature = (0..signature.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(signature.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    let mut mac = mac();
+    mac.update(value.as_bytes());
+    mac.verify_slice(&signature).ok()?;
+    Some(value)
+}
+
+fn percent_decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Wrapper around a response that shows flash messages. For htmx requests the messages are sent
+/// as a `flash` event in `HX-Trigger`, so they're shown without a page load, for other requests
+/// (like a form post followed by a redirect) they're stored in a signed cookie and shown on the
+/// next page that's loaded.
+///
+/// ```ignore
+/// FlashResponse::new(TodoItemViewComponent { item }, &htmx)
+///     .success("Saved")
+///     .into_response()
+/// ```
+pub struct FlashResponse {
+    response: Response,
+    is_htmx: bool,
+    flashes: Vec<Flash>,
+}
+
+impl FlashResponse {
+    pub fn new(response: impl IntoResponse, htmx: &HtmxRequest) -> Self {
+        Self {
+            response: response.into_response(),
+            is_htmx: htmx.is_htmx,
+            flashes: Vec::new(),
+        }
+    }
+
+    pub fn flash(mut self, flash: Flash) -> Self {
+        self.flashes.push(flash);
+        self
+    }
+
+    pub fn success(self, message: impl Into<String>) -> Self {
+        self.flash(Flash::success(message))
+    }
+
+    pub fn error(self, message: impl Into<String>) -> Self {
+        self.flash(Flash::error(message))
+    }
+
+    pub fn try_into_response(self) -> AppResult<Response> {
+        if self.flashes.is_empty() {
+            return Ok(self.response);
+        }
+
+        if self.is_htmx {
+            // Events with the same name would overwrite each other, so add to the existing list
+            let mut flashes =
+                FlashMessages::from_trigger_header(self.response.headers().get(HX_TRIGGER));
+            flashes.messages.extend(self.flashes);
+            return HtmxResponse::new(self.response)
+                .trigger_with_detail(FLASH_EVENT, flashes)
+                .try_into_response();
+        }
+
+        let flashes = serde_json::to_string(&FlashMessages {
+            messages: self.flashes,
+        })
+        .map_err(|e| AppError::Internal(format!("failed to serialize flashes: {}", e)))?;
+        // Percent encoded so it's a valid cookie value, and can be read with `decodeURIComponent`
+        let flashes: String = flashes
+            .bytes()
+            .map(|b| match b {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                    (b as char).to_string()
+                }
+                _ => format!("%{:02X}", b),
+            })
+            .collect();
+        let cookie = format!(
+            "{}={}.{}; Path=/; SameSite=Lax",
+            FLASH_COOKIE,
+            sign(&flashes),
+            flashes
+        )
+        .parse::<HeaderValue>()
+        .map_err(|e| AppError::Internal(format!("failed to build flash cookie: {}", e)))?;
+        let mut response = self.response;
+        response.headers_mut().append(header::SET_COOKIE, cookie);
+        Ok(response)
+    }
+}
+
+impl IntoResponse for FlashResponse {
+    fn into_response(self) -> Response {
+        match self.try_into_response() {
+            Ok(response) => response,
+            Err(err) => err.into_response(),
+        }
+    }
+}
+
+/// Extractor for the flashes stored in the cookie by a previous response, to render them on the
+/// server with `FlashToastsComponent` instead of leaving them to the script of
+/// `flash_toasts_tag`. Extracting this never fails, and `apply` needs to be called on the response
+/// to clear the cookie, so the flashes are only shown once.
+///
+/// Unlike the script, which can't check the signature of the cookie, this ignores cookies that
+/// weren't set by the app (e.g. by another site on a sibling subdomain), so pages showing flashes
+/// that shouldn't be forged should render them with this.
+///
+/// ```ignore
+/// async fn index(flashes: CookieFlashes, htmx: HtmxRequest) -> Response {
+///     flashes.apply(IndexPage { flashes: flashes.flashes().to_vec() }.into_page_response(&htmx))
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CookieFlashes {
+    flashes: Vec<Flash>,
+    has_cookie: bool,
+}
+
+impl CookieFlashes {
+    pub fn flashes(&self) -> &[Flash] {
+        &self.flashes
+    }
+
+    /// Clears the flash cookie on the response if the request had one.
+    pub fn apply(&self, mut response: Response) -> Response {
+        if !self.has_cookie {
+            return response;
+        }
+        let cookie = format!("{}=; Path=/; Max-Age=0", FLASH_COOKIE);
+        if let Ok(value) = HeaderValue::from_str(&cookie) {
+            response.headers_mut().append(header::SET_COOKIE, value);
+        }
+        response
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for CookieFlashes {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let cookie = parts
+            .headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .find(|(name, _)| *name == FLASH_COOKIE)
+            .map(|(_, value)| value);
+        Ok(match cookie {
+            Some(value) => Self {
+                flashes: FlashMessages::from_cookie(value).messages,
+                has_cookie: true,
+            },
+            None => Self::default(),
+        })
+    }
+}
+
+/// Toasts rendered on the server, followed by `flash_toasts_tag`, whose script moves them into its
+/// container. Include it instead of `flash_toasts_tag` in pages which have `CookieFlashes`:
+/// `{{ break_stack::flash::FlashToastsComponentRef::new(flashes)|safe }}`.
+#[derive(Component)]
+#[template(
+    source = r#"
+        {%- for flash in flashes.clone() %}
+        <div class="break-stack-flash break-stack-flash-{{ flash.level }}" data-break-stack-flash="{{ flash.level }}">{{ flash.message }}</div>
+        {%- endfor %}
+        {{ break_stack::flash::flash_toasts_tag()|safe }}
+    "#,
+    ext = "html"
+)]
+pub struct FlashToastsComponent {
+    pub flashes: Vec<Flash>,
+}
+
+/// Middleware showing error responses to htmx requests as error flashes, since htmx doesn't swap
+/// in the content of error responses by default. Use with `axum::middleware::from_fn`.
+pub async fn flash_errors(htmx: HtmxRequest, request: Request, next: Next) -> Response {
+    let response = next.run(request).await;
+    let status = response.status();
+    if !htmx.is_htmx || !(status.is_client_error() || status.is_server_error()) {
+        return response;
+    }
+    if !FlashMessages::from_trigger_header(response.headers().get(HX_TRIGGER))
+        .messages
+        .is_empty()
+    {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(err) => {
+            return AppError::Internal(format!("failed to read error response: {}", err))
+                .into_response()
+        }
+    };
+    let message = std::str::from_utf8(&body)
+        .ok()
+        .map(str::trim)
+        .filter(|message| {
+            !message.is_empty()
+                && message.len() <= MAX_ERROR_MESSAGE_LEN
+                && !message.starts_with('<')
+        })
+        .unwrap_or("Something went wrong")
+        .to_string();
+    FlashResponse::new(Response::from_parts(parts, Body::from(body)), &htmx)
+        .error(message)
+        .into_response()
+}
+
+/// Container and script showing the flashes as toasts, include this at the end of the `<body>` of
+/// the layout: `{{ break_stack::flash::flash_toasts_tag()|safe }}`. The toasts can be styled with
+/// the `break-stack-flash` and `break-stack-flash-{success,info,warning,error}` classes.
+pub fn flash_toasts_tag() -> &'static str {
+    r##"<div id="break-stack-flash" aria-live="polite" style="position: fixed; top: 1rem; right: 1rem; z-index: 1000; display: flex; flex-direction: column; gap: 0.5rem;"></div>
+<script>
+(function () {
+  const container = document.getElementById("break-stack-flash");
+  const colors = { success: "#2e7d32", info: "#1565c0", warning: "#ef6c00", error: "#c62828" };
+  function add(toast, level) {
+    toast.setAttribute("role", level === "error" ? "alert" : "status");
+    toast.style.cssText = "padding: 0.75rem 1rem; border-radius: 4px; color: white; cursor: pointer; background: " + (colors[level] || colors.info);
+    toast.addEventListener("click", () => toast.remove());
+    container.appendChild(toast);
+    setTimeout(() => toast.remove(), level === "error" ? 10000 : 4000);
+  }
+  function show(flashes) {
+    for (const flash of (flashes && flashes.messages) || []) {
+      const toast = document.createElement("div");
+      toast.className = "break-stack-flash break-stack-flash-" + flash.level;
+      toast.textContent = flash.message;
+      add(toast, flash.level);
+    }
+  }
+  // Toasts rendered on the server by `FlashToastsComponent`
+  for (const toast of document.querySelectorAll("[data-break-stack-flash]")) {
+    add(toast, toast.dataset.breakStackFlash);
+  }
+  document.body.addEventListener("flash", (event) => show(event.detail));
+  const cookie = document.cookie.split("; ").find((c) => c.startsWith("break_stack_flash="));
+  if (cookie) {
+    document.cookie = "break_stack_flash=; Path=/; Max-Age=0";
+    try {
+      const value = cookie.split("=")[1];
+      show(JSON.parse(decodeURIComponent(value.slice(value.indexOf(".") + 1))));
+    } catch (e) {}
+  }
+})();
+</script>"##
+}
@@ -1,30 +1,145 @@
-use axum::{response::IntoResponse, routing::get, Router};
+use crate::dev_toolbar;
+use axum::{
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse,
+    },
+    routing::get,
+    Router,
+};
+use futures_util::stream::{self, Stream, StreamExt};
+use std::convert::Infallible;
+
+/// Env var setting what the hot reload script logs to the console: `reload` logs the
+/// connections and reloads, `htmx` also logs all the htmx events (`htmx.logAll()`). Read when
+/// the script is served, so it can be changed without rebuilding.
+pub const HOT_RELOAD_LOG_ENV: &str = "HOT_RELOAD_LOG";
 
 fn hot_reload_script() -> String {
+    let log = std::env::var(HOT_RELOAD_LOG_ENV).unwrap_or_default();
     format!(
         r#"
-let current_version = "{}".trim();
-async function watch() {{
-  const response = await fetch("/reload/build_id");
-  const version = (await response.text()).trim();
-  if (version.length > 0 && version != current_version) {{
-    console.log("old version", current_version);
-    console.log("new version", version);
-    window.location.reload();
+const currentVersion = "{}".trim();
+const logReload = {};
+const logHtmx = {};
+const stateKey = "break-stack-hot-reload";
+const fieldsSelector = "input, textarea, select";
+
+function debug(...args) {{
+  if (logReload) console.log("[hot reload]", ...args);
+}}
+if (logHtmx) htmx.logAll();
+
+// Keep the scroll position and the state of the form inputs across the reload
+function saveState() {{
+  const fields = Array.from(document.querySelectorAll(fieldsSelector));
+  const state = {{
+    url: location.href,
+    x: window.scrollX,
+    y: window.scrollY,
+    fields: fields
+      .map((field, index) => ({{ index, id: field.id, name: field.name, value: field.value, checked: field.checked, type: field.type }}))
+      .filter((field) => field.type !== "password" && field.type !== "file"),
+    focused: fields.indexOf(document.activeElement),
+  }};
+  if (state.focused >= 0) {{
+    state.selection = [document.activeElement.selectionStart, document.activeElement.selectionEnd];
+  }}
+  sessionStorage.setItem(stateKey, JSON.stringify(state));
+}}
+
+function restoreState() {{
+  const state = JSON.parse(sessionStorage.getItem(stateKey));
+  sessionStorage.removeItem(stateKey);
+  if (state === null || state.url !== location.href) return;
+  const fields = document.querySelectorAll(fieldsSelector);
+  // Only restore the inputs of a page with the same inputs
+  const same = (saved) => fields[saved.index] && fields[saved.index].id === saved.id && fields[saved.index].name === saved.name;
+  for (const saved of state.fields.filter(same)) {{
+    const field = fields[saved.index];
+    if (field.type === "checkbox" || field.type === "radio") {{
+      field.checked = saved.checked;
+    }} else {{
+      field.value = saved.value;
+    }}
+  }}
+  const focused = fields[state.focused];
+  if (focused) {{
+    focused.focus();
+    try {{
+      focused.setSelectionRange(...state.selection);
+    }} catch {{}}
   }}
+  window.scrollTo(state.x, state.y);
+  debug("restored", state);
 }}
+document.addEventListener("DOMContentLoaded", restoreState);
 
-setInterval(watch, 2000);
+// The server sends its build id when connecting, the connection is lost while it restarts
+let retryDelay = 100;
+function connect() {{
+  const events = new EventSource("/reload/events");
+  events.addEventListener("build", (event) => {{
+    retryDelay = 100;
+    const version = event.data.trim();
+    debug("connected to version", version);
+    if (version.length > 0 && version !== currentVersion) {{
+      debug("reloading, old version", currentVersion);
+      events.close();
+      saveState();
+      window.location.reload();
+    }}
+  }});
+  events.addEventListener("error", () => {{
+    events.close();
+    debug("disconnected, reconnecting in", retryDelay, "ms");
+    setTimeout(connect, retryDelay);
+    retryDelay = Math.min(retryDelay * 2, 5000);
+  }});
+}}
+connect();
 
-htmx.logAll();"#,
-        hot_reload_build_id()
+// Dev toolbar, see `dev_toolbar`
+const toolbar = document.createElement("div");
+toolbar.id = "break-stack-toolbar";
+toolbar.style = "position: fixed; bottom: 0; right: 0; max-width: 50vw; max-height: 50vh; overflow: auto; z-index: 10000; background: #fff; border: 1px solid #888; font: 12px monospace; padding: 4px;";
+async function refreshToolbar() {{
+  const response = await fetch("/reload/toolbar");
+  toolbar.innerHTML = await response.text();
+}}
+document.addEventListener("DOMContentLoaded", () => {{
+  document.body.appendChild(toolbar);
+  refreshToolbar();
+}});
+document.addEventListener("htmx:afterRequest", refreshToolbar);"#,
+        hot_reload_build_id(),
+        log == "reload" || log == "htmx",
+        log == "htmx"
     )
 }
 
+/// Server-sent events for the hot reload script: a `build` event with the build id of the server
+/// when connecting, then the connection is kept open until the server stops. The script
+/// reconnects until the new server is up, and reloads the page if its build id is different.
+pub async fn build_events() -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let event = Event::default().event("build").data(hot_reload_build_id());
+    Sse::new(stream::once(async move { Ok(event) }).chain(stream::pending()))
+        .keep_alive(KeepAlive::default())
+}
+
 pub fn reload_router<S: Clone + Send + Sync + 'static>() -> Router<S> {
     if hot_reload_enabled() {
         Router::new()
             .route("/build_id", get(|| async { hot_reload_build_id() }))
+            .route("/events", get(build_events))
+            .route(
+                "/toolbar",
+                get(|| async {
+                    dev_toolbar::ToolbarComponent {
+                        requests: dev_toolbar::recent_requests(),
+                    }
+                }),
+            )
             .route(
                 "/script",
                 get(|| async {
@@ -35,6 +150,8 @@ pub fn reload_router<S: Clone + Send + Sync + 'static>() -> Router<S> {
                         .into_response()
                 }),
             )
+            // The requests of the toolbar itself aren't worth showing in it
+            .layer(axum::middleware::from_fn(dev_toolbar::skip_recording))
     } else {
         Router::new()
     }
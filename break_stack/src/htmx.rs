@@ -0,0 +1,330 @@
+use crate::errors::*;
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{
+        header::{HeaderMap, HeaderValue},
+        request::Parts,
+    },
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+
+pub const HX_REQUEST: &str = "HX-Request";
+pub const HX_BOOSTED: &str = "HX-Boosted";
+pub const HX_CURRENT_URL: &str = "HX-Current-URL";
+pub const HX_HISTORY_RESTORE_REQUEST: &str = "HX-History-Restore-Request";
+pub const HX_PROMPT: &str = "HX-Prompt";
+pub const HX_TARGET: &str = "HX-Target";
+pub const HX_TRIGGER_NAME: &str = "HX-Trigger-Name";
+
+pub const HX_TRIGGER: &str = "HX-Trigger";
+pub const HX_TRIGGER_AFTER_SETTLE: &str = "HX-Trigger-After-Settle";
+pub const HX_TRIGGER_AFTER_SWAP: &str = "HX-Trigger-After-Swap";
+pub const HX_REDIRECT: &str = "HX-Redirect";
+pub const HX_PUSH_URL: &str = "HX-Push-Url";
+pub const HX_RETARGET: &str = "HX-Retarget";
+pub const HX_RESWAP: &str = "HX-Reswap";
+pub const HX_REFRESH: &str = "HX-Refresh";
+
+/// Extractor for the request headers htmx sets on the requests it sends.
+/// Extracting this never fails, for requests not sent by htmx all flags are
+/// `false` and all values are `None`.
+#[derive(Debug, Clone, Default)]
+pub struct HtmxRequest {
+    pub is_htmx: bool,
+    pub is_boosted: bool,
+    pub is_history_restore: bool,
+    pub current_url: Option<String>,
+    pub prompt: Option<String>,
+    pub target: Option<String>,
+    /// The id of the element that triggered the request (the `HX-Trigger` request header).
+    pub trigger: Option<String>,
+    pub trigger_name: Option<String>,
+}
+
+impl HtmxRequest {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let flag = |name: &str| {
+            headers
+                .get(name)
+                .map(|value| value.as_bytes() == b"true")
+                .unwrap_or(false)
+        };
+        let value = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string())
+        };
+        Self {
+            is_htmx: flag(HX_REQUEST),
+            is_boosted: flag(HX_BOOSTED),
+            is_history_restore: flag(HX_HISTORY_RESTORE_REQUEST),
+            current_url: value(HX_CURRENT_URL),
+            prompt: value(HX_PROMPT),
+            target: value(HX_TARGET),
+            trigger: value(HX_TRIGGER),
+            trigger_name: value(HX_TRIGGER_NAME),
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for HtmxRequest {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self::from_headers(&parts.headers))
+    }
+}
+
+/// List of events for one of the `HX-Trigger*` response headers. If none of the
+/// events have a detail payload the header is written as a comma separated list
+/// of event names, otherwise it's written as a JSON object.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct HtmxTriggers(pub(crate) Vec<(String, Option<serde_json::Value>)>);
+
+impl HtmxTriggers {
+    fn push(&mut self, event: String, detail: Option<serde_json::Value>) {
+        self.0.push((event, detail));
+    }
+
+    pub(crate) fn from_header(value: &HeaderValue) -> AppResult<Self> {
+        let value = value.to_str().map_err(|e| {
+            AppError::Internal(format!(
+                "failed to read existing htmx trigger header: {}",
+                e
+            ))
+        })?;
+        if value.trim_start().starts_with('{') {
+            let events: serde_json::Map<String, serde_json::Value> = serde_json::from_str(value)
+                .map_err(|e| {
+                    AppError::Internal(format!(
+                        "failed to parse existing htmx trigger header: {}",
+                        e
+                    ))
+                })?;
+            Ok(Self(
+                events
+                    .into_iter()
+                    .map(|(event, detail)| (event, Some(detail)))
+                    .collect(),
+            ))
+        } else {
+            Ok(Self(
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|event| !event.is_empty())
+                    .map(|event| (event.to_string(), None))
+                    .collect(),
+            ))
+        }
+    }
+
+    fn to_header(&self) -> AppResult<HeaderValue> {
+        let value = if self.0.iter().all(|(_, detail)| detail.is_none()) {
+            self.0
+                .iter()
+                .map(|(event, _)| event.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        } else {
+            let events: serde_json::Map<String, serde_json::Value> = self
+                .0
+                .iter()
+                .map(|(event, detail)| {
+                    (
+                        event.clone(),
+                        detail.clone().unwrap_or(serde_json::Value::Null),
+                    )
+                })
+                .collect();
+            serde_json::Value::Object(events).to_string()
+        };
+        value
+            .parse::<HeaderValue>()
+            .map_err(|e| AppError::Internal(format!("failed to build htmx trigger header: {}", e)))
+    }
+
+    /// Writes the events to `name`, keeping any events already set on the response.
+    fn apply(self, headers: &mut HeaderMap, name: &'static str) -> AppResult<()> {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+        let mut triggers = match headers.get(name) {
+            Some(existing) => Self::from_header(existing)?,
+            None => Self::default(),
+        };
+        triggers.0.extend(self.0);
+        headers.insert(name, triggers.to_header()?);
+        Ok(())
+    }
+}
+
+/// Wrapper around a response that sets htmx response headers.
+///
+/// ```ignore
+/// HtmxResponse::new(TodoItemViewComponent { item })
+///     .trigger(TodoItemModel::event_updated())
+///     .trigger_with_detail("showMessage", json!({"level": "info", "message": "Saved"}))
+///     .into_response()
+/// ```
+///
+/// Events added with the `trigger*` methods are merged with any events already set
+/// on the wrapped response, so wrapping a response returned by
+/// `ModelController::build_response` won't drop the events it added.
+pub struct HtmxResponse {
+    response: Response,
+    trigger: HtmxTriggers,
+    trigger_after_settle: HtmxTriggers,
+    trigger_after_swap: HtmxTriggers,
+    redirect: Option<String>,
+    push_url: Option<String>,
+    retarget: Option<String>,
+    reswap: Option<String>,
+    refresh: bool,
+    error: Option<AppError>,
+}
+
+impl HtmxResponse {
+    pub fn new(response: impl IntoResponse) -> Self {
+        Self {
+            response: response.into_response(),
+            trigger: HtmxTriggers::default(),
+            trigger_after_settle: HtmxTriggers::default(),
+            trigger_after_swap: HtmxTriggers::default(),
+            redirect: None,
+            push_url: None,
+            retarget: None,
+            reswap: None,
+            refresh: false,
+            error: None,
+        }
+    }
+
+    fn detail<T: Serialize>(&mut self, detail: T) -> Option<serde_json::Value> {
+        match serde_json::to_value(detail) {
+            Ok(detail) => Some(detail),
+            Err(e) => {
+                self.error.get_or_insert_with(|| {
+                    AppError::Internal(format!("failed to serialize htmx event detail: {}", e))
+                });
+                None
+            }
+        }
+    }
+
+    pub fn trigger(mut self, event: impl Into<String>) -> Self {
+        self.trigger.push(event.into(), None);
+        self
+    }
+
+    pub fn trigger_with_detail<T: Serialize>(
+        mut self,
+        event: impl Into<String>,
+        detail: T,
+    ) -> Self {
+        let detail = self.detail(detail);
+        self.trigger.push(event.into(), detail);
+        self
+    }
+
+    pub fn trigger_after_settle(mut self, event: impl Into<String>) -> Self {
+        self.trigger_after_settle.push(event.into(), None);
+        self
+    }
+
+    pub fn trigger_after_settle_with_detail<T: Serialize>(
+        mut self,
+        event: impl Into<String>,
+        detail: T,
+    ) -> Self {
+        let detail = self.detail(detail);
+        self.trigger_after_settle.push(event.into(), detail);
+        self
+    }
+
+    pub fn trigger_after_swap(mut self, event: impl Into<String>) -> Self {
+        self.trigger_after_swap.push(event.into(), None);
+        self
+    }
+
+    pub fn trigger_after_swap_with_detail<T: Serialize>(
+        mut self,
+        event: impl Into<String>,
+        detail: T,
+    ) -> Self {
+        let detail = self.detail(detail);
+        self.trigger_after_swap.push(event.into(), detail);
+        self
+    }
+
+    pub fn redirect(mut self, url: impl Into<String>) -> Self {
+        self.redirect = Some(url.into());
+        self
+    }
+
+    pub fn push_url(mut self, url: impl Into<String>) -> Self {
+        self.push_url = Some(url.into());
+        self
+    }
+
+    pub fn retarget(mut self, selector: impl Into<String>) -> Self {
+        self.retarget = Some(selector.into());
+        self
+    }
+
+    pub fn reswap(mut self, swap: impl Into<String>) -> Self {
+        self.reswap = Some(swap.into());
+        self
+    }
+
+    pub fn refresh(mut self) -> Self {
+        self.refresh = true;
+        self
+    }
+
+    /// Builds the response, failing if any of the header values are invalid.
+    pub fn try_into_response(self) -> AppResult<Response> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        let mut response = self.response;
+        let headers = response.headers_mut();
+
+        self.trigger.apply(headers, HX_TRIGGER)?;
+        self.trigger_after_settle
+            .apply(headers, HX_TRIGGER_AFTER_SETTLE)?;
+        self.trigger_after_swap
+            .apply(headers, HX_TRIGGER_AFTER_SWAP)?;
+
+        for (name, value) in [
+            (HX_REDIRECT, self.redirect),
+            (HX_PUSH_URL, self.push_url),
+            (HX_RETARGET, self.retarget),
+            (HX_RESWAP, self.reswap),
+            (HX_REFRESH, self.refresh.then(|| "true".to_string())),
+        ] {
+            let Some(value) = value else {
+                continue;
+            };
+            let value = value.parse::<HeaderValue>().map_err(|e| {
+                AppError::Internal(format!("failed to build {} header: {}", name, e))
+            })?;
+            headers.insert(name, value);
+        }
+
+        Ok(response)
+    }
+}
+
+impl IntoResponse for HtmxResponse {
+    fn into_response(self) -> Response {
+        match self.try_into_response() {
+            Ok(response) => response,
+            Err(err) => err.into_response(),
+        }
+    }
+}
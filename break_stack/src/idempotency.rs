@@ -0,0 +1,215 @@
+use crate::errors::*;
+use crate::models::DBConn;
+use crate::utils::unix_timestamp;
+use axum::{
+    async_trait,
+    body::{Body, Bytes},
+    extract::{FromRequest, Request},
+    http::{HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Form,
+};
+use serde::de::DeserializeOwned;
+use std::time::Duration;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+pub const IDEMPOTENCY_KEY_FIELD: &str = "idempotency_key";
+
+/// How long a key can stay claimed without a stored response. After that the first request is
+/// considered abandoned (e.g. the app was restarted while handling it), and the key can be
+/// claimed again.
+pub const IN_PROGRESS_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Largest response that can be stored, handling a request with a larger response fails.
+const MAX_STORED_BODY_SIZE: usize = 1024 * 1024;
+
+/// Hidden form field with a new random idempotency key, to include in forms posting to
+/// controllers with idempotency enabled:
+///
+/// ```ignore
+/// <form hx-post="...">
+///     {{ break_stack::idempotency::idempotency_key_input()|safe }}
+///     ...
+/// </form>
+/// ```
+pub fn idempotency_key_input() -> String {
+    format!(
+        r#"<input type="hidden" name="{}" value="{}" />"#,
+        IDEMPOTENCY_KEY_FIELD,
+        uuid::Uuid::new_v4()
+    )
+}
+
+/// Extractor for a form that might include an idempotency key, either as the
+/// `Idempotency-Key` header, or as the `idempotency_key` form field.
+pub struct IdempotentForm<T> {
+    pub key: Option<String>,
+    pub data: T,
+}
+
+impl<T> From<Form<T>> for IdempotentForm<T> {
+    fn from(Form(data): Form<T>) -> Self {
+        Self { key: None, data }
+    }
+}
+
+#[async_trait]
+impl<T: DeserializeOwned, S: Send + Sync> FromRequest<S> for IdempotentForm<T> {
+    type Rejection = Response;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let (parts, body) = req.into_parts();
+        let bytes = Bytes::from_request(Request::from_parts(parts.clone(), body), state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        let key = parts
+            .headers
+            .get(IDEMPOTENCY_KEY_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string())
+            .or_else(|| {
+                serde_urlencoded::from_bytes::<Vec<(String, String)>>(&bytes)
+                    .ok()?
+                    .into_iter()
+                    .find(|(name, _)| name == IDEMPOTENCY_KEY_FIELD)
+                    .map(|(_, value)| value)
+            })
+            .filter(|key| !key.is_empty());
+
+        let Form(data) =
+            Form::<T>::from_request(Request::from_parts(parts, Body::from(bytes)), state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+        Ok(Self { key, data })
+    }
+}
+
+/// Status, headers (as a json list of name/value pairs) and body, the status is `NULL` while
+/// the first request is being handled.
+type StoredResponse = (Option<u16>, Option<String>, Option<Vec<u8>>);
+
+/// Claims `key` within `scope` before handling a request. Returns the stored response if the
+/// key was already used within `window`, or a `409 Conflict` response if the first request
+/// with the key is still being handled (for at most `IN_PROGRESS_TIMEOUT`). If this returns `None` the request should be handled,
+/// and the result passed to `finish`.
+pub async fn begin(
+    conn: &mut DBConn,
+    scope: &str,
+    key: &str,
+    window: Duration,
+) -> Result<Option<Response>, ModelError> {
+    let now = unix_timestamp();
+    sqlx::query(
+        "DELETE FROM break_stack_idempotency_keys WHERE scope = ? AND key = ?
+        AND (created_at <= ? OR (status IS NULL AND created_at <= ?))",
+    )
+    .bind(scope)
+    .bind(key)
+    .bind(now - window.as_secs() as i64)
+    .bind(now - IN_PROGRESS_TIMEOUT.as_secs() as i64)
+    .execute(&mut **conn)
+    .await?;
+
+    let claimed = sqlx::query(
+        "INSERT OR IGNORE INTO break_stack_idempotency_keys (scope, key, created_at) VALUES (?, ?, ?)",
+    )
+    .bind(scope)
+    .bind(key)
+    .bind(now)
+    .execute(&mut **conn)
+    .await?;
+    if claimed.rows_affected() == 1 {
+        return Ok(None);
+    }
+
+    let stored: Option<StoredResponse> = sqlx::query_as(
+        "SELECT status, headers, body FROM break_stack_idempotency_keys WHERE scope = ? AND key = ?",
+    )
+    .bind(scope)
+    .bind(key)
+    .fetch_optional(&mut **conn)
+    .await?;
+    let Some((Some(status), headers, body)) = stored else {
+        return Ok(Some(
+            (
+                StatusCode::CONFLICT,
+                "This request is already being handled",
+            )
+                .into_response(),
+        ));
+    };
+
+    let headers: Vec<(String, String)> = headers
+        .map(|headers| serde_json::from_str(&headers))
+        .transpose()
+        .map_err(|e| ModelError::Internal(format!("failed to read stored headers: {}", e)))?
+        .unwrap_or_default();
+    let mut response = Response::new(Body::from(body.unwrap_or_default()));
+    *response.status_mut() = StatusCode::from_u16(status)
+        .map_err(|e| ModelError::Internal(format!("invalid stored status: {}", e)))?;
+    for (name, value) in headers {
+        if let (Ok(name), Ok(value)) = (name.parse::<HeaderName>(), value.parse::<HeaderValue>()) {
+            response.headers_mut().append(name, value);
+        }
+    }
+    Ok(Some(response))
+}
+
+/// Stores the response of a request claimed with `begin`, so it's replayed for requests with
+/// the same key. If the request failed the key is released, so it can be retried.
+pub async fn finish(
+    conn: &mut DBConn,
+    scope: &str,
+    key: &str,
+    result: AppResult<Response>,
+) -> AppResult<Response> {
+    let response = match result {
+        Ok(response) => response,
+        Err(err) => {
+            release(conn, scope, key).await?;
+            return Err(err);
+        }
+    };
+
+    let (parts, body) = response.into_parts();
+    let body = match axum::body::to_bytes(body, MAX_STORED_BODY_SIZE).await {
+        Ok(body) => body,
+        Err(e) => {
+            release(conn, scope, key).await?;
+            return Err(AppError::Internal(format!(
+                "failed to read response body: {}",
+                e
+            )));
+        }
+    };
+    let headers: Vec<(&str, &str)> = parts
+        .headers
+        .iter()
+        .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?)))
+        .collect();
+    let headers = serde_json::to_string(&headers)
+        .map_err(|e| AppError::Internal(format!("failed to serialize headers: {}", e)))?;
+
+    sqlx::query(
+        "UPDATE break_stack_idempotency_keys SET status = ?, headers = ?, body = ? WHERE scope = ? AND key = ?",
+    )
+    .bind(parts.status.as_u16())
+    .bind(headers)
+    .bind(body.as_ref())
+    .bind(scope)
+    .bind(key)
+    .execute(&mut **conn)
+    .await?;
+
+    Ok(Response::from_parts(parts, Body::from(body)))
+}
+
+async fn release(conn: &mut DBConn, scope: &str, key: &str) -> Result<(), ModelError> {
+    sqlx::query("DELETE FROM break_stack_idempotency_keys WHERE scope = ? AND key = ?")
+        .bind(scope)
+        .bind(key)
+        .execute(&mut **conn)
+        .await?;
+    Ok(())
+}
@@ -0,0 +1,271 @@
+use crate::errors::*;
+use crate::models::{DBConn, DBPool};
+use crate::utils::unix_timestamp;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+
+/// Work that is run outside of the request by a `Worker`. Jobs are stored in the
+/// `break_stack_jobs` table (see `migrations::run`) as json, and are retried with a
+/// backoff if they fail.
+pub trait Job: Serialize + DeserializeOwned + Send + Sync + Sized + 'static {
+    /// Name used to find the handler for a stored job, needs to be unique among the
+    /// registered jobs and shouldn't change while there are queued jobs.
+    const NAME: &'static str;
+    /// Number of times the job is run before it's marked as failed.
+    const MAX_ATTEMPTS: u32 = 5;
+
+    fn run(self, conn: &mut DBConn) -> impl Future<Output = AppResult<()>> + Send;
+
+    /// Delay before retrying after `attempts` failed attempts.
+    fn backoff(attempts: u32) -> Duration {
+        Duration::from_secs(2u64.saturating_pow(attempts).min(60 * 60))
+    }
+}
+
+pub async fn enqueue<J: Job>(conn: &mut DBConn, job: &J) -> Result<i64, ModelError> {
+    enqueue_in(conn, job, Duration::ZERO).await
+}
+
+/// Queues a job that won't run before `delay` has passed.
+pub async fn enqueue_in<J: Job>(
+    conn: &mut DBConn,
+    job: &J,
+    delay: Duration,
+) -> Result<i64, ModelError> {
+    let payload = serde_json::to_string(job)
+        .map_err(|e| ModelError::Internal(format!("failed to serialize job: {}", e)))?;
+    let now = unix_timestamp();
+    let id = sqlx::query_scalar(
+        "INSERT INTO break_stack_jobs (name, payload, max_attempts, run_at, created_at) VALUES (?, ?, ?, ?, ?) RETURNING id",
+    )
+    .bind(J::NAME)
+    .bind(payload)
+    .bind(J::MAX_ATTEMPTS)
+    .bind(now + delay.as_secs() as i64)
+    .bind(now)
+    .fetch_one(&mut **conn)
+    .await?;
+    Ok(id)
+}
+
+type JobFuture<'a> = Pin<Box<dyn Future<Output = AppResult<()>> + Send + 'a>>;
+
+struct JobHandler {
+    run: for<'a> fn(&'a mut DBConn, &'a str) -> JobFuture<'a>,
+    backoff: fn(u32) -> Duration,
+}
+
+fn run_job<'a, J: Job>(conn: &'a mut DBConn, payload: &'a str) -> JobFuture<'a> {
+    Box::pin(async move {
+        let job: J = serde_json::from_str(payload)
+            .map_err(|e| AppError::Internal(format!("failed to deserialize job: {}", e)))?;
+        job.run(conn).await
+    })
+}
+
+/// Runs queued jobs. Every job type needs to be registered with `Worker::register`.
+///
+/// ```ignore
+/// jobs::Worker::new(db_pool.clone())
+///     .register::<SendEmailJob>()
+///     .spawn();
+/// axum::serve(listener, app).await.unwrap();
+/// ```
+pub struct Worker {
+    pool: DBPool,
+    handlers: HashMap<&'static str, JobHandler>,
+    poll_interval: Duration,
+    lock_timeout: Duration,
+    on_error: Box<dyn Fn(&AppError) + Send + Sync>,
+}
+
+impl Worker {
+    pub fn new(pool: DBPool) -> Self {
+        Self {
+            pool,
+            handlers: HashMap::new(),
+            poll_interval: Duration::from_secs(1),
+            lock_timeout: Duration::from_secs(10 * 60),
+            on_error: Box::new(|err| tracing::error!("failed to run job: {:?}", err)),
+        }
+    }
+
+    pub fn register<J: Job>(mut self) -> Self {
+        self.handlers.insert(
+            J::NAME,
+            JobHandler {
+                run: run_job::<J>,
+                backoff: J::backoff,
+            },
+        );
+        self
+    }
+
+    /// How long to wait before checking for new jobs when the queue is empty.
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// How long a job can run before it's considered abandoned (e.g. because the app
+    /// was restarted while it was running), and is picked up again.
+    pub fn lock_timeout(mut self, lock_timeout: Duration) -> Self {
+        self.lock_timeout = lock_timeout;
+        self
+    }
+
+    /// Called by `run` when the worker fails to run jobs, e.g. because the database is
+    /// unavailable, logs the error with `tracing` by default. Errors returned by jobs are
+    /// stored on the job instead.
+    pub fn on_error(mut self, on_error: impl Fn(&AppError) + Send + Sync + 'static) -> Self {
+        self.on_error = Box::new(on_error);
+        self
+    }
+
+    /// Runs the next job that is due, returns `false` if there were no jobs to run.
+    /// Errors returned by the job itself are stored on the job, and don't make this fail.
+    pub async fn run_next(&self) -> AppResult<bool> {
+        let mut conn: DBConn = self.pool.acquire().await?.into();
+        let now = unix_timestamp();
+
+        // The job is selected and locked by a single statement, which only updates the job if
+        // it's still unlocked, so concurrent workers can't claim the same job
+        let job: Option<(i64, String, String, u32, u32)> = sqlx::query_as(
+            "UPDATE break_stack_jobs SET locked_at = ?1, attempts = attempts + 1
+            WHERE id = (
+                SELECT id FROM break_stack_jobs
+                WHERE failed_at IS NULL AND run_at <= ?1 AND (locked_at IS NULL OR locked_at <= ?2)
+                ORDER BY run_at, id LIMIT 1
+            ) AND failed_at IS NULL AND (locked_at IS NULL OR locked_at <= ?2)
+            RETURNING id, name, payload, attempts, max_attempts",
+        )
+        .bind(now)
+        .bind(now - self.lock_timeout.as_secs() as i64)
+        .fetch_optional(&mut *conn)
+        .await?;
+        let Some((id, name, payload, attempts, max_attempts)) = job else {
+            return Ok(false);
+        };
+
+        let handler = self.handlers.get(name.as_str());
+        let result = match handler {
+            Some(handler) => (handler.run)(&mut conn, &payload).await,
+            None => Err(AppError::Internal(format!(
+                "no handler registered for job '{}'",
+                name
+            ))),
+        };
+
+        let now = unix_timestamp();
+        match result {
+            Ok(()) => {
+                sqlx::query("DELETE FROM break_stack_jobs WHERE id = ?")
+                    .bind(id)
+                    .execute(&mut *conn)
+                    .await?;
+            }
+            Err(err) if attempts >= max_attempts => {
+                sqlx::query(
+                    "UPDATE break_stack_jobs SET locked_at = NULL, failed_at = ?, last_error = ? WHERE id = ?",
+                )
+                .bind(now)
+                .bind(format!("{:?}", err))
+                .bind(id)
+                .execute(&mut *conn)
+                .await?;
+            }
+            Err(err) => {
+                let backoff = handler
+                    .map(|handler| (handler.backoff)(attempts))
+                    .unwrap_or(Duration::from_secs(60));
+                sqlx::query(
+                    "UPDATE break_stack_jobs SET locked_at = NULL, run_at = ?, last_error = ? WHERE id = ?",
+                )
+                .bind(now + backoff.as_secs() as i64)
+                .bind(format!("{:?}", err))
+                .bind(id)
+                .execute(&mut *conn)
+                .await?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Runs jobs until there are no more jobs that are due.
+    pub async fn run_pending(&self) -> AppResult<usize> {
+        let mut count = 0;
+        while self.run_next().await? {
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Runs jobs forever, waiting `poll_interval` between each check when there are no jobs.
+    pub async fn run(self) {
+        loop {
+            match self.run_next().await {
+                Ok(true) => continue,
+                Ok(false) => {}
+                Err(err) => (self.on_error)(&err),
+            }
+            tokio::time::sleep(self.poll_interval).await;
+        }
+    }
+
+    pub fn spawn(self) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(self.run())
+    }
+}
+
+pub mod testutils {
+    use super::*;
+
+    /// Runs all queued jobs right away, including retries, until every job has either
+    /// succeeded or failed permanently. Returns the number of times a job was run.
+    pub async fn run_all_jobs(worker: &Worker) -> AppResult<usize> {
+        let mut count = 0;
+        loop {
+            let mut conn = worker.pool.acquire().await?;
+            sqlx::query("UPDATE break_stack_jobs SET run_at = 0 WHERE failed_at IS NULL")
+                .execute(&mut *conn)
+                .await?;
+            drop(conn);
+
+            if !worker.run_next().await? {
+                return Ok(count);
+            }
+            count += 1;
+        }
+    }
+
+    /// Jobs of type `J` that are waiting to be run.
+    pub async fn queued_jobs<J: Job>(conn: &mut DBConn) -> Result<Vec<J>, ModelError> {
+        jobs_where::<J>(conn, "failed_at IS NULL").await
+    }
+
+    /// Jobs of type `J` that have used all their attempts.
+    pub async fn failed_jobs<J: Job>(conn: &mut DBConn) -> Result<Vec<J>, ModelError> {
+        jobs_where::<J>(conn, "failed_at IS NOT NULL").await
+    }
+
+    async fn jobs_where<J: Job>(conn: &mut DBConn, filter: &str) -> Result<Vec<J>, ModelError> {
+        let payloads: Vec<String> = sqlx::query_scalar(&format!(
+            "SELECT payload FROM break_stack_jobs WHERE name = ? AND {} ORDER BY id",
+            filter
+        ))
+        .bind(J::NAME)
+        .fetch_all(&mut **conn)
+        .await?;
+        payloads
+            .iter()
+            .map(|payload| {
+                serde_json::from_str(payload)
+                    .map_err(|e| ModelError::Internal(format!("failed to deserialize job: {}", e)))
+            })
+            .collect()
+    }
+}
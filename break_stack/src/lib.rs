@@ -1,8 +1,27 @@
+// Lets the derive macros, which use `::break_stack` paths, be used inside this crate
+extern crate self as break_stack;
+
 pub mod auth;
 pub mod components;
 pub mod controllers;
+pub mod dev_toolbar;
 pub mod errors;
+pub mod etag;
+pub mod flash;
 pub mod hot_reload;
+pub mod htmx;
+pub mod idempotency;
+pub mod jobs;
+pub mod migrations;
 pub mod models;
-pub mod utils;
+pub mod queries;
 pub mod routes;
+pub mod scheduler;
+pub mod session;
+pub mod storage;
+pub mod template_reload;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod undo;
+pub mod utils;
+pub mod wizard;
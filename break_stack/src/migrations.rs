@@ -0,0 +1,66 @@
+use crate::errors::ModelError;
+use crate::models::DBConn;
+use sqlx::Connection;
+
+/// Migrations for the tables used by break_stack itself. These are tracked in their own
+/// table, so they can be run next to the migrations of the app using `sqlx::migrate!`.
+const MIGRATIONS: &[(i64, &str, &str)] = &[
+    (1, "jobs", include_str!("../migrations/0001_jobs.sql")),
+    (
+        2,
+        "scheduler",
+        include_str!("../migrations/0002_scheduler.sql"),
+    ),
+    (
+        3,
+        "idempotency",
+        include_str!("../migrations/0003_idempotency.sql"),
+    ),
+    (
+        4,
+        "attachments",
+        include_str!("../migrations/0004_attachments.sql"),
+    ),
+    (
+        5,
+        "pending_deletes",
+        include_str!("../migrations/0005_pending_deletes.sql"),
+    ),
+    (
+        6,
+        "wizard_drafts",
+        include_str!("../migrations/0006_wizard_drafts.sql"),
+    ),
+];
+
+/// Creates/updates the tables needed by the break_stack subsystems (like `jobs` or `storage`).
+/// Should be called on startup, and in tests using those subsystems.
+pub async fn run(conn: &mut DBConn) -> Result<(), ModelError> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS _break_stack_migrations (version INTEGER PRIMARY KEY NOT NULL, name TEXT NOT NULL)",
+    )
+    .execute(&mut **conn)
+    .await?;
+
+    for (version, name, sql) in MIGRATIONS {
+        let applied: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM _break_stack_migrations WHERE version = ?")
+                .bind(version)
+                .fetch_one(&mut **conn)
+                .await?;
+        if applied > 0 {
+            continue;
+        }
+
+        let mut tx = conn.begin().await?;
+        sqlx::raw_sql(sql).execute(&mut *tx).await?;
+        sqlx::query("INSERT INTO _break_stack_migrations (version, name) VALUES (?, ?)")
+            .bind(version)
+            .bind(name)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+    }
+
+    Ok(())
+}
@@ -1,11 +1,71 @@
 use crate::auth::UserId;
 use crate::errors::{AuthError, ModelError};
-pub use break_stack_macros::{Model, ModelCreate, ModelRead, ModelWrite, ModelDelete, WithOwnerModel};
+use crate::queries::QueryLogGuard;
+pub use break_stack_macros::{
+    Model, ModelCreate, ModelDelete, ModelFactory, ModelRead, ModelWrite, WithOwnerModel,
+};
+use sqlx::pool::PoolConnection;
+use sqlx::{Sqlite, SqliteConnection};
+use std::ops::{Deref, DerefMut};
+
+pub type DBPool = sqlx::Pool<sqlx::Sqlite>;
+
+/// A connection from the pool, which derefs to the `SqliteConnection`, so it's used as an
+/// executor with `&mut *conn`. Created from the connections of the pool with `into()`.
+pub struct DBConn {
+    // Dropped before the connection goes back to the pool, see `queries::attach`
+    query_log: Option<QueryLogGuard>,
+    conn: PoolConnection<Sqlite>,
+}
+
+impl DBConn {
+    pub(crate) fn set_query_log(&mut self, guard: QueryLogGuard) {
+        self.query_log = Some(guard);
+    }
+}
+
+impl From<PoolConnection<Sqlite>> for DBConn {
+    fn from(conn: PoolConnection<Sqlite>) -> Self {
+        Self {
+            query_log: None,
+            conn,
+        }
+    }
+}
+
+impl Deref for DBConn {
+    type Target = SqliteConnection;
+
+    fn deref(&self) -> &SqliteConnection {
+        &self.conn
+    }
+}
+
+impl DerefMut for DBConn {
+    fn deref_mut(&mut self) -> &mut SqliteConnection {
+        &mut self.conn
+    }
+}
 
-pub type DBConn = sqlx::pool::PoolConnection<sqlx::Sqlite>;
+/// Connection the model traits and the "model-based" controllers are given, see `Model::Conn`.
+pub trait Connection: Send + 'static {
+    /// The sqlite connection, for the subsystems that store their state in the database (like
+    /// idempotency keys or attachments). Fails for connections without a database.
+    fn db(&mut self) -> Result<&mut DBConn, ModelError>;
+}
+
+impl Connection for DBConn {
+    fn db(&mut self) -> Result<&mut DBConn, ModelError> {
+        Ok(self)
+    }
+}
 
 pub trait Model {
     type ID: Copy + Send + Sync;
+    /// Connection the model is read and written with. This is `DBConn` for models stored in
+    /// sqlite, models used in tests can use `testutils::MockConn` instead, so the controllers
+    /// using them can be tested without a database.
+    type Conn: Connection;
 
     const MODEL_NAME: &'static str;
     fn event_created() -> String {
@@ -17,15 +77,28 @@ pub trait Model {
     fn event_deleted() -> String {
         format!("{}Deleted", Self::MODEL_NAME)
     }
+    fn event_restored() -> String {
+        format!("{}Restored", Self::MODEL_NAME)
+    }
+    /// Cheap version of the object, like an `updated_at` or `version` column, that changes
+    /// whenever the object changes. If this is set `model_controller_read` uses it for the
+    /// `ETag`, so it can respond with `304 Not Modified` without rendering the response.
+    ///
+    /// The version must cover everything the controllers render for the object: if a
+    /// `build_response` also renders e.g. related objects, their changes need to bump the
+    /// version too, or this should stay `None` so the tag is a hash of the rendered body.
+    fn version(&self) -> Option<String> {
+        None
+    }
 }
 
 pub trait WithOwnerModel: Sized + Model {
     fn owner(
-        conn: &mut DBConn,
+        conn: &mut <Self as Model>::Conn,
         id: <Self as Model>::ID,
     ) -> impl std::future::Future<Output = Result<Option<i64>, ModelError>> + Send;
     fn all_for_owner(
-        conn: &mut DBConn,
+        conn: &mut <Self as Model>::Conn,
         user_id: i64,
     ) -> impl std::future::Future<Output = Result<Vec<Self>, ModelError>> + Send;
 }
@@ -35,7 +108,7 @@ pub trait WithRelatedModel: Sized + Model {
     type Related: Sized;
     fn fetch_related(
         &self,
-        conn: &mut DBConn,
+        conn: &mut <Self as Model>::Conn,
     ) -> impl std::future::Future<Output = Result<Self::Related, ModelError>> + Send;
 }
 */
@@ -45,11 +118,11 @@ pub trait WithRelatedModel: Sized + Model {
 /// implemented outside of this trait.
 pub trait ModelRead: Sized + Model {
     fn read(
-        conn: &mut DBConn,
+        conn: &mut <Self as Model>::Conn,
         id: <Self as Model>::ID,
     ) -> impl std::future::Future<Output = Result<Option<Self>, ModelError>> + Send;
     fn read_one(
-        conn: &mut DBConn,
+        conn: &mut <Self as Model>::Conn,
         id: <Self as Model>::ID,
     ) -> impl std::future::Future<Output = Result<Self, ModelError>> + Send {
         async move {
@@ -67,12 +140,12 @@ pub trait ModelRead: Sized + Model {
 pub trait ModelWrite: Sized + Model {
     type Write: Sized + Send + Sync;
     fn write(
-        conn: &mut DBConn,
+        conn: &mut <Self as Model>::Conn,
         id: <Self as Model>::ID,
         data: Self::Write,
     ) -> impl std::future::Future<Output = Result<Option<Self>, ModelError>> + Send;
     fn write_one(
-        conn: &mut DBConn,
+        conn: &mut <Self as Model>::Conn,
         id: <Self as Model>::ID,
         data: Self::Write,
     ) -> impl std::future::Future<Output = Result<Self, ModelError>> + Send {
@@ -90,22 +163,49 @@ pub trait ModelWrite: Sized + Model {
 pub trait ModelCreate: Sized + Model {
     type Create: Sized + Send + Sync;
     fn create(
-        conn: &mut DBConn,
+        conn: &mut <Self as Model>::Conn,
         data: Self::Create,
     ) -> impl std::future::Future<Output = Result<Self, ModelError>> + Send;
 }
 
+/// Default data for creating objects of a model in tests, implemented for the
+/// `ModelCreate::Create` type, see `testing::Factory` and `testing::Fixtures`.
+pub trait ModelFactory: Sized + Send + Sync + 'static {
+    type Model: ModelCreate<Create = Self>;
+    /// Data of the `n`th object created by a factory. `n` starts at 1 and is counted per type
+    /// for the whole test binary, so it can be used for values that have to be unique.
+    fn build(n: u64) -> Self;
+    fn id(model: &Self::Model) -> <Self::Model as Model>::ID;
+}
+
 pub trait ModelDelete: Sized + Model {
     fn delete(
-        conn: &mut DBConn,
+        conn: &mut <Self as Model>::Conn,
         id: <Self as Model>::ID,
     ) -> impl std::future::Future<Output = Result<Self, ModelError>> + Send;
 }
 
+/// Trait for models that can be deleted in two steps: hidden first, so the delete can still be
+/// undone, and permanently deleted with `ModelDelete::delete` later (see `undo`). Hidden objects
+/// should be skipped by `ModelRead::read` and by lists, but should still be found by the
+/// `AuthModelDelete` checks, since those are also used to check who can restore an object.
+pub trait ModelSoftDelete: ModelDelete {
+    fn hide(
+        conn: &mut <Self as Model>::Conn,
+        id: <Self as Model>::ID,
+    ) -> impl std::future::Future<Output = Result<Self, ModelError>> + Send;
+    /// Makes a hidden object visible again, returns `None` if there's no hidden object with
+    /// the given id.
+    fn restore(
+        conn: &mut <Self as Model>::Conn,
+        id: <Self as Model>::ID,
+    ) -> impl std::future::Future<Output = Result<Option<Self>, ModelError>> + Send;
+}
+
 /// Trait for authentication and authorization checks for reading an object.
 pub trait AuthModelRead: ModelRead {
     fn can_read(
-        conn: &mut DBConn,
+        conn: &mut <Self as Model>::Conn,
         id: <Self as Model>::ID,
         user_id: Option<UserId>,
     ) -> impl std::future::Future<Output = Result<(), AuthError>> + Send;
@@ -116,7 +216,7 @@ pub trait AuthModelRead: ModelRead {
 /// this should be inspected to determine if the user has permission to perform the operation.
 pub trait AuthModelWrite: ModelWrite {
     fn can_write(
-        conn: &mut DBConn,
+        conn: &mut <Self as Model>::Conn,
         id: <Self as Model>::ID,
         user_id: Option<UserId>,
         data: &<Self as ModelWrite>::Write,
@@ -128,7 +228,7 @@ pub trait AuthModelWrite: ModelWrite {
 /// this should be inspected to determine if the user has permission to perform the operation.
 pub trait AuthModelCreate: ModelCreate {
     fn can_create(
-        conn: &mut DBConn,
+        conn: &mut <Self as Model>::Conn,
         user_id: Option<UserId>,
         data: &<Self as ModelCreate>::Create,
     ) -> impl std::future::Future<Output = Result<(), AuthError>> + Send;
@@ -136,17 +236,26 @@ pub trait AuthModelCreate: ModelCreate {
 
 pub trait AuthModelDelete: ModelDelete {
     fn can_delete(
-        conn: &mut DBConn,
+        conn: &mut <Self as Model>::Conn,
         id: <Self as Model>::ID,
         user_id: Option<UserId>,
     ) -> impl std::future::Future<Output = Result<(), AuthError>> + Send;
 }
 
+/// Trait for listing the objects a user has access to. Unlike the other model traits this
+/// also covers authorization, since which objects are returned depends on the user.
+pub trait AuthModelList: Sized + Model {
+    fn list(
+        conn: &mut <Self as Model>::Conn,
+        user_id: Option<UserId>,
+    ) -> impl std::future::Future<Output = Result<Vec<Self>, AuthError>> + Send;
+}
+
 pub trait OwnerAuthModelRead: WithOwnerModel + ModelRead {}
 
 impl<ModelImpl: OwnerAuthModelRead> AuthModelRead for ModelImpl {
     async fn can_read(
-        conn: &mut DBConn,
+        conn: &mut <Self as Model>::Conn,
         id: <Self as Model>::ID,
         user_id: Option<UserId>,
     ) -> Result<(), AuthError> {
@@ -169,7 +278,7 @@ pub trait OwnerAuthModelWrite: WithOwnerModel + ModelWrite {}
 
 impl<ModelImpl: OwnerAuthModelWrite> AuthModelWrite for ModelImpl {
     async fn can_write(
-        conn: &mut DBConn,
+        conn: &mut <Self as Model>::Conn,
         id: <Self as Model>::ID,
         user_id: Option<UserId>,
         _data: &<Self as ModelWrite>::Write,
@@ -191,14 +300,14 @@ impl<ModelImpl: OwnerAuthModelWrite> AuthModelWrite for ModelImpl {
 
 pub trait OwnerAuthModelCreate: WithOwnerModel + ModelCreate {
     fn will_be_owner(
-        conn: &mut DBConn,
+        conn: &mut <Self as Model>::Conn,
         data: &<Self as ModelCreate>::Create,
     ) -> impl std::future::Future<Output = Result<i64, ModelError>> + Send;
 }
 
 impl<ModelImpl: OwnerAuthModelCreate> AuthModelCreate for ModelImpl {
     async fn can_create(
-        conn: &mut DBConn,
+        conn: &mut <Self as Model>::Conn,
         user_id: Option<UserId>,
         data: &<Self as ModelCreate>::Create,
     ) -> Result<(), AuthError> {
@@ -219,7 +328,7 @@ pub trait OwnerAuthModelDelete: WithOwnerModel + ModelDelete {}
 
 impl<ModelImpl: OwnerAuthModelDelete> AuthModelDelete for ModelImpl {
     async fn can_delete(
-        conn: &mut DBConn,
+        conn: &mut <Self as Model>::Conn,
         id: <Self as Model>::ID,
         user_id: Option<UserId>,
     ) -> Result<(), AuthError> {
@@ -238,41 +347,231 @@ impl<ModelImpl: OwnerAuthModelDelete> AuthModelDelete for ModelImpl {
     }
 }
 
+pub trait OwnerAuthModelList: WithOwnerModel {}
+
+impl<ModelImpl: OwnerAuthModelList> AuthModelList for ModelImpl {
+    async fn list(
+        conn: &mut <Self as Model>::Conn,
+        user_id: Option<UserId>,
+    ) -> Result<Vec<Self>, AuthError> {
+        let Some(user_id) = user_id else {
+            return Err(AuthError::Unauthenticated);
+        };
+
+        Ok(ModelImpl::all_for_owner(conn, *user_id).await?)
+    }
+}
+
 pub mod testutils {
+    use super::{Connection, DBConn};
+    use crate::errors::ModelError;
+    use std::any::{Any, TypeId};
+    use std::collections::HashMap;
+    use std::panic::AssertUnwindSafe;
+
+    /// In-memory connection for models used in tests, so the controllers using them can be
+    /// tested without a database. Mock models can keep their objects in `rows`:
+    ///
+    /// ```ignore
+    /// impl ModelRead for TestModel {
+    ///     async fn read(conn: &mut MockConn, id: i64) -> Result<Option<Self>, ModelError> {
+    ///         Ok(conn.rows::<Self>().iter().find(|m| m.id == id).cloned())
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// Subsystems that need a database (like idempotency keys) fail with a mock connection,
+    /// unless it wraps a real connection with `MockConn::with_db`.
+    #[derive(Default)]
+    pub struct MockConn {
+        rows: HashMap<TypeId, Box<dyn Any + Send>>,
+        db: Option<DBConn>,
+    }
+
+    impl MockConn {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn with_db(db: DBConn) -> Self {
+            Self {
+                rows: HashMap::new(),
+                db: Some(db),
+            }
+        }
+
+        /// The stored values of type `T`, empty until something is added.
+        pub fn rows<T: Send + 'static>(&mut self) -> &mut Vec<T> {
+            self.rows
+                .entry(TypeId::of::<T>())
+                .or_insert_with(|| Box::new(Vec::<T>::new()))
+                .downcast_mut()
+                .expect("rows are stored by their type id")
+        }
+
+        /// Adds rows, e.g. the objects a test starts with.
+        pub fn with_rows<T: Send + 'static>(mut self, rows: impl IntoIterator<Item = T>) -> Self {
+            self.rows::<T>().extend(rows);
+            self
+        }
+    }
+
+    impl Connection for MockConn {
+        fn db(&mut self) -> Result<&mut DBConn, ModelError> {
+            self.db
+                .as_mut()
+                .ok_or_else(|| ModelError::Internal("MockConn doesn't have a database".to_string()))
+        }
+    }
+
+    /// Results of the cases of a table-driven test, used by the `*_test_cases!` macros below, so
+    /// all the failing cases are reported at once by `finish`, instead of the test stopping at
+    /// the first one. Tests can check their own cases the same way:
+    ///
+    /// ```ignore
+    /// let mut cases = TestCases::new("slugify");
+    /// for (case, title, expected) in [("spaces", "Buy milk", "buy-milk"), ("empty", "", "")] {
+    ///     cases.start(case);
+    ///     let slug = slugify(title);
+    ///     cases.check(case, slug == expected, || format!("Got slug: {:?}", slug));
+    /// }
+    /// cases.finish();
+    /// ```
+    pub struct TestCases {
+        name: &'static str,
+        count: usize,
+        failures: Vec<String>,
+    }
+
+    impl TestCases {
+        pub fn new(name: &'static str) -> Self {
+            Self {
+                name,
+                count: 0,
+                failures: Vec::new(),
+            }
+        }
+
+        pub fn start(&mut self, case: &str) {
+            println!("Running case '{}'", case);
+            self.count += 1;
+        }
+
+        pub fn fail(&mut self, case: &str, message: impl std::fmt::Display) {
+            self.failures.push(format!("Case '{}'\n{}", case, message));
+        }
+
+        pub fn check(&mut self, case: &str, ok: bool, message: impl FnOnce() -> String) {
+            if !ok {
+                self.fail(case, message());
+            }
+        }
+
+        /// Records the output of `function` not matching the pattern `expected`.
+        pub fn check_match(
+            &mut self,
+            case: &str,
+            function: &str,
+            matched: bool,
+            expected: &str,
+            output: &dyn std::fmt::Debug,
+        ) {
+            self.check(case, matched, || {
+                format!(
+                    "Output of {} didn't match expected output:\nExpected output to match: {}\nGot: {:?}",
+                    function, expected, output
+                )
+            });
+        }
+
+        /// Runs checks that panic, like the `assert_*` methods of `testing::TestResponse`, and
+        /// records the panic message as the failure of the case.
+        pub fn check_panics(&mut self, case: &str, f: impl FnOnce()) {
+            let Err(panic) = std::panic::catch_unwind(AssertUnwindSafe(f)) else {
+                return;
+            };
+            let message = match panic.downcast::<String>() {
+                Ok(message) => *message,
+                Err(panic) => panic
+                    .downcast_ref::<&str>()
+                    .map(|message| message.to_string())
+                    .unwrap_or_else(|| "panicked".to_string()),
+            };
+            self.fail(case, message);
+        }
+
+        /// Panics with all the failures, if any case failed.
+        pub fn finish(self) {
+            if !self.failures.is_empty() {
+                panic!(
+                    "{} of {} cases of {} failed\n\n{}",
+                    self.failures.len(),
+                    self.count,
+                    self.name,
+                    self.failures.join("\n\n")
+                );
+            }
+        }
+    }
+
+    // The `*_test_cases!` macros call a method of a model trait for every case, and check that
+    // the output matches the pattern of the case. The model methods returning an `Option` or a
+    // list are checked on the value they return, and panic when they fail, the others (like
+    // `ModelDelete::delete` and the auth checks) are checked on their `Result`. Lists are
+    // matched as slices, e.g. `[TestModel { id: 1, .. }, _]`.
+
     #[macro_export]
     macro_rules! model_read_test_cases {
-        ( $model:ident, $conn:expr, [ $( $case_name:literal : $id:literal => $expect_pat:pat $(if $cond:expr)?, )* ] ) => {
-            $(
-                {
-                    println!("Running case '{}'", $case_name);
-                    let id = $id;
-                    let entry = <$model as break_stack::models::ModelRead>::read($conn, id)
-                        .await
-                        .unwrap();
-                    if !matches!(entry, $expect_pat $(if $cond)*) {
-                        panic!("Case '{}'\nEntry returned by ModelRead::read didn't match expected output:\nExpected output to match: {}\nGot entry: {:?}", $case_name, stringify!($expect_pat $(if $cond)*), entry);
+        ( $model:ident, $conn:expr, [ $( $case_name:literal : $id:expr => $expect_pat:pat $(if $cond:expr)?, )* ] ) => {
+            {
+                let mut cases = $crate::models::testutils::TestCases::new("model_read_test_cases");
+                $(
+                    cases.start($case_name);
+                    match <$model as $crate::models::ModelRead>::read($conn, $id).await {
+                        Ok(output) => {
+                            #[allow(clippy::redundant_pattern_matching)]
+                            let matched = matches!(output, $expect_pat $(if $cond)*);
+                            cases.check_match(
+                                $case_name,
+                                "ModelRead::read",
+                                matched,
+                                stringify!($expect_pat $(if $cond)*),
+                                &output,
+                            );
+                        }
+                        Err(err) => cases.fail($case_name, format!("ModelRead::read failed: {:?}", err)),
                     }
-                }
-            )*
+                )*
+                cases.finish();
+            }
         }
     }
     pub use model_read_test_cases;
 
     #[macro_export]
     macro_rules! model_write_test_cases {
-        ( $model:ident, $conn:expr, [ $( $case_name:literal : ($id:literal, $write_data:expr) => $expect_pat:pat $(if $cond:expr)?, )* ] ) => {
-            $(
-                {
-                    println!("Running case '{}'", $case_name);
-                    let id = $id;
-                    let entry = <$model as break_stack::models::ModelWrite>::write($conn, id, $write_data)
-                        .await
-                        .unwrap();
-                    if !matches!(entry, $expect_pat $(if $cond)*) {
-                        panic!("Case '{}'\nEntry returned by ModelWrite::write didn't match expected output:\nExpected output to match: {}\nGot entry: {:?}", $case_name, stringify!($expect_pat $(if $cond)*), entry);
+        ( $model:ident, $conn:expr, [ $( $case_name:literal : ($id:expr, $write_data:expr) => $expect_pat:pat $(if $cond:expr)?, )* ] ) => {
+            {
+                let mut cases = $crate::models::testutils::TestCases::new("model_write_test_cases");
+                $(
+                    cases.start($case_name);
+                    match <$model as $crate::models::ModelWrite>::write($conn, $id, $write_data).await {
+                        Ok(output) => {
+                            #[allow(clippy::redundant_pattern_matching)]
+                            let matched = matches!(output, $expect_pat $(if $cond)*);
+                            cases.check_match(
+                                $case_name,
+                                "ModelWrite::write",
+                                matched,
+                                stringify!($expect_pat $(if $cond)*),
+                                &output,
+                            );
+                        }
+                        Err(err) => cases.fail($case_name, format!("ModelWrite::write failed: {:?}", err)),
                     }
-                }
-            )*
+                )*
+                cases.finish();
+            }
         }
     }
     pub use model_write_test_cases;
@@ -280,55 +579,155 @@ pub mod testutils {
     #[macro_export]
     macro_rules! model_create_test_cases {
         ( $model:ident, $conn:expr, [ $( $case_name:literal : $create_data:expr => $expect_pat:pat $(if $cond:expr)?, )* ] ) => {
-            $(
-                {
-                    println!("Running case '{}'", $case_name);
-                    let entry = <$model as break_stack::models::ModelCreate>::create($conn, $create_data)
-                        .await
-                        .unwrap();
-                    if !matches!(entry, $expect_pat $(if $cond)*) {
-                        panic!("Case '{}'\nEntry returned by ModelCreate::create didn't match expected output:\nExpected output to match: {}\nGot entry: {:?}", $case_name, stringify!($expect_pat $(if $cond)*), entry);
+            {
+                let mut cases = $crate::models::testutils::TestCases::new("model_create_test_cases");
+                $(
+                    cases.start($case_name);
+                    match <$model as $crate::models::ModelCreate>::create($conn, $create_data).await {
+                        Ok(output) => {
+                            #[allow(clippy::redundant_pattern_matching)]
+                            let matched = matches!(output, $expect_pat $(if $cond)*);
+                            cases.check_match(
+                                $case_name,
+                                "ModelCreate::create",
+                                matched,
+                                stringify!($expect_pat $(if $cond)*),
+                                &output,
+                            );
+                        }
+                        Err(err) => cases.fail($case_name, format!("ModelCreate::create failed: {:?}", err)),
                     }
-                }
-            )*
+                )*
+                cases.finish();
+            }
         }
     }
     pub use model_create_test_cases;
 
     #[macro_export]
-    macro_rules! auth_model_read_test_cases {
-        ( $model:ident, $conn:expr, [ $( $case_name:literal : ($id:literal, $user:expr) => $expect_pat:pat $(if $cond:expr)?, )* ] ) => {
-            $(
-                {
-                    println!("Running case '{}'", $case_name);
-                    let id = $id;
-                    let user = $user;
-                    let res = <$model as break_stack::models::AuthModelRead>::can_read($conn, id, user)
-                        .await;
-                    if !matches!(res, $expect_pat $(if $cond)*) {
-                        panic!("Case '{}'\nResult returned by AuthModelRead::can_read didn't match expected output:\nExpected output to match: {}\nGot: {:?}", $case_name, stringify!($expect_pat $(if $cond)*), res);
+    macro_rules! model_delete_test_cases {
+        ( $model:ident, $conn:expr, [ $( $case_name:literal : $id:expr => $expect_pat:pat $(if $cond:expr)?, )* ] ) => {
+            {
+                let mut cases = $crate::models::testutils::TestCases::new("model_delete_test_cases");
+                $(
+                    cases.start($case_name);
+                    let output = <$model as $crate::models::ModelDelete>::delete($conn, $id).await;
+                    #[allow(clippy::redundant_pattern_matching)]
+                    let matched = matches!(output, $expect_pat $(if $cond)*);
+                    cases.check_match(
+                        $case_name,
+                        "ModelDelete::delete",
+                        matched,
+                        stringify!($expect_pat $(if $cond)*),
+                        &output,
+                    );
+                )*
+                cases.finish();
+            }
+        }
+    }
+    pub use model_delete_test_cases;
+
+    #[macro_export]
+    macro_rules! model_owner_test_cases {
+        ( $model:ident, $conn:expr, [ $( $case_name:literal : $id:expr => $expect_pat:pat $(if $cond:expr)?, )* ] ) => {
+            {
+                let mut cases = $crate::models::testutils::TestCases::new("model_owner_test_cases");
+                $(
+                    cases.start($case_name);
+                    match <$model as $crate::models::WithOwnerModel>::owner($conn, $id).await {
+                        Ok(output) => {
+                            #[allow(clippy::redundant_pattern_matching)]
+                            let matched = matches!(output, $expect_pat $(if $cond)*);
+                            cases.check_match(
+                                $case_name,
+                                "WithOwnerModel::owner",
+                                matched,
+                                stringify!($expect_pat $(if $cond)*),
+                                &output,
+                            );
+                        }
+                        Err(err) => cases.fail($case_name, format!("WithOwnerModel::owner failed: {:?}", err)),
+                    }
+                )*
+                cases.finish();
+            }
+        }
+    }
+    pub use model_owner_test_cases;
+
+    #[macro_export]
+    macro_rules! model_all_for_owner_test_cases {
+        ( $model:ident, $conn:expr, [ $( $case_name:literal : $user_id:expr => $expect_pat:pat $(if $cond:expr)?, )* ] ) => {
+            {
+                let mut cases = $crate::models::testutils::TestCases::new("model_all_for_owner_test_cases");
+                $(
+                    cases.start($case_name);
+                    match <$model as $crate::models::WithOwnerModel>::all_for_owner($conn, $user_id).await {
+                        Ok(output) => {
+                            #[allow(clippy::redundant_pattern_matching)]
+                            let matched = matches!(output.as_slice(), $expect_pat $(if $cond)*);
+                            cases.check_match(
+                                $case_name,
+                                "WithOwnerModel::all_for_owner",
+                                matched,
+                                stringify!($expect_pat $(if $cond)*),
+                                &output,
+                            );
+                        }
+                        Err(err) => cases.fail($case_name, format!("WithOwnerModel::all_for_owner failed: {:?}", err)),
                     }
-                }
-            )*
+                )*
+                cases.finish();
+            }
+        }
+    }
+    pub use model_all_for_owner_test_cases;
+
+    #[macro_export]
+    macro_rules! auth_model_read_test_cases {
+        ( $model:ident, $conn:expr, [ $( $case_name:literal : ($id:expr, $user:expr) => $expect_pat:pat $(if $cond:expr)?, )* ] ) => {
+            {
+                let mut cases = $crate::models::testutils::TestCases::new("auth_model_read_test_cases");
+                $(
+                    cases.start($case_name);
+                    let output = <$model as $crate::models::AuthModelRead>::can_read($conn, $id, $user).await;
+                    #[allow(clippy::redundant_pattern_matching)]
+                    let matched = matches!(output, $expect_pat $(if $cond)*);
+                    cases.check_match(
+                        $case_name,
+                        "AuthModelRead::can_read",
+                        matched,
+                        stringify!($expect_pat $(if $cond)*),
+                        &output,
+                    );
+                )*
+                cases.finish();
+            }
         }
     }
     pub use auth_model_read_test_cases;
 
     #[macro_export]
     macro_rules! auth_model_write_test_cases {
-        ( $model:ident, $conn:expr, [ $( $case_name:literal : ($id:literal, $user:expr, $data:expr) => $expect_pat:pat $(if $cond:expr)?, )* ] ) => {
-            $(
-                {
-                    println!("Running case '{}'", $case_name);
-                    let id = $id;
-                    let user = $user;
-                    let res = <$model as break_stack::models::AuthModelWrite>::can_write($conn, id, user, $data)
-                        .await;
-                    if !matches!(res, $expect_pat $(if $cond)*) {
-                        panic!("Case '{}'\nResult returned by AuthModelWrite::can_write didn't match expected output:\nExpected output to match: {}\nGot: {:?}", $case_name, stringify!($expect_pat $(if $cond)*), res);
-                    }
-                }
-            )*
+        ( $model:ident, $conn:expr, [ $( $case_name:literal : ($id:expr, $user:expr, $data:expr) => $expect_pat:pat $(if $cond:expr)?, )* ] ) => {
+            {
+                let mut cases = $crate::models::testutils::TestCases::new("auth_model_write_test_cases");
+                $(
+                    cases.start($case_name);
+                    let output = <$model as $crate::models::AuthModelWrite>::can_write($conn, $id, $user, $data).await;
+                    #[allow(clippy::redundant_pattern_matching)]
+                    let matched = matches!(output, $expect_pat $(if $cond)*);
+                    cases.check_match(
+                        $case_name,
+                        "AuthModelWrite::can_write",
+                        matched,
+                        stringify!($expect_pat $(if $cond)*),
+                        &output,
+                    );
+                )*
+                cases.finish();
+            }
         }
     }
     pub use auth_model_write_test_cases;
@@ -336,18 +735,105 @@ pub mod testutils {
     #[macro_export]
     macro_rules! auth_model_create_test_cases {
         ( $model:ident, $conn:expr, [ $( $case_name:literal : ($user:expr, $data:expr) => $expect_pat:pat $(if $cond:expr)?, )* ] ) => {
-            $(
-                {
-                    println!("Running case '{}'", $case_name);
-                    let user = $user;
-                    let res = <$model as break_stack::models::AuthModelCreate>::can_create($conn, user, $data)
-                        .await;
-                    if !matches!(res, $expect_pat $(if $cond)*) {
-                        panic!("Case '{}'\nResult returned by AuthModelCreate::can_create didn't match expected output:\nExpected output to match: {}\nGot: {:?}", $case_name, stringify!($expect_pat $(if $cond)*), res);
-                    }
-                }
-            )*
+            {
+                let mut cases = $crate::models::testutils::TestCases::new("auth_model_create_test_cases");
+                $(
+                    cases.start($case_name);
+                    let output = <$model as $crate::models::AuthModelCreate>::can_create($conn, $user, $data).await;
+                    #[allow(clippy::redundant_pattern_matching)]
+                    let matched = matches!(output, $expect_pat $(if $cond)*);
+                    cases.check_match(
+                        $case_name,
+                        "AuthModelCreate::can_create",
+                        matched,
+                        stringify!($expect_pat $(if $cond)*),
+                        &output,
+                    );
+                )*
+                cases.finish();
+            }
         }
     }
     pub use auth_model_create_test_cases;
+
+    #[macro_export]
+    macro_rules! auth_model_delete_test_cases {
+        ( $model:ident, $conn:expr, [ $( $case_name:literal : ($id:expr, $user:expr) => $expect_pat:pat $(if $cond:expr)?, )* ] ) => {
+            {
+                let mut cases = $crate::models::testutils::TestCases::new("auth_model_delete_test_cases");
+                $(
+                    cases.start($case_name);
+                    let output = <$model as $crate::models::AuthModelDelete>::can_delete($conn, $id, $user).await;
+                    #[allow(clippy::redundant_pattern_matching)]
+                    let matched = matches!(output, $expect_pat $(if $cond)*);
+                    cases.check_match(
+                        $case_name,
+                        "AuthModelDelete::can_delete",
+                        matched,
+                        stringify!($expect_pat $(if $cond)*),
+                        &output,
+                    );
+                )*
+                cases.finish();
+            }
+        }
+    }
+    pub use auth_model_delete_test_cases;
+
+    #[macro_export]
+    macro_rules! auth_model_list_test_cases {
+        ( $model:ident, $conn:expr, [ $( $case_name:literal : $user:expr => $expect_pat:pat $(if $cond:expr)?, )* ] ) => {
+            {
+                let mut cases = $crate::models::testutils::TestCases::new("auth_model_list_test_cases");
+                $(
+                    cases.start($case_name);
+                    let output = <$model as $crate::models::AuthModelList>::list($conn, $user).await;
+                    #[allow(clippy::redundant_pattern_matching)]
+                    let matched = matches!(output.as_deref(), $expect_pat $(if $cond)*);
+                    cases.check_match(
+                        $case_name,
+                        "AuthModelList::list",
+                        matched,
+                        stringify!($expect_pat $(if $cond)*),
+                        &output,
+                    );
+                )*
+                cases.finish();
+            }
+        }
+    }
+    pub use auth_model_list_test_cases;
+
+    /// Calls handlers, like the `model_controller_*` handlers, and checks their responses with
+    /// the assertions of `testing::TestResponse`, reporting every failing case at once:
+    ///
+    /// ```ignore
+    /// controller_test_cases!([
+    ///     "read": model_controller_read::<TestController>(
+    ///         MockConn::new().with_rows([TestModel { id: 1, data: 2 }]),
+    ///         Path(1), Some(UserId(1)), HtmxRequest::default(), IfNoneMatch::default(),
+    ///     ) => |response| response.assert_ok().assert_contains("2"),
+    ///     "delete without user": model_controller_delete::<TestController>(
+    ///         MockConn::new(), Path(1), None, HtmxRequest::default(),
+    ///     ) => |response| response.assert_status(StatusCode::UNAUTHORIZED),
+    /// ]);
+    /// ```
+    #[macro_export]
+    macro_rules! controller_test_cases {
+        ( [ $( $case_name:literal : $handler:expr => |$response:ident| $check:expr, )* ] ) => {
+            {
+                let mut cases = $crate::models::testutils::TestCases::new("controller_test_cases");
+                $(
+                    cases.start($case_name);
+                    let response = $crate::testing::TestResponse::from_response($handler.await).await;
+                    cases.check_panics($case_name, || {
+                        let $response = &response;
+                        $check;
+                    });
+                )*
+                cases.finish();
+            }
+        }
+    }
+    pub use controller_test_cases;
 }
@@ -0,0 +1,361 @@
+//! Recording the statements executed on database connections, to see how many queries a request
+//! issues, and to catch N+1 queries (like a query per object in `build_response`).
+//!
+//! `log_queries` gives every request a `QueryLog`, which the `DBConn` extractor of the app
+//! attaches to the connection of the request, until the connection is dropped:
+//!
+//! ```ignore
+//! #[async_trait]
+//! impl FromRequestParts<AppState> for DBConn {
+//!     type Rejection = AppError;
+//!
+//!     async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+//!         let mut conn = state.conn().await?;
+//!         queries::attach(parts, &mut conn).await?;
+//!         Ok(conn)
+//!     }
+//! }
+//!
+//! if cfg!(debug_assertions) {
+//!     app = app.layer(axum::middleware::from_fn(queries::log_queries));
+//! }
+//! ```
+//!
+//! Connections without a log attached aren't traced, so apart from the middleware this costs
+//! nothing in release builds. In debug builds `log_queries` logs a `tracing` warning about
+//! statements a request executed several times with different parameters.
+
+use crate::models::DBConn;
+use axum::{extract::Request, http::request::Parts, middleware::Next, response::Response};
+use libsqlite3_sys::{
+    sqlite3_db_handle, sqlite3_expanded_sql, sqlite3_free, sqlite3_sql, sqlite3_stmt,
+    sqlite3_trace_v2, SQLITE_TRACE_PROFILE, SQLITE_TRACE_STMT,
+};
+use sqlx::SqliteConnection;
+use std::collections::BTreeMap;
+use std::ffi::{c_int, c_uint, c_void, CStr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+/// A statement executed on a connection with a `QueryLog` attached.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    /// The sql of the statement, with the parameter placeholders.
+    pub sql: String,
+    /// The sql with the values of the parameters.
+    pub expanded: String,
+    /// How long the statement took to run, once it's done.
+    pub duration: Option<Duration>,
+}
+
+impl std::fmt::Display for Query {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.expanded)
+    }
+}
+
+#[derive(Default)]
+struct Recorded {
+    queries: Vec<Query>,
+    /// The statements which are still running, with the index of their query.
+    running: Vec<(usize, usize)>,
+}
+
+type Queries = Mutex<Recorded>;
+
+/// The logs attached to each connection, by the address of its sqlite handle, with the id of the
+/// `QueryLogGuard` that attached them. The statements are recorded by the worker thread of the
+/// connection, where the request isn't known.
+static ATTACHED: Mutex<BTreeMap<usize, AttachedLogs>> = Mutex::new(BTreeMap::new());
+
+type AttachedLogs = Vec<(u64, Weak<Queries>)>;
+
+static NEXT_GUARD_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Records the statements executed on the connections it's attached to, until it's dropped.
+#[derive(Clone, Default)]
+pub struct QueryLog(Arc<Queries>);
+
+impl QueryLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the statements executed on `conn` until the returned guard is dropped, or the log
+    /// is dropped.
+    pub async fn attach(&self, conn: &mut SqliteConnection) -> Result<QueryLogGuard, sqlx::Error> {
+        let mut handle = conn.lock_handle().await?;
+        let db = handle.as_raw_handle().as_ptr();
+        let id = NEXT_GUARD_ID.fetch_add(1, Ordering::Relaxed);
+        {
+            let mut attached = ATTACHED.lock().unwrap();
+            attached.retain(|_, logs| {
+                logs.retain(|(_, log)| log.strong_count() > 0);
+                !logs.is_empty()
+            });
+            attached
+                .entry(db as usize)
+                .or_default()
+                .push((id, Arc::downgrade(&self.0)));
+        }
+        // SAFETY: the handle is locked, so the worker thread of the connection isn't using it
+        unsafe {
+            sqlite3_trace_v2(
+                db,
+                (SQLITE_TRACE_STMT | SQLITE_TRACE_PROFILE) as c_uint,
+                Some(trace_statement),
+                std::ptr::null_mut(),
+            );
+        }
+        Ok(QueryLogGuard {
+            db: db as usize,
+            id,
+        })
+    }
+
+    pub fn queries(&self) -> Vec<Query> {
+        self.0.lock().unwrap().queries.clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().queries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forgets the statements recorded so far.
+    pub fn clear(&self) {
+        let mut recorded = self.0.lock().unwrap();
+        recorded.queries.clear();
+        recorded.running.clear();
+    }
+}
+
+/// Keeps a `QueryLog` attached to a connection, see `QueryLog::attach`. A `DBConn` owns the guard
+/// of the log of its request, so the log is detached before the connection goes back to the pool.
+#[must_use = "the log is detached when the guard is dropped"]
+pub struct QueryLogGuard {
+    db: usize,
+    id: u64,
+}
+
+impl QueryLogGuard {
+    /// Keeps the log attached for the lifetime of the connection, or until the log is dropped,
+    /// e.g. for a log of all the connections of a pool, attached in `after_connect`.
+    pub fn keep(self) {
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for QueryLogGuard {
+    fn drop(&mut self) {
+        // The trace hook of the connection is removed by `trace_statement` once nothing is
+        // attached, since the handle can only be used from the worker thread of the connection
+        let mut attached = ATTACHED.lock().unwrap();
+        if let Some(logs) = attached.get_mut(&self.db) {
+            logs.retain(|(id, _)| *id != self.id);
+            if logs.is_empty() {
+                attached.remove(&self.db);
+            }
+        }
+    }
+}
+
+/// Statements executed several times with different parameters, with how many times, which
+/// usually means a query is run in a loop (an N+1 query) instead of fetching all the rows at once.
+pub fn repeated(queries: &[Query]) -> Vec<(String, usize)> {
+    let mut statements: Vec<(String, Vec<&str>)> = Vec::new();
+    for query in queries {
+        match statements.iter_mut().find(|(sql, _)| *sql == query.sql) {
+            Some((_, expanded)) => expanded.push(&query.expanded),
+            None => statements.push((query.sql.clone(), vec![&query.expanded])),
+        }
+    }
+    statements
+        .into_iter()
+        .filter_map(|(sql, mut expanded)| {
+            let count = expanded.len();
+            expanded.sort();
+            expanded.dedup();
+            (expanded.len() > 1).then_some((sql, count))
+        })
+        .collect()
+}
+
+/// Attaches the `QueryLog` of the request (see `log_queries`) to `conn` until `conn` is dropped,
+/// does nothing for requests without one.
+pub async fn attach(parts: &Parts, conn: &mut DBConn) -> Result<(), sqlx::Error> {
+    if let Some(log) = parts.extensions.get::<QueryLog>() {
+        let guard = log.attach(conn).await?;
+        conn.set_query_log(guard);
+    }
+    Ok(())
+}
+
+/// Middleware giving each request a `QueryLog`, see `attach`, unless an outer middleware
+/// (like `dev_toolbar::record_requests`) already did. The log is also put in the response
+/// extensions. In debug builds this logs a `tracing` warning about repeated statements, see
+/// `repeated`.
+pub async fn log_queries(mut request: Request, next: Next) -> Response {
+    let log = request_log(&mut request);
+    let (method, uri) = (request.method().clone(), request.uri().clone());
+
+    let mut response = next.run(request).await;
+    if cfg!(debug_assertions) {
+        for (sql, count) in repeated(&log.queries()) {
+            tracing::warn!(
+                "{} {} executed a statement {} times with different parameters, \
+                 this may be an N+1 query: {}",
+                method,
+                uri,
+                count,
+                sql
+            );
+        }
+    }
+    response.extensions_mut().insert(log);
+    response
+}
+
+/// The `QueryLog` of the request, inserted in its extensions if it doesn't have one yet.
+pub(crate) fn request_log(request: &mut Request) -> QueryLog {
+    request
+        .extensions_mut()
+        .get_or_insert_with(QueryLog::new)
+        .clone()
+}
+
+/// Checks that at most `max` statements were executed, and panics with the statements if more
+/// were. `queries` can be a `testing::TestResponse`, or a `QueryLog` attached to a connection.
+///
+/// ```ignore
+/// let response = assert_max_queries!(2, app.get("/htmx/items").send().await);
+///
+/// let log = QueryLog::new();
+/// log.attach(&mut conn).await?;
+/// model_controller_list::<TodoItemsController>(conn, Some(UserId(1)), HtmxRequest::default()).await?;
+/// assert_max_queries!(1, log);
+/// ```
+#[macro_export]
+macro_rules! assert_max_queries {
+    ($max:expr, $queries:expr) => {{
+        let queries = $queries;
+        $crate::queries::check_max_queries(
+            $max,
+            &$crate::queries::RecordedQueries::recorded_queries(&queries),
+            stringify!($queries),
+        );
+        queries
+    }};
+}
+pub use assert_max_queries;
+
+/// Things that recorded statements, for `assert_max_queries!`.
+pub trait RecordedQueries {
+    fn recorded_queries(&self) -> Vec<Query>;
+}
+
+impl<T: RecordedQueries + ?Sized> RecordedQueries for &T {
+    fn recorded_queries(&self) -> Vec<Query> {
+        (**self).recorded_queries()
+    }
+}
+
+impl RecordedQueries for QueryLog {
+    fn recorded_queries(&self) -> Vec<Query> {
+        self.queries()
+    }
+}
+
+#[doc(hidden)]
+pub fn check_max_queries(max: usize, queries: &[Query], what: &str) {
+    if queries.len() <= max {
+        return;
+    }
+    let mut message = format!(
+        "expected at most {} queries for {}, got {}:\n",
+        max,
+        what,
+        queries.len()
+    );
+    for query in queries {
+        message.push_str(&format!("  {}\n", query));
+    }
+    for (sql, count) in repeated(queries) {
+        message.push_str(&format!("repeated {} times: {}\n", count, sql));
+    }
+    panic!("{}", message);
+}
+
+unsafe extern "C" fn trace_statement(
+    mask: c_uint,
+    _context: *mut c_void,
+    statement: *mut c_void,
+    x: *mut c_void,
+) -> c_int {
+    let statement = statement as *mut sqlite3_stmt;
+    let _ = std::panic::catch_unwind(|| {
+        let db = sqlite3_db_handle(statement);
+        let logs = ATTACHED
+            .lock()
+            .unwrap()
+            .get(&(db as usize))
+            .map(|logs| {
+                logs.iter()
+                    .filter_map(|(_, log)| log.upgrade())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        if logs.is_empty() {
+            // Nothing is attached anymore, so stop tracing the statements of the connection
+            sqlite3_trace_v2(db, 0, None, std::ptr::null_mut());
+            return;
+        }
+
+        if mask == SQLITE_TRACE_PROFILE as c_uint {
+            let duration = Duration::from_nanos(*(x as *const i64) as u64);
+            for log in logs {
+                let mut recorded = log.lock().unwrap();
+                let Recorded { queries, running } = &mut *recorded;
+                if let Some(position) = running.iter().position(|(s, _)| *s == statement as usize) {
+                    let (_, index) = running.swap_remove(position);
+                    queries[index].duration = Some(duration);
+                }
+            }
+            return;
+        }
+
+        // Statements of triggers are traced as comments
+        let traced = CStr::from_ptr(x as *const _).to_string_lossy();
+        if traced.starts_with("--") {
+            return;
+        }
+        let sql = CStr::from_ptr(sqlite3_sql(statement))
+            .to_string_lossy()
+            .into_owned();
+        let expanded = sqlite3_expanded_sql(statement);
+        let query = Query {
+            expanded: if expanded.is_null() {
+                sql.clone()
+            } else {
+                let text = CStr::from_ptr(expanded).to_string_lossy().into_owned();
+                sqlite3_free(expanded as *mut c_void);
+                text
+            },
+            sql,
+            duration: None,
+        };
+        for log in logs {
+            let mut recorded = log.lock().unwrap();
+            let index = recorded.queries.len();
+            recorded.queries.push(query.clone());
+            // A statement which is run again is reset first, which ends its previous run
+            recorded.running.retain(|(s, _)| *s != statement as usize);
+            recorded.running.push((statement as usize, index));
+        }
+    });
+    0
+}
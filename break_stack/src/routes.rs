@@ -3,6 +3,9 @@ macro_rules! build_router {
     ( $app_state:ident, $( ($path_id:ident, $path_fmt:expr, ( $( path -> $( $path_arg:ident : $path_arg_t:ty => $fmt_arg:expr ),* )? ), $handler:expr) , )* ) => {
         #[allow(dead_code)]
         pub mod route_paths {
+            #[allow(unused_imports)]
+            use super::*;
+
             $(
                 pub fn $path_id($($($path_arg : $path_arg_t),*)*) -> String {
                     format!($path_fmt, $($($path_arg),*)*)
@@ -22,3 +25,97 @@ macro_rules! build_router {
     };
 }
 pub use build_router;
+
+/// Declares a module with a router and `route_paths` for a CRUD resource, using the
+/// "model-based" controllers with conventional paths:
+///
+/// | action   | route                     | handler                                  |
+/// |----------|---------------------------|------------------------------------------|
+/// | `list`   | `GET {path}`              | `list` component from `AuthModelList`    |
+/// | `create` | `POST {path}`             | `view` component of the created object   |
+/// | `new`    | `GET {path}/new`          | `new` component, using `Default`         |
+/// | `read`   | `GET {path}/:id`          | `view` component                         |
+/// | `write`  | `PUT {path}/:id`          | `view` component of the updated object   |
+/// | `delete` | `DELETE {path}/:id`       | empty response                           |
+/// | `edit`   | `GET {path}/:id/edit`     | `edit` component                         |
+///
+/// The paths are available as `route_paths::{index, new, id, edit}` in the declared module.
+/// Any of the handlers can be replaced in an optional `overrides` block:
+///
+/// ```ignore
+/// resource! {
+///     AppState, htmx_items, "/htmx/items", TodoItemModel,
+///     view: TodoItemViewComponent,
+///     edit: TodoItemEditComponent,
+///     new: TodoItemNewComponent,
+///     list: TodoItemListComponent,
+///     overrides {
+///         delete: model_controller_delete::<HtmxTodoItemDeletedController>,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! resource {
+    (
+        $app_state:ident, $name:ident, $path:literal, $model:ty,
+        view: $view:ty,
+        edit: $edit:ty,
+        new: $new:ty,
+        list: $list:ty
+        $(, overrides { $($overrides:tt)* })? $(,)?
+    ) => {
+        pub mod $name {
+            #[allow(unused_imports)]
+            use super::*;
+
+            pub type ViewController = $crate::controllers::ComponentFromModelController<$model, $view>;
+            pub type EditController = $crate::controllers::ComponentFromModelController<$model, $edit>;
+            pub type ListController = $crate::controllers::ComponentFromModelsController<$model, $list>;
+
+            $crate::resource! {
+                @handlers $app_state, $path, $model,
+                [
+                    $crate::controllers::model_controller_list::<ListController>,
+                    $crate::controllers::model_controller_create::<ViewController>,
+                    $crate::controllers::component_controller_default::<$new>,
+                    $crate::controllers::model_controller_read::<ViewController>,
+                    $crate::controllers::model_controller_write::<ViewController>,
+                    $crate::controllers::model_controller_delete::<$crate::controllers::EmptyModelController<$model>>,
+                    $crate::controllers::model_controller_read::<EditController>
+                ]
+                $($($overrides)*)?
+            }
+        }
+    };
+    (@handlers $app_state:ident, $path:literal, $model:ty, [$list:expr, $create:expr, $new:expr, $read:expr, $write:expr, $delete:expr, $edit:expr] list: $handler:expr $(, $($rest:tt)*)?) => {
+        $crate::resource! { @handlers $app_state, $path, $model, [$handler, $create, $new, $read, $write, $delete, $edit] $($($rest)*)? }
+    };
+    (@handlers $app_state:ident, $path:literal, $model:ty, [$list:expr, $create:expr, $new:expr, $read:expr, $write:expr, $delete:expr, $edit:expr] create: $handler:expr $(, $($rest:tt)*)?) => {
+        $crate::resource! { @handlers $app_state, $path, $model, [$list, $handler, $new, $read, $write, $delete, $edit] $($($rest)*)? }
+    };
+    (@handlers $app_state:ident, $path:literal, $model:ty, [$list:expr, $create:expr, $new:expr, $read:expr, $write:expr, $delete:expr, $edit:expr] new: $handler:expr $(, $($rest:tt)*)?) => {
+        $crate::resource! { @handlers $app_state, $path, $model, [$list, $create, $handler, $read, $write, $delete, $edit] $($($rest)*)? }
+    };
+    (@handlers $app_state:ident, $path:literal, $model:ty, [$list:expr, $create:expr, $new:expr, $read:expr, $write:expr, $delete:expr, $edit:expr] read: $handler:expr $(, $($rest:tt)*)?) => {
+        $crate::resource! { @handlers $app_state, $path, $model, [$list, $create, $new, $handler, $write, $delete, $edit] $($($rest)*)? }
+    };
+    (@handlers $app_state:ident, $path:literal, $model:ty, [$list:expr, $create:expr, $new:expr, $read:expr, $write:expr, $delete:expr, $edit:expr] write: $handler:expr $(, $($rest:tt)*)?) => {
+        $crate::resource! { @handlers $app_state, $path, $model, [$list, $create, $new, $read, $handler, $delete, $edit] $($($rest)*)? }
+    };
+    (@handlers $app_state:ident, $path:literal, $model:ty, [$list:expr, $create:expr, $new:expr, $read:expr, $write:expr, $delete:expr, $edit:expr] delete: $handler:expr $(, $($rest:tt)*)?) => {
+        $crate::resource! { @handlers $app_state, $path, $model, [$list, $create, $new, $read, $write, $handler, $edit] $($($rest)*)? }
+    };
+    (@handlers $app_state:ident, $path:literal, $model:ty, [$list:expr, $create:expr, $new:expr, $read:expr, $write:expr, $delete:expr, $edit:expr] edit: $handler:expr $(, $($rest:tt)*)?) => {
+        $crate::resource! { @handlers $app_state, $path, $model, [$list, $create, $new, $read, $write, $delete, $handler] $($($rest)*)? }
+    };
+    (@handlers $app_state:ident, $path:literal, $model:ty, [$list:expr, $create:expr, $new:expr, $read:expr, $write:expr, $delete:expr, $edit:expr]) => {
+        $crate::build_router! {
+            $app_state,
+            (index, $path, (), ::axum::routing::get($list).post($create)),
+            (new, concat!($path, "/new"), (), ::axum::routing::get($new)),
+            (id, concat!($path, "/{}"), (path -> id: &<$model as $crate::models::Model>::ID => ":id"), ::axum::routing::get($read).put($write).delete($delete)),
+            (edit, concat!($path, "/{}/edit"), (path -> id: &<$model as $crate::models::Model>::ID => ":id"), ::axum::routing::get($edit)),
+        }
+    };
+}
+pub use resource;
@@ -0,0 +1,236 @@
+use crate::errors::*;
+use crate::models::{DBConn, DBPool};
+use crate::utils::unix_timestamp;
+use axum::{
+    extract::{Path, Request},
+    http::request::Parts,
+    response::IntoResponse,
+    routing::post,
+    Router,
+};
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+type TaskFuture = Pin<Box<dyn Future<Output = AppResult<()>> + Send>>;
+
+#[derive(Clone)]
+struct ScheduledTask {
+    name: &'static str,
+    schedule: cron::Schedule,
+    run: Arc<dyn Fn(DBConn) -> TaskFuture + Send + Sync>,
+}
+
+/// Runs tasks on cron schedules. The last run of each task is stored in the
+/// `break_stack_scheduled_tasks` table (see `migrations::run`), so restarting the app
+/// doesn't run a task again before its next scheduled time, and if several instances of
+/// the app share the database, only one of them runs each scheduled run.
+///
+/// ```ignore
+/// let scheduler = Scheduler::new(db_pool.clone())
+///     .task("purge_done_items", "0 0 3 * * *", |mut conn| async move {
+///         TodoItemModel::purge_done(&mut conn).await?;
+///         Ok(())
+///     });
+/// scheduler.clone().spawn();
+/// ```
+///
+/// Schedules use the `cron` crate syntax, with seconds: `sec min hour day month weekday [year]`,
+/// and are evaluated in UTC. A task that was missed (e.g. because the app was down) runs
+/// once when the scheduler is started again, not once per missed run.
+#[derive(Clone)]
+pub struct Scheduler {
+    pool: DBPool,
+    tasks: Vec<ScheduledTask>,
+    tick_interval: Duration,
+    on_error: Arc<dyn Fn(&AppError) + Send + Sync>,
+}
+
+impl Scheduler {
+    pub fn new(pool: DBPool) -> Self {
+        Self {
+            pool,
+            tasks: Vec::new(),
+            tick_interval: Duration::from_secs(1),
+            on_error: Arc::new(|err| tracing::error!("failed to run scheduled tasks: {:?}", err)),
+        }
+    }
+
+    /// Registers a task. Panics if `schedule` isn't a valid cron expression, or if a task
+    /// with the same name is already registered.
+    pub fn task<F, Fut>(mut self, name: &'static str, schedule: &str, run: F) -> Self
+    where
+        F: Fn(DBConn) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = AppResult<()>> + Send + 'static,
+    {
+        let schedule = cron::Schedule::from_str(schedule)
+            .unwrap_or_else(|e| panic!("invalid schedule for task '{}': {}", name, e));
+        assert!(
+            self.find(name).is_none(),
+            "task '{}' is already registered",
+            name
+        );
+        self.tasks.push(ScheduledTask {
+            name,
+            schedule,
+            run: Arc::new(move |conn| Box::pin(run(conn))),
+        });
+        self
+    }
+
+    /// How often to check if any tasks are due.
+    pub fn tick_interval(mut self, tick_interval: Duration) -> Self {
+        self.tick_interval = tick_interval;
+        self
+    }
+
+    /// Called by `run` when the scheduler fails to check or run the due tasks, e.g. because
+    /// the database is unavailable, logs the error with `tracing` by default. Errors returned
+    /// by tasks are stored as their `last_error` instead.
+    pub fn on_error(mut self, on_error: impl Fn(&AppError) + Send + Sync + 'static) -> Self {
+        self.on_error = Arc::new(on_error);
+        self
+    }
+
+    fn find(&self, name: &str) -> Option<&ScheduledTask> {
+        self.tasks.iter().find(|task| task.name == name)
+    }
+
+    /// Runs all tasks that are due at `now` (unix timestamp in seconds), returns the number of
+    /// tasks that were run. Errors returned by the tasks are stored as the `last_error` of the
+    /// task, and don't make this fail.
+    pub async fn run_due_at(&self, now: i64) -> AppResult<usize> {
+        let mut count = 0;
+        for task in &self.tasks {
+            if self.claim(task, now).await? {
+                // The error is stored on the task, only fail on database errors
+                let _ = self.run_task(task, now).await?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    pub async fn run_due(&self) -> AppResult<usize> {
+        self.run_due_at(unix_timestamp()).await
+    }
+
+    /// Runs a task right away, regardless of its schedule, and returns the result of the task.
+    /// This counts as a run, so the next scheduled run is calculated from now.
+    pub async fn run_now(&self, name: &str) -> AppResult<()> {
+        let task = self.find(name).ok_or(AppError::NotFound)?;
+        let now = unix_timestamp();
+        let mut conn = self.pool.acquire().await?;
+        sqlx::query(
+            "INSERT INTO break_stack_scheduled_tasks (name, last_run_at) VALUES (?1, ?2)
+            ON CONFLICT (name) DO UPDATE SET last_run_at = ?2",
+        )
+        .bind(task.name)
+        .bind(now)
+        .execute(&mut *conn)
+        .await?;
+        drop(conn);
+        self.run_task(task, now).await?
+    }
+
+    /// Checks if the task is due and marks it as run, returns `false` if the task isn't due,
+    /// or if it was claimed by someone else.
+    async fn claim(&self, task: &ScheduledTask, now: i64) -> AppResult<bool> {
+        let mut conn = self.pool.acquire().await?;
+        // Tasks that haven't run before are scheduled from when they were first seen
+        sqlx::query(
+            "INSERT OR IGNORE INTO break_stack_scheduled_tasks (name, last_run_at) VALUES (?, ?)",
+        )
+        .bind(task.name)
+        .bind(now)
+        .execute(&mut *conn)
+        .await?;
+        let last_run_at: i64 = sqlx::query_scalar(
+            "SELECT last_run_at FROM break_stack_scheduled_tasks WHERE name = ?",
+        )
+        .bind(task.name)
+        .fetch_one(&mut *conn)
+        .await?;
+
+        let Some(last_run) = chrono::DateTime::from_timestamp(last_run_at, 0) else {
+            return Ok(false);
+        };
+        let Some(next_run) = task.schedule.after(&last_run).next() else {
+            return Ok(false);
+        };
+        if next_run.timestamp() > now {
+            return Ok(false);
+        }
+
+        let claimed = sqlx::query(
+            "UPDATE break_stack_scheduled_tasks SET last_run_at = ? WHERE name = ? AND last_run_at = ?",
+        )
+        .bind(now)
+        .bind(task.name)
+        .bind(last_run_at)
+        .execute(&mut *conn)
+        .await?;
+        Ok(claimed.rows_affected() == 1)
+    }
+
+    /// Runs the task and stores its error, returns the result of the task, or an error if the
+    /// result couldn't be stored.
+    async fn run_task(&self, task: &ScheduledTask, now: i64) -> AppResult<AppResult<()>> {
+        let result = (task.run)(self.pool.acquire().await?.into()).await;
+
+        let mut conn = self.pool.acquire().await?;
+        sqlx::query(
+            "UPDATE break_stack_scheduled_tasks SET last_error = ? WHERE name = ? AND last_run_at = ?",
+        )
+        .bind(result.as_ref().err().map(|err| format!("{:?}", err)))
+        .bind(task.name)
+        .bind(now)
+        .execute(&mut *conn)
+        .await?;
+        Ok(result)
+    }
+
+    /// Runs due tasks forever, checking every `tick_interval`.
+    pub async fn run(self) {
+        loop {
+            if let Err(err) = self.run_due().await {
+                (self.on_error)(&err);
+            }
+            tokio::time::sleep(self.tick_interval).await;
+        }
+    }
+
+    pub fn spawn(self) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(self.run())
+    }
+}
+
+/// Router with a `POST /:name` endpoint that runs a scheduled task right away, for the
+/// requests `authorize` accepts. The other requests get a 403 without running anything.
+///
+/// ```ignore
+/// .nest(
+///     "/scheduler",
+///     scheduler::scheduler_router(scheduler.clone(), |parts| {
+///         parts.extensions.get::<UserId>().is_some_and(|user_id| is_admin(*user_id))
+///     }),
+/// )
+/// ```
+pub fn scheduler_router<S: Clone + Send + Sync + 'static>(
+    scheduler: Scheduler,
+    authorize: impl Fn(&Parts) -> bool + Clone + Send + Sync + 'static,
+) -> Router<S> {
+    Router::new().route(
+        "/:name",
+        post(|Path(name): Path<String>, request: Request| async move {
+            let (parts, _) = request.into_parts();
+            if !authorize(&parts) {
+                return Err(AppError::Auth(AuthError::Unauthorized));
+            }
+            scheduler.run_now(&name).await?;
+            AppResult::Ok(().into_response())
+        }),
+    )
+}
@@ -0,0 +1,93 @@
+use crate::hot_reload::hot_reload_enabled;
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderValue},
+    response::Response,
+};
+
+pub const SESSION_COOKIE: &str = "break_stack_session";
+
+/// Extractor for an anonymous session id stored in a cookie, used to key server-side state like
+/// wizard drafts. Extracting this never fails, if the request doesn't have a session a new id is
+/// generated, and `apply` needs to be called on the response to set the cookie. The cookie is
+/// `Secure` unless hot reloading is enabled, like during development.
+///
+/// The id only identifies the browser, it isn't tied to the user, so state keyed by the session
+/// should also check the user if it matters who it belongs to.
+#[derive(Debug, Clone)]
+pub struct SessionId {
+    id: String,
+    is_new: bool,
+}
+
+impl SessionId {
+    pub fn new() -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            is_new: true,
+        }
+    }
+
+    /// Session from an existing id, e.g. in tests.
+    pub fn existing(id: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            is_new: false,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.id
+    }
+
+    pub fn is_new(&self) -> bool {
+        self.is_new
+    }
+
+    /// Sets the session cookie on the response if the session is new.
+    pub fn apply(&self, mut response: Response) -> Response {
+        if !self.is_new {
+            return response;
+        }
+        // Outside of development the app is expected to be served over https
+        let secure = if hot_reload_enabled() { "" } else { "; Secure" };
+        let cookie = format!(
+            "{}={}; Path=/; HttpOnly; SameSite=Lax{}",
+            SESSION_COOKIE, self.id, secure
+        );
+        if let Ok(value) = HeaderValue::from_str(&cookie) {
+            response.headers_mut().append(header::SET_COOKIE, value);
+        }
+        response
+    }
+}
+
+impl Default for SessionId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for SessionId {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let id = parts
+            .headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .find(|(name, _)| *name == SESSION_COOKIE)
+            .map(|(_, id)| id)
+            // Only accept ids that look like the ones generated here
+            .filter(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_hexdigit() || c == '-'));
+        Ok(match id {
+            Some(id) => Self::existing(id),
+            None => Self::new(),
+        })
+    }
+}
@@ -0,0 +1,324 @@
+use crate::errors::*;
+use crate::models::{DBConn, Model};
+use crate::utils::unix_timestamp;
+use axum::{async_trait, body::Bytes, extract::Multipart};
+use serde::de::DeserializeOwned;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Where the content of uploaded files is stored. The keys are generated by `Attachment::create`,
+/// and only contain ascii letters, digits and `-`.
+#[async_trait]
+pub trait StorageBackend: Send + Sync + 'static {
+    async fn put(&self, key: &str, data: Bytes) -> AppResult<()>;
+    async fn get(&self, key: &str) -> AppResult<Option<Bytes>>;
+    /// Deleting a key that doesn't exist isn't an error.
+    async fn delete(&self, key: &str) -> AppResult<()>;
+}
+
+/// Stores files in a directory on the local filesystem.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, key: &str) -> AppResult<PathBuf> {
+        if key.is_empty()
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(AppError::Internal(format!("invalid storage key '{}'", key)));
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+#[async_trait]
+impl StorageBackend for LocalStorage {
+    async fn put(&self, key: &str, data: Bytes) -> AppResult<()> {
+        let path = self.path(key)?;
+        tokio::fs::create_dir_all(&self.root)
+            .await
+            .map_err(|e| AppError::Internal(format!("failed to create storage dir: {}", e)))?;
+        tokio::fs::write(path, data)
+            .await
+            .map_err(|e| AppError::Internal(format!("failed to write file: {}", e)))
+    }
+
+    async fn get(&self, key: &str) -> AppResult<Option<Bytes>> {
+        match tokio::fs::read(self.path(key)?).await {
+            Ok(data) => Ok(Some(data.into())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(AppError::Internal(format!("failed to read file: {}", e))),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> AppResult<()> {
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(AppError::Internal(format!("failed to delete file: {}", e))),
+        }
+    }
+}
+
+/// Handle to the storage backend, cheap to clone. The multipart controllers extract this from
+/// the request, so the app needs to implement `FromRequestParts<AppState>` for it, the same
+/// way as for `DBConn`.
+#[derive(Clone)]
+pub struct Storage(Arc<dyn StorageBackend>);
+
+impl Storage {
+    pub fn new(backend: impl StorageBackend) -> Self {
+        Self(Arc::new(backend))
+    }
+
+    pub fn local(root: impl Into<PathBuf>) -> Self {
+        Self::new(LocalStorage::new(root))
+    }
+}
+
+impl std::ops::Deref for Storage {
+    type Target = dyn StorageBackend;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+/// A file from a multipart request.
+#[derive(Debug, Clone)]
+pub struct Upload {
+    /// Name of the form field the file was uploaded with.
+    pub field: String,
+    pub filename: String,
+    pub content_type: String,
+    pub data: Bytes,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct UploadLimits {
+    pub max_file_size: usize,
+    pub max_files: usize,
+    /// Allowed content types, either exact (`image/png`), or a prefix (`image/*`). If this is
+    /// empty all types are allowed.
+    pub allowed_content_types: &'static [&'static str],
+}
+
+impl UploadLimits {
+    pub const DEFAULT: Self = Self {
+        max_file_size: 10 * 1024 * 1024,
+        max_files: 10,
+        allowed_content_types: &[],
+    };
+
+    /// Largest request with `max_files` files of `max_file_size`, and up to 1MB of other fields,
+    /// see `controllers::upload_body_limit`.
+    pub const fn max_body_size(&self) -> usize {
+        self.max_file_size
+            .saturating_mul(self.max_files)
+            .saturating_add(1024 * 1024)
+    }
+
+    fn allows_content_type(&self, content_type: &str) -> bool {
+        self.allowed_content_types.is_empty()
+            || self
+                .allowed_content_types
+                .iter()
+                .any(|allowed| match allowed.strip_suffix('*') {
+                    Some(prefix) => content_type.starts_with(prefix),
+                    None => content_type == *allowed,
+                })
+    }
+}
+
+impl Default for UploadLimits {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// A multipart form, with the text fields deserialized into `data` the same way as for `Form`,
+/// and the files in `uploads`.
+pub struct MultipartForm<T> {
+    pub data: T,
+    pub uploads: Vec<Upload>,
+}
+
+impl<T: DeserializeOwned> MultipartForm<T> {
+    /// Reads the whole form, failing with `AppError::BadRequest` if it's invalid or if any of the
+    /// files break the limits. Note that axum's `DefaultBodyLimit` (2MB by default) also applies
+    /// to multipart requests, `controllers::upload_body_limit` raises it to match the limits.
+    pub async fn parse(mut multipart: Multipart, limits: &UploadLimits) -> AppResult<Self> {
+        let bad_request = |e: axum::extract::multipart::MultipartError| {
+            AppError::BadRequest(format!("invalid multipart form: {}", e))
+        };
+
+        let mut fields: Vec<(String, String)> = Vec::new();
+        let mut uploads = Vec::new();
+        while let Some(mut field) = multipart.next_field().await.map_err(bad_request)? {
+            let name = field.name().unwrap_or_default().to_string();
+            let Some(filename) = field.file_name().map(|filename| filename.to_string()) else {
+                fields.push((name, field.text().await.map_err(bad_request)?));
+                continue;
+            };
+            let content_type = field
+                .content_type()
+                .unwrap_or("application/octet-stream")
+                .to_string();
+
+            let mut data = Vec::new();
+            while let Some(chunk) = field.chunk().await.map_err(bad_request)? {
+                if data.len() + chunk.len() > limits.max_file_size {
+                    return Err(AppError::BadRequest(format!(
+                        "file '{}' is larger than {} bytes",
+                        filename, limits.max_file_size
+                    )));
+                }
+                data.extend_from_slice(&chunk);
+            }
+            // Browsers send an empty file for file inputs without a selected file
+            if filename.is_empty() && data.is_empty() {
+                continue;
+            }
+
+            if !limits.allows_content_type(&content_type) {
+                return Err(AppError::BadRequest(format!(
+                    "file '{}' has a content type that isn't allowed: {}",
+                    filename, content_type
+                )));
+            }
+            if uploads.len() >= limits.max_files {
+                return Err(AppError::BadRequest(format!(
+                    "more than {} files uploaded",
+                    limits.max_files
+                )));
+            }
+            uploads.push(Upload {
+                field: name,
+                filename,
+                content_type,
+                data: data.into(),
+            });
+        }
+
+        let fields = serde_urlencoded::to_string(&fields)
+            .map_err(|e| AppError::Internal(format!("failed to encode form fields: {}", e)))?;
+        let data = serde_urlencoded::from_str(&fields)
+            .map_err(|e| AppError::BadRequest(format!("invalid form fields: {}", e)))?;
+        Ok(Self { data, uploads })
+    }
+}
+
+/// A file stored with `Storage`, linked to an object of some `Model`.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Attachment {
+    pub id: i64,
+    pub model_name: String,
+    pub model_id: String,
+    pub field: String,
+    pub filename: String,
+    pub content_type: String,
+    pub size: i64,
+    pub storage_key: String,
+    pub created_at: i64,
+}
+
+impl Attachment {
+    /// Stores the uploaded file and links it to the object `model_id` of `M`.
+    pub async fn create<M: Model>(
+        conn: &mut DBConn,
+        storage: &Storage,
+        model_id: M::ID,
+        upload: Upload,
+    ) -> AppResult<Self>
+    where
+        M::ID: std::fmt::Display,
+    {
+        let storage_key = uuid::Uuid::new_v4().to_string();
+        storage.put(&storage_key, upload.data.clone()).await?;
+
+        let attachment = sqlx::query_as(
+            "INSERT INTO break_stack_attachments (model_name, model_id, field, filename, content_type, size, storage_key, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?) RETURNING *",
+        )
+        .bind(M::MODEL_NAME)
+        .bind(model_id.to_string())
+        .bind(upload.field)
+        .bind(upload.filename)
+        .bind(upload.content_type)
+        .bind(upload.data.len() as i64)
+        .bind(&storage_key)
+        .bind(unix_timestamp())
+        .fetch_one(&mut **conn)
+        .await;
+        match attachment {
+            Ok(attachment) => Ok(attachment),
+            Err(err) => {
+                // Report the original error, even if the file can't be cleaned up
+                let _ = storage.delete(&storage_key).await;
+                Err(err.into())
+            }
+        }
+    }
+
+    pub async fn read(conn: &mut DBConn, id: i64) -> Result<Option<Self>, ModelError> {
+        Ok(
+            sqlx::query_as("SELECT * FROM break_stack_attachments WHERE id = ?")
+                .bind(id)
+                .fetch_optional(&mut **conn)
+                .await?,
+        )
+    }
+
+    /// All attachments of the object `model_id` of `M`, oldest first.
+    pub async fn all_for<M: Model>(
+        conn: &mut DBConn,
+        model_id: M::ID,
+    ) -> Result<Vec<Self>, ModelError>
+    where
+        M::ID: std::fmt::Display,
+    {
+        Ok(sqlx::query_as(
+            "SELECT * FROM break_stack_attachments WHERE model_name = ? AND model_id = ? ORDER BY id",
+        )
+        .bind(M::MODEL_NAME)
+        .bind(model_id.to_string())
+        .fetch_all(&mut **conn)
+        .await?)
+    }
+
+    pub async fn data(&self, storage: &Storage) -> AppResult<Bytes> {
+        storage.get(&self.storage_key).await?.ok_or_else(|| {
+            AppError::Internal(format!("file for attachment {} is missing", self.id))
+        })
+    }
+
+    pub async fn delete(self, conn: &mut DBConn, storage: &Storage) -> AppResult<()> {
+        sqlx::query("DELETE FROM break_stack_attachments WHERE id = ?")
+            .bind(self.id)
+            .execute(&mut **conn)
+            .await?;
+        storage.delete(&self.storage_key).await
+    }
+
+    /// Deletes all attachments of an object, e.g. from `ModelController::after_delete`.
+    pub async fn delete_all_for<M: Model>(
+        conn: &mut DBConn,
+        storage: &Storage,
+        model_id: M::ID,
+    ) -> AppResult<()>
+    where
+        M::ID: std::fmt::Display,
+    {
+        for attachment in Self::all_for::<M>(conn, model_id).await? {
+            attachment.delete(conn, storage).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+//! Rendering the templates of components from disk in dev builds, so template changes show up
+//! without rebuilding the app.
+//!
+//! `#[derive(Component)]` implements `ReloadableTemplate` for the templates it declares. When
+//! reloading is enabled (see `enabled`), components whose template is a file
+//! (`#[template(path = "...")]`), or extends or includes files, are rendered with minijinja
+//! from the templates directory of the crate, and with the compiled askama template otherwise.
+//! If minijinja can't render the template (e.g. because it uses some askama specific syntax),
+//! or the fields of the component don't implement `Serialize`, the compiled template is used,
+//! with a warning.
+//!
+//! Rust paths in the templates, like `{{ break_stack::flash::flash_toasts_tag()|safe }}`, are
+//! resolved with the functions registered with `add_function`. The functions used by the
+//! break_stack templates, and the askama filters of `utils::askama::filters`, are always there.
+//!
+//! Rendering from disk needs the `template-reload` feature, which should only be enabled for
+//! development, e.g. through a `dev` feature of the app. Without it minijinja isn't compiled,
+//! and the components are always rendered with their compiled templates.
+
+use crate::dev_toolbar;
+use crate::hot_reload::hot_reload_enabled;
+use axum::http::{header, HeaderValue};
+use axum::response::{IntoResponse, Response};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+#[cfg(feature = "template-reload")]
+mod disk;
+
+#[cfg(feature = "template-reload")]
+use disk::render_from_disk;
+#[cfg(feature = "template-reload")]
+pub use disk::{add_function, Value};
+#[doc(hidden)]
+#[cfg(feature = "template-reload")]
+pub use disk::{context, ContextField, ToContextValue, ToContextValueFallback};
+
+/// Where the template of a component comes from.
+#[derive(Debug, Clone, Copy)]
+pub enum TemplateSource {
+    /// `#[template(path = "...")]`, relative to the templates directory.
+    Path(&'static str),
+    /// `#[template(source = "...", ext = "...")]`.
+    Source {
+        source: &'static str,
+        ext: &'static str,
+    },
+    /// Templates only rendered with askama, like the ones rendering a single `block`.
+    Compiled,
+}
+
+/// Template which can be rendered from disk, implemented by `#[derive(Component)]`.
+pub trait ReloadableTemplate {
+    /// Name of the component, for the warnings.
+    const NAME: &'static str;
+    /// Path of the component, for the dev toolbar.
+    const PATH: &'static str;
+    const SOURCE: TemplateSource;
+    /// Directory of the templates of the crate declaring the component.
+    const TEMPLATES_DIR: &'static str;
+
+    /// The fields of the template, `None` if some of them don't implement `Serialize`.
+    #[cfg(feature = "template-reload")]
+    fn context(&self) -> Option<Value>;
+}
+
+/// Items of the `ReloadableTemplate` impls of `#[derive(Component)]` which are only needed to
+/// render from disk, so they're only compiled with the `template-reload` feature.
+#[doc(hidden)]
+#[macro_export]
+#[cfg(feature = "template-reload")]
+macro_rules! __template_reload_items {
+    ($($item:tt)*) => { $($item)* };
+}
+
+#[doc(hidden)]
+#[macro_export]
+#[cfg(not(feature = "template-reload"))]
+macro_rules! __template_reload_items {
+    ($($item:tt)*) => {};
+}
+
+const UNSET: u8 = 0;
+const DISABLED: u8 = 1;
+const ENABLED: u8 = 2;
+
+static ENABLED_STATE: AtomicU8 = AtomicU8::new(UNSET);
+
+/// If templates are rendered from disk, by default in debug builds with hot reload enabled.
+/// Always `false` without the `template-reload` feature.
+pub fn enabled() -> bool {
+    if !cfg!(feature = "template-reload") {
+        return false;
+    }
+    match ENABLED_STATE.load(Ordering::Relaxed) {
+        UNSET => cfg!(debug_assertions) && hot_reload_enabled(),
+        state => state == ENABLED,
+    }
+}
+
+/// Enables or disables rendering the templates from disk, e.g. in tests.
+pub fn set_enabled(enabled: bool) {
+    ENABLED_STATE.store(if enabled { ENABLED } else { DISABLED }, Ordering::Relaxed);
+}
+
+#[cfg(not(feature = "template-reload"))]
+fn render_from_disk<T>(_template: &T) -> Option<String> {
+    None
+}
+
+/// Renders `template` from disk if reloading is enabled, with the compiled template otherwise.
+/// The component is recorded in the dev toolbar, see `dev_toolbar::record_component`.
+pub fn render<T: askama::Template + ReloadableTemplate>(template: &T) -> askama::Result<String> {
+    dev_toolbar::record_component(T::PATH);
+    match render_from_disk(template) {
+        Some(rendered) => Ok(rendered),
+        None => template.render(),
+    }
+}
+
+/// Same as `render`, as a response like the one of `askama_axum`.
+pub fn into_response<T: askama::Template + ReloadableTemplate + IntoResponse>(
+    template: T,
+) -> Response {
+    dev_toolbar::record_component(T::PATH);
+    match render_from_disk(&template) {
+        Some(rendered) => (
+            [(header::CONTENT_TYPE, HeaderValue::from_static(T::MIME_TYPE))],
+            rendered,
+        )
+            .into_response(),
+        None => template.into_response(),
+    }
+}
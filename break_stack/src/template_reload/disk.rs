@@ -0,0 +1,261 @@
+//! Rendering the templates with minijinja, see `template_reload`.
+
+use super::{enabled, ReloadableTemplate, TemplateSource};
+use minijinja::value::{FunctionArgs, FunctionResult};
+use minijinja::{Environment, Error, ErrorKind, UndefinedBehavior};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+pub use minijinja::Value;
+
+impl TemplateSource {
+    /// If rendering the template from disk can show changes, i.e. it's a file, or it uses files.
+    fn uses_files(&self) -> bool {
+        match self {
+            TemplateSource::Path(_) => true,
+            TemplateSource::Compiled => false,
+            // The name of a tag can follow whitespace control (`{%- include ... %}`) and any
+            // amount of whitespace, or none
+            TemplateSource::Source { source, .. } => source.split("{%").skip(1).any(|tag| {
+                let name = tag
+                    .trim_start_matches(['-', '+', '~'])
+                    .trim_start()
+                    .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .next()
+                    .unwrap_or_default();
+                ["extends", "include", "import", "from"].contains(&name)
+            }),
+        }
+    }
+}
+
+static FUNCTIONS: Mutex<BTreeMap<String, Value>> = Mutex::new(BTreeMap::new());
+
+/// Registers a function for the templates rendered from disk, by the path the templates call it
+/// with, e.g. to render `hx-get="{{ crate::routes::items::route_paths::edit(item.id) }}"`:
+///
+/// ```ignore
+/// template_reload::add_function(
+///     "crate::routes::items::route_paths::edit",
+///     |id: i64| routes::items::route_paths::edit(id),
+/// );
+/// ```
+pub fn add_function<F, Rv, Args>(path: &str, f: F)
+where
+    F: minijinja::functions::Function<Rv, Args>,
+    Rv: FunctionResult,
+    Args: for<'a> FunctionArgs<'a>,
+{
+    FUNCTIONS
+        .lock()
+        .unwrap()
+        .insert(path.replace("::", "__"), Value::from_function(f));
+}
+
+/// Last warning printed for each component, so a failing template isn't reported on every render.
+static WARNINGS: Mutex<BTreeMap<&'static str, String>> = Mutex::new(BTreeMap::new());
+
+fn warn(name: &'static str, warning: String) {
+    let mut warnings = WARNINGS.lock().unwrap();
+    if warnings.get(name) != Some(&warning) {
+        tracing::warn!("{}", warning);
+        warnings.insert(name, warning);
+    }
+}
+
+pub(super) fn render_from_disk<T: ReloadableTemplate>(template: &T) -> Option<String> {
+    if !enabled() || !T::SOURCE.uses_files() {
+        return None;
+    }
+    let Some(context) = template.context() else {
+        warn(
+            T::NAME,
+            format!(
+                "{} is rendered with its compiled template, the types of its fields should \
+                 implement Serialize to render it from disk",
+                T::NAME
+            ),
+        );
+        return None;
+    };
+    match render_source(T::SOURCE, T::NAME, T::TEMPLATES_DIR, context) {
+        Ok(rendered) => {
+            WARNINGS.lock().unwrap().remove(T::NAME);
+            Some(rendered)
+        }
+        Err(err) => {
+            let mut warning = format!(
+                "failed to render {} from disk, using its compiled template: {}",
+                T::NAME,
+                err
+            );
+            let mut source = std::error::Error::source(&err);
+            while let Some(err) = source {
+                warning.push_str(&format!("\n  caused by: {}", err));
+                source = err.source();
+            }
+            warn(T::NAME, warning);
+            None
+        }
+    }
+}
+
+fn render_source(
+    source: TemplateSource,
+    name: &str,
+    templates_dir: &'static str,
+    context: Value,
+) -> Result<String, Error> {
+    let mut env = environment(templates_dir);
+    let template = match source {
+        TemplateSource::Path(path) => env.get_template(path)?,
+        TemplateSource::Compiled => unreachable!("compiled templates aren't rendered from disk"),
+        TemplateSource::Source { source, ext } => {
+            let name = format!("{}.{}", name, ext);
+            env.add_template_owned(name.clone(), rust_paths_to_names(source))?;
+            env.get_template(&name)?
+        }
+    };
+    template.render(context)
+}
+
+fn environment(templates_dir: &'static str) -> Environment<'static> {
+    let mut env = Environment::new();
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+    env.set_loader(move |name| {
+        if name.split(['/', '\\']).any(|part| part == "..") {
+            return Ok(None);
+        }
+        match std::fs::read_to_string(Path::new(templates_dir).join(name)) {
+            Ok(source) => Ok(Some(rust_paths_to_names(&source))),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(
+                Error::new(ErrorKind::InvalidOperation, "failed to read template").with_source(err),
+            ),
+        }
+    });
+
+    // `{% for item in items.clone() %}`, needed by askama for the iterators of the `Ref` structs
+    env.set_unknown_method_callback(|_state, value, method, args| match (method, args) {
+        ("clone", []) => Ok(value.clone()),
+        _ => Err(Error::from(ErrorKind::UnknownMethod)),
+    });
+
+    // `utils::askama::filters`
+    env.add_filter("string_or_empty", |value: Value| {
+        if value.is_none() || value.is_undefined() {
+            String::new()
+        } else {
+            value.to_string()
+        }
+    });
+    env.add_filter("some_matches", |value: Value, other: Value| {
+        !value.is_none() && value == other
+    });
+    env.add_filter(
+        "string_if_true",
+        |value: bool, s: String| {
+            if value {
+                s
+            } else {
+                String::new()
+            }
+        },
+    );
+    env.add_function("break_stack__hot_reload__hot_reload_script_tag", || {
+        crate::hot_reload::hot_reload_script_tag()
+    });
+    env.add_function("break_stack__flash__flash_toasts_tag", || {
+        crate::flash::flash_toasts_tag()
+    });
+    env.add_function("break_stack__idempotency__idempotency_key_input", || {
+        crate::idempotency::idempotency_key_input()
+    });
+    for (name, function) in FUNCTIONS.lock().unwrap().iter() {
+        env.add_global(name.clone(), function.clone());
+    }
+    env
+}
+
+/// Replaces the `::` of the Rust paths in the expressions and tags of a template, which aren't
+/// valid in minijinja expressions, by `__`, the names `add_function` registers the functions with.
+fn rust_paths_to_names(source: &str) -> String {
+    let mut result = String::with_capacity(source.len());
+    let mut rest = source;
+    while let Some(start) = rest.find('{') {
+        let (text, tag) = rest.split_at(start);
+        result.push_str(text);
+        let end_delimiter = match tag.get(..2) {
+            Some("{{") => "}}",
+            Some("{%") => "%}",
+            _ => {
+                result.push('{');
+                rest = &tag[1..];
+                continue;
+            }
+        };
+        result.push_str(&tag[..2]);
+        rest = &tag[2..];
+
+        // Copy the tag, outside of its string literals
+        let mut quote = None;
+        let mut chars = rest.char_indices().peekable();
+        let mut end = rest.len();
+        while let Some((i, c)) = chars.next() {
+            match quote {
+                Some(q) if c == q => quote = None,
+                Some(_) => {}
+                None if c == '"' || c == '\'' => quote = Some(c),
+                None if rest[i..].starts_with(end_delimiter) => {
+                    end = i;
+                    break;
+                }
+                None if rest[i..].starts_with("::") => {
+                    result.push_str("__");
+                    chars.next();
+                    continue;
+                }
+                None => {}
+            }
+            result.push(c);
+        }
+        rest = &rest[end..];
+    }
+    result.push_str(rest);
+    result
+}
+
+#[doc(hidden)]
+pub fn context<const N: usize>(fields: [(&'static str, Value); N]) -> Value {
+    Value::from_iter(fields)
+}
+
+/// Wrapper to convert the fields of components to minijinja values, with `Serialize` if the type
+/// of the field implements it, used by `#[derive(Component)]` as
+/// `(&&ContextField(&field)).to_context_value()`.
+#[doc(hidden)]
+pub struct ContextField<T>(pub T);
+
+#[doc(hidden)]
+pub trait ToContextValue {
+    fn to_context_value(&self) -> Option<Value>;
+}
+
+impl<T: Serialize> ToContextValue for &ContextField<T> {
+    fn to_context_value(&self) -> Option<Value> {
+        Some(Value::from_serialize(&self.0))
+    }
+}
+
+#[doc(hidden)]
+pub trait ToContextValueFallback {
+    fn to_context_value(&self) -> Option<Value>;
+}
+
+impl<T> ToContextValueFallback for ContextField<T> {
+    fn to_context_value(&self) -> Option<Value> {
+        None
+    }
+}
@@ -0,0 +1,566 @@
+//! Harness for testing an app end-to-end through its `Router`, without starting a server.
+//!
+//! ```ignore
+//! let app = TestApp::with_migrator(&sqlx::migrate!(), |db_pool| {
+//!     routes::router()
+//!         .merge(routes::htmx_items::router())
+//!         .with_state(AppState { db_pool })
+//! })
+//! .await;
+//!
+//! app.post("/htmx/items")
+//!     .user(UserId(1))
+//!     .htmx()
+//!     .form(&[("description", "Buy milk")])
+//!     .send()
+//!     .await
+//!     .assert_status(StatusCode::OK)
+//!     .assert_triggered("TodoItemCreated")
+//!     .assert_contains("Buy milk");
+//! ```
+//!
+//! The user of a request is put in the request extensions, where an auth middleware would
+//! put it, so the `FromRequestParts` implementation for `UserId` of the app needs to read it
+//! from there: `parts.extensions.get::<UserId>().copied()`.
+
+use crate::auth::UserId;
+use crate::htmx::{HtmxTriggers, HX_CURRENT_URL, HX_REDIRECT, HX_REQUEST, HX_TRIGGER};
+use crate::models::{DBConn, DBPool};
+use crate::queries::{self, Query, QueryLog, RecordedQueries};
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode},
+    response::IntoResponse,
+    Router,
+};
+use serde::Serialize;
+use sqlx::migrate::Migrator;
+use sqlx::sqlite::SqlitePoolOptions;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use tower::ServiceExt;
+
+mod auth;
+mod factory;
+mod html;
+mod page;
+mod snapshot;
+pub use auth::{OwnerAuthCheck, AUTH_SEED_ENV};
+pub use factory::{Factory, Fixtures};
+pub use html::{assert_html_eq, Dom, Element};
+pub use page::Page;
+pub use snapshot::{
+    assert_component_snapshot, assert_snapshot, next_snapshot_name, UPDATE_SNAPSHOTS_ENV,
+};
+
+/// Most `HX-Redirect`s followed for one request, to catch redirect loops.
+const MAX_REDIRECTS: usize = 10;
+
+/// An app with its own in-memory database, with the break_stack migrations (and optionally the
+/// migrations of the app) applied. Cookies set by responses (like the session cookie) are kept,
+/// and sent with the following requests. The statements executed on the database are recorded,
+/// and each `TestResponse` has the ones executed for its request.
+pub struct TestApp {
+    router: Router,
+    db_pool: DBPool,
+    cookies: Mutex<BTreeMap<String, String>>,
+    queries: QueryLog,
+}
+
+impl TestApp {
+    /// Creates the database, and builds the router with `router`, which gets the pool to put in
+    /// the state of the app.
+    pub async fn new(router: impl FnOnce(DBPool) -> Router) -> Self {
+        Self::build(None, router).await
+    }
+
+    /// Like `new`, but also applies the migrations of the app, e.g. `&sqlx::migrate!()`.
+    pub async fn with_migrator(migrator: &Migrator, router: impl FnOnce(DBPool) -> Router) -> Self {
+        Self::build(Some(migrator), router).await
+    }
+
+    async fn build(migrator: Option<&Migrator>, router: impl FnOnce(DBPool) -> Router) -> Self {
+        // The connections to `sqlite::memory:` share the database, which is dropped when the
+        // last one is closed, so keep them open for the lifetime of the pool
+        let queries = QueryLog::new();
+        let log = queries.clone();
+        let db_pool = SqlitePoolOptions::new()
+            .after_connect(move |conn, _| {
+                let log = log.clone();
+                Box::pin(async move {
+                    log.attach(conn).await?.keep();
+                    Ok(())
+                })
+            })
+            .min_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await
+            .expect("failed to create test database");
+
+        let mut conn: DBConn = db_pool
+            .acquire()
+            .await
+            .expect("failed to connect to test database")
+            .into();
+        if let Some(migrator) = migrator {
+            migrator
+                .run(&mut *conn)
+                .await
+                .expect("failed to run app migrations");
+        }
+        crate::migrations::run(&mut conn)
+            .await
+            .expect("failed to run break_stack migrations");
+        drop(conn);
+
+        Self {
+            router: router(db_pool.clone()),
+            db_pool,
+            cookies: Mutex::new(BTreeMap::new()),
+            queries,
+        }
+    }
+
+    pub fn db_pool(&self) -> &DBPool {
+        &self.db_pool
+    }
+
+    /// A connection to the database of the app, e.g. to insert fixtures or to check what a
+    /// request stored.
+    pub async fn conn(&self) -> DBConn {
+        self.db_pool
+            .acquire()
+            .await
+            .expect("failed to connect to test database")
+            .into()
+    }
+
+    pub fn request(&self, method: Method, uri: impl Into<String>) -> TestRequest<'_> {
+        TestRequest {
+            app: self,
+            method,
+            uri: uri.into(),
+            headers: HeaderMap::new(),
+            body: Vec::new(),
+            user_id: None,
+            is_htmx: false,
+            follow_redirects: true,
+        }
+    }
+
+    pub fn get(&self, uri: impl Into<String>) -> TestRequest<'_> {
+        self.request(Method::GET, uri)
+    }
+
+    pub fn post(&self, uri: impl Into<String>) -> TestRequest<'_> {
+        self.request(Method::POST, uri)
+    }
+
+    pub fn put(&self, uri: impl Into<String>) -> TestRequest<'_> {
+        self.request(Method::PUT, uri)
+    }
+
+    pub fn patch(&self, uri: impl Into<String>) -> TestRequest<'_> {
+        self.request(Method::PATCH, uri)
+    }
+
+    pub fn delete(&self, uri: impl Into<String>) -> TestRequest<'_> {
+        self.request(Method::DELETE, uri)
+    }
+
+    /// Drops the stored cookies, like a new browser.
+    pub fn clear_cookies(&self) {
+        self.cookies.lock().unwrap().clear();
+    }
+
+    fn cookie_header(&self) -> Option<HeaderValue> {
+        let cookies = self.cookies.lock().unwrap();
+        if cookies.is_empty() {
+            return None;
+        }
+        let cookies = cookies
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("; ");
+        HeaderValue::from_str(&cookies).ok()
+    }
+
+    fn store_cookies(&self, headers: &HeaderMap) {
+        let mut cookies = self.cookies.lock().unwrap();
+        for cookie in headers
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+        {
+            let mut attributes = cookie.split(';').map(str::trim);
+            let Some((name, value)) = attributes.next().and_then(|pair| pair.split_once('='))
+            else {
+                continue;
+            };
+            let expired = attributes.any(|attribute| attribute.eq_ignore_ascii_case("Max-Age=0"));
+            if expired || value.is_empty() {
+                cookies.remove(name);
+            } else {
+                cookies.insert(name.to_string(), value.to_string());
+            }
+        }
+    }
+}
+
+/// A request to a `TestApp`, sent with `send`.
+pub struct TestRequest<'a> {
+    app: &'a TestApp,
+    method: Method,
+    uri: String,
+    headers: HeaderMap,
+    body: Vec<u8>,
+    user_id: Option<UserId>,
+    is_htmx: bool,
+    follow_redirects: bool,
+}
+
+impl<'a> TestRequest<'a> {
+    /// Sends the request as `user_id`, see the module docs.
+    pub fn user(mut self, user_id: UserId) -> Self {
+        self.user_id = Some(user_id);
+        self
+    }
+
+    /// Sends the request like htmx does, with the `HX-Request` header.
+    pub fn htmx(mut self) -> Self {
+        self.is_htmx = true;
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        let name = HeaderName::try_from(name).expect("invalid header name");
+        let value = HeaderValue::try_from(value).expect("invalid header value");
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Sends `form` url encoded, the way a form is submitted.
+    pub fn form<T: Serialize + ?Sized>(mut self, form: &T) -> Self {
+        self.body = serde_urlencoded::to_string(form)
+            .expect("failed to encode form")
+            .into_bytes();
+        self.headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/x-www-form-urlencoded"),
+        );
+        self
+    }
+
+    pub fn body(mut self, content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self.headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::try_from(content_type).expect("invalid content type"),
+        );
+        self
+    }
+
+    /// By default the `HX-Redirect` of a response is followed, like htmx does, and the response
+    /// of the page it redirects to is returned. This returns the redirect response instead.
+    pub fn no_follow(mut self) -> Self {
+        self.follow_redirects = false;
+        self
+    }
+
+    /// Sends the request, and opens the response as a `Page` to interact with.
+    pub async fn open(self) -> Page<'a> {
+        let (app, user_id) = (self.app, self.user_id);
+        Page::new(app, user_id, self.send().await)
+    }
+
+    pub async fn send(self) -> TestResponse {
+        let mut request = self.build_request();
+        let mut redirects = 0;
+        loop {
+            let uri = request.uri().to_string();
+            let first_query = self.app.queries.len();
+            let response = self
+                .app
+                .router
+                .clone()
+                .oneshot(request)
+                .await
+                .expect("router failed");
+            let (parts, body) = response.into_parts();
+            self.app.store_cookies(&parts.headers);
+            let body = axum::body::to_bytes(body, usize::MAX)
+                .await
+                .expect("failed to read response body");
+            let response = TestResponse {
+                uri,
+                status: parts.status,
+                headers: parts.headers,
+                body: String::from_utf8_lossy(&body).into_owned(),
+                queries: self.app.queries.queries().split_off(first_query),
+            };
+
+            let Some(location) = response.hx_redirect().filter(|_| self.follow_redirects) else {
+                return response;
+            };
+            redirects += 1;
+            if redirects > MAX_REDIRECTS {
+                panic!(
+                    "more than {} redirects, last to {}",
+                    MAX_REDIRECTS, location
+                );
+            }
+            // htmx redirects by loading the page, so the new request isn't an htmx request
+            request = self.redirect_request(location);
+        }
+    }
+
+    fn build_request(&self) -> Request<Body> {
+        let mut request = Request::builder()
+            .method(self.method.clone())
+            .uri(&self.uri)
+            .body(Body::from(self.body.clone()))
+            .expect("invalid request");
+        *request.headers_mut() = self.headers.clone();
+        if self.is_htmx {
+            request
+                .headers_mut()
+                .insert(HX_REQUEST, HeaderValue::from_static("true"));
+            if let Ok(uri) = HeaderValue::try_from(&self.uri) {
+                request.headers_mut().entry(HX_CURRENT_URL).or_insert(uri);
+            }
+        }
+        self.finish_request(request)
+    }
+
+    fn redirect_request(&self, location: &str) -> Request<Body> {
+        let request = Request::get(location)
+            .body(Body::empty())
+            .expect("invalid redirect location");
+        self.finish_request(request)
+    }
+
+    fn finish_request(&self, mut request: Request<Body>) -> Request<Body> {
+        if let Some(cookies) = self.app.cookie_header() {
+            request.headers_mut().insert(header::COOKIE, cookies);
+        }
+        if let Some(user_id) = self.user_id {
+            request.extensions_mut().insert(user_id);
+        }
+        request
+    }
+}
+
+/// The response to a `TestRequest`. The `assert_*` methods panic with the response in the
+/// message, and return the response so they can be chained.
+#[derive(Debug)]
+pub struct TestResponse {
+    uri: String,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: String,
+    queries: Vec<Query>,
+}
+
+impl TestResponse {
+    /// Reads a response that didn't go through a `TestApp`, like the response of a handler that
+    /// is called directly, to use the assertions on it. Its `uri` is empty, and it only has
+    /// `queries` if the response went through `queries::log_queries`.
+    pub async fn from_response(response: impl IntoResponse) -> Self {
+        let (parts, body) = response.into_response().into_parts();
+        let queries = parts
+            .extensions
+            .get::<QueryLog>()
+            .map(QueryLog::queries)
+            .unwrap_or_default();
+        let body = axum::body::to_bytes(body, usize::MAX)
+            .await
+            .expect("failed to read response body");
+        Self {
+            uri: String::new(),
+            status: parts.status,
+            headers: parts.headers,
+            body: String::from_utf8_lossy(&body).into_owned(),
+            queries,
+        }
+    }
+
+    /// Uri of the request the response is for, which is the page redirected to if a redirect
+    /// was followed.
+    pub fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+
+    pub fn text(&self) -> &str {
+        &self.body
+    }
+
+    /// The statements executed on the database of the app while handling the request, see
+    /// `assert_max_queries!`.
+    pub fn queries(&self) -> &[Query] {
+        &self.queries
+    }
+
+    /// The body parsed as html, to query it with css selectors.
+    pub fn dom(&self) -> Dom {
+        Dom::parse(&self.body)
+    }
+
+    pub fn hx_redirect(&self) -> Option<&str> {
+        self.header(HX_REDIRECT)
+    }
+
+    /// Names of the events in the `HX-Trigger` header.
+    pub fn triggers(&self) -> Vec<String> {
+        self.trigger_events()
+            .into_iter()
+            .map(|(event, _)| event)
+            .collect()
+    }
+
+    /// Detail of the `HX-Trigger` event, `None` if the event isn't triggered, or doesn't have a
+    /// detail.
+    pub fn trigger_detail(&self, event: &str) -> Option<serde_json::Value> {
+        self.trigger_events()
+            .into_iter()
+            .find(|(name, _)| name == event)
+            .and_then(|(_, detail)| detail)
+    }
+
+    fn trigger_events(&self) -> Vec<(String, Option<serde_json::Value>)> {
+        self.header_trigger_events(HX_TRIGGER)
+    }
+
+    /// Names of the events in one of the `HX-Trigger*` headers.
+    fn header_events(&self, header: &str) -> Vec<String> {
+        self.header_trigger_events(header)
+            .into_iter()
+            .map(|(event, _)| event)
+            .collect()
+    }
+
+    fn header_trigger_events(&self, header: &str) -> Vec<(String, Option<serde_json::Value>)> {
+        let Some(value) = self.headers.get(header) else {
+            return Vec::new();
+        };
+        match HtmxTriggers::from_header(value) {
+            Ok(triggers) => triggers.0,
+            Err(err) => panic!("{:?}\n{}", err, self),
+        }
+    }
+
+    pub fn assert_status(&self, status: StatusCode) -> &Self {
+        assert_eq!(self.status, status, "unexpected status\n{}", self);
+        self
+    }
+
+    pub fn assert_ok(&self) -> &Self {
+        self.assert_status(StatusCode::OK)
+    }
+
+    pub fn assert_header(&self, name: &str, value: &str) -> &Self {
+        assert_eq!(
+            self.header(name),
+            Some(value),
+            "unexpected {} header\n{}",
+            name,
+            self
+        );
+        self
+    }
+
+    pub fn assert_triggered(&self, event: &str) -> &Self {
+        assert!(
+            self.triggers().iter().any(|name| name == event),
+            "event {} wasn't triggered\n{}",
+            event,
+            self
+        );
+        self
+    }
+
+    pub fn assert_not_triggered(&self, event: &str) -> &Self {
+        assert!(
+            !self.triggers().iter().any(|name| name == event),
+            "event {} was triggered\n{}",
+            event,
+            self
+        );
+        self
+    }
+
+    /// Checks that the body contains `html`, ignoring differences in whitespace between tags,
+    /// since those depend on how the templates are formatted.
+    pub fn assert_contains(&self, html: &str) -> &Self {
+        assert!(
+            normalize_html(&self.body).contains(&normalize_html(html)),
+            "body doesn't contain {:?}\n{}",
+            html,
+            self
+        );
+        self
+    }
+
+    pub fn assert_not_contains(&self, html: &str) -> &Self {
+        assert!(
+            !normalize_html(&self.body).contains(&normalize_html(html)),
+            "body contains {:?}\n{}",
+            html,
+            self
+        );
+        self
+    }
+
+    /// Checks that no statement was executed several times with different parameters, which
+    /// usually means an N+1 query, see `queries::repeated`.
+    pub fn assert_no_repeated_queries(&self) -> &Self {
+        let repeated = queries::repeated(&self.queries);
+        assert!(
+            repeated.is_empty(),
+            "statements executed several times with different parameters: {:?}\n{}",
+            repeated,
+            self
+        );
+        self
+    }
+}
+
+impl RecordedQueries for TestResponse {
+    fn recorded_queries(&self) -> Vec<Query> {
+        self.queries.clone()
+    }
+}
+
+impl std::fmt::Display for TestResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.uri.is_empty() {
+            writeln!(f, "response: {}", self.status)?;
+        } else {
+            writeln!(f, "response to {}: {}", self.uri, self.status)?;
+        }
+        for (name, value) in &self.headers {
+            writeln!(f, "{}: {}", name, value.to_str().unwrap_or("<binary>"))?;
+        }
+        write!(f, "\n{}", self.body)
+    }
+}
+
+/// Collapses whitespace, and drops it around tags.
+fn normalize_html(html: &str) -> String {
+    let collapsed = html.split_whitespace().collect::<Vec<_>>().join(" ");
+    collapsed.replace("> ", ">").replace(" <", "<")
+}
@@ -0,0 +1,353 @@
+use crate::auth::UserId;
+use crate::errors::AuthError;
+use crate::models::{
+    AuthModelCreate, AuthModelDelete, AuthModelList, AuthModelRead, AuthModelWrite, Model,
+    ModelCreate, ModelFactory, ModelWrite, WithOwnerModel,
+};
+use crate::testing::Factory;
+
+/// Env var to set the seed of `OwnerAuthCheck`, to reproduce a failing run with the seed it
+/// printed, e.g. `BREAK_STACK_AUTH_SEED=1234 cargo test`.
+pub const AUTH_SEED_ENV: &str = "BREAK_STACK_AUTH_SEED";
+
+type SetOwner<F> = Box<dyn Fn(&mut F, UserId) + Send + Sync>;
+
+const DEFAULT_USERS: usize = 4;
+const DEFAULT_OBJECTS: usize = 12;
+
+/// Checks the owner-based authorization of a model against random users owning random objects,
+/// created with the `ModelFactory` of the model. Each check calls the `AuthModel*` trait for
+/// every object and user, and expects `Ok` for the owner, `AuthError::Unauthorized` for other
+/// users and `AuthError::Unauthenticated` without a user. One extra user never owns anything.
+///
+/// ```ignore
+/// let mut conn = app.conn().await;
+/// let mut check = OwnerAuthCheck::<TodoItemCreate>::new(&mut conn, |item, owner| {
+///     item.owner = *owner;
+/// })
+/// .await;
+/// check.read(&mut conn).await;
+/// check.write(&mut conn, |item| TodoItemWrite { description: "Changed".to_string(), done: true }).await;
+/// check.delete(&mut conn).await;
+/// check.all_for_owner(&mut conn).await;
+/// check.finish();
+/// ```
+///
+/// The checks don't change the database, `finish` panics with every violation found and the
+/// seed of the run, which can be set with `AUTH_SEED_ENV` to reproduce it.
+pub struct OwnerAuthCheck<F: ModelFactory> {
+    seed: u64,
+    rng: SplitMix64,
+    set_owner: SetOwner<F>,
+    users: Vec<i64>,
+    objects: Vec<(i64, F::Model)>,
+    violations: Vec<String>,
+}
+
+impl<F: ModelFactory> OwnerAuthCheck<F>
+where
+    F::Model: WithOwnerModel<ID: PartialEq + std::fmt::Debug>,
+{
+    /// Creates the objects, `set_owner` sets the owner of the data built by the factory.
+    pub async fn new(
+        conn: &mut <F::Model as Model>::Conn,
+        set_owner: impl Fn(&mut F, UserId) + Send + Sync + 'static,
+    ) -> Self {
+        Self::with_size(conn, DEFAULT_USERS, DEFAULT_OBJECTS, set_owner).await
+    }
+
+    /// Same as `new`, with the number of users owning objects, and of objects.
+    pub async fn with_size(
+        conn: &mut <F::Model as Model>::Conn,
+        users: usize,
+        objects: usize,
+        set_owner: impl Fn(&mut F, UserId) + Send + Sync + 'static,
+    ) -> Self {
+        let seed = match std::env::var(AUTH_SEED_ENV) {
+            Ok(seed) => seed
+                .parse()
+                .unwrap_or_else(|_| panic!("{} should be a number", AUTH_SEED_ENV)),
+            Err(_) => std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|time| time.as_nanos() as u64)
+                .unwrap_or_default(),
+        };
+        let mut check = Self {
+            seed,
+            rng: SplitMix64(seed),
+            set_owner: Box::new(set_owner),
+            users: Vec::new(),
+            objects: Vec::new(),
+            violations: Vec::new(),
+        };
+
+        // Random ids, so rules comparing with a fixed id (or with the id of the object) fail
+        while check.users.len() < users.max(1) + 1 {
+            let user = 1 + check.rng.below(1_000_000) as i64;
+            if !check.users.contains(&user) {
+                check.users.push(user);
+            }
+        }
+        let factory = Factory::<F>::new();
+        for _ in 0..objects {
+            // The last user doesn't own anything
+            let owner = check.users[check.rng.below(users.max(1) as u64) as usize];
+            let mut data = factory.build();
+            (check.set_owner)(&mut data, UserId(owner));
+            match F::Model::create(conn, data).await {
+                Ok(model) => check.objects.push((owner, model)),
+                Err(err) => panic!(
+                    "failed to create {} (seed {}): {}",
+                    <F::Model as Model>::MODEL_NAME,
+                    seed,
+                    err
+                ),
+            }
+        }
+        check
+    }
+
+    /// The users, the last one doesn't own any object.
+    pub fn users(&self) -> Vec<UserId> {
+        self.users.iter().copied().map(UserId).collect()
+    }
+
+    /// The objects with their owner.
+    pub fn objects(&self) -> impl Iterator<Item = (UserId, &F::Model)> {
+        self.objects
+            .iter()
+            .map(|(owner, model)| (UserId(*owner), model))
+    }
+
+    pub async fn read(&mut self, conn: &mut <F::Model as Model>::Conn)
+    where
+        F::Model: AuthModelRead,
+    {
+        for (owner, id) in self.ids() {
+            for user in self.users_and_anonymous() {
+                let result = <F::Model as AuthModelRead>::can_read(conn, id, user).await;
+                self.expect("read", owner, Some(id), user, result);
+            }
+        }
+    }
+
+    /// `data` gives the data the users try to write to an object.
+    pub async fn write(
+        &mut self,
+        conn: &mut <F::Model as Model>::Conn,
+        data: impl Fn(&F::Model) -> <F::Model as ModelWrite>::Write,
+    ) where
+        F::Model: AuthModelWrite,
+    {
+        for index in 0..self.objects.len() {
+            let (owner, model) = &self.objects[index];
+            let (owner, id, data) = (*owner, F::id(model), data(model));
+            for user in self.users_and_anonymous() {
+                let result = <F::Model as AuthModelWrite>::can_write(conn, id, user, &data).await;
+                self.expect("write", owner, Some(id), user, result);
+            }
+        }
+    }
+
+    /// Users try to create objects owned by every user.
+    pub async fn create(&mut self, conn: &mut <F::Model as Model>::Conn)
+    where
+        F::Model: AuthModelCreate,
+    {
+        let factory = Factory::<F>::new();
+        for owner in self.users.clone() {
+            let mut data = factory.build();
+            (self.set_owner)(&mut data, UserId(owner));
+            for user in self.users_and_anonymous() {
+                let result = <F::Model as AuthModelCreate>::can_create(conn, user, &data).await;
+                self.expect("create", owner, None, user, result);
+            }
+        }
+    }
+
+    pub async fn delete(&mut self, conn: &mut <F::Model as Model>::Conn)
+    where
+        F::Model: AuthModelDelete,
+    {
+        for (owner, id) in self.ids() {
+            for user in self.users_and_anonymous() {
+                let result = <F::Model as AuthModelDelete>::can_delete(conn, id, user).await;
+                self.expect("delete", owner, Some(id), user, result);
+            }
+        }
+    }
+
+    /// `WithOwnerModel::all_for_owner` returns all the objects of every user, and no object of
+    /// another user.
+    pub async fn all_for_owner(&mut self, conn: &mut <F::Model as Model>::Conn) {
+        for user in self.users.clone() {
+            match F::Model::all_for_owner(conn, user).await {
+                Ok(models) => {
+                    self.expect_objects_of("all_for_owner", conn, user, models)
+                        .await
+                }
+                Err(err) => self
+                    .violations
+                    .push(format!("all_for_owner for user {} failed: {:?}", user, err)),
+            }
+        }
+    }
+
+    /// Same as `all_for_owner`, for `AuthModelList::list`, which also has to fail with
+    /// `AuthError::Unauthenticated` without a user.
+    pub async fn list(&mut self, conn: &mut <F::Model as Model>::Conn)
+    where
+        F::Model: AuthModelList,
+    {
+        for user in self.users_and_anonymous() {
+            let result = <F::Model as AuthModelList>::list(conn, user).await;
+            match (user, result) {
+                (Some(user), Ok(models)) => {
+                    self.expect_objects_of("list", conn, *user, models).await
+                }
+                (None, Err(AuthError::Unauthenticated)) => {}
+                (user, result) => self.violations.push(format!(
+                    "list for {} returned {:?}",
+                    describe_user(user),
+                    result.map(|models| models.iter().map(F::id).collect::<Vec<_>>())
+                )),
+            }
+        }
+    }
+
+    /// The violations found by the checks so far.
+    pub fn violations(&self) -> &[String] {
+        &self.violations
+    }
+
+    /// Panics with all the violations found by the checks, if any.
+    pub fn finish(self) {
+        if !self.violations.is_empty() {
+            panic!(
+                "{} authorization violations for {} (seed {}, rerun with {}={})\n\n{}",
+                self.violations.len(),
+                <F::Model as Model>::MODEL_NAME,
+                self.seed,
+                AUTH_SEED_ENV,
+                self.seed,
+                self.violations.join("\n")
+            );
+        }
+    }
+
+    fn ids(&self) -> Vec<(i64, <F::Model as Model>::ID)> {
+        self.objects
+            .iter()
+            .map(|(owner, model)| (*owner, F::id(model)))
+            .collect()
+    }
+
+    /// The users in a random order, and no user, since the order of the checks could matter
+    /// for rules that cache something.
+    fn users_and_anonymous(&mut self) -> Vec<Option<UserId>> {
+        let mut users: Vec<Option<UserId>> = self.users.iter().map(|u| Some(UserId(*u))).collect();
+        users.push(None);
+        for i in (1..users.len()).rev() {
+            users.swap(i, self.rng.below(i as u64 + 1) as usize);
+        }
+        users
+    }
+
+    fn expect(
+        &mut self,
+        action: &str,
+        owner: i64,
+        id: Option<<F::Model as Model>::ID>,
+        user: Option<UserId>,
+        result: Result<(), AuthError>,
+    ) {
+        let (passed, expected) = match user {
+            None => (
+                matches!(result, Err(AuthError::Unauthenticated)),
+                "Err(Unauthenticated)",
+            ),
+            Some(user) if *user == owner => (result.is_ok(), "Ok(())"),
+            Some(_) => (
+                matches!(result, Err(AuthError::Unauthorized)),
+                "Err(Unauthorized)",
+            ),
+        };
+        if !passed {
+            let object = match id {
+                Some(id) => format!("{} {:?}", <F::Model as Model>::MODEL_NAME, id),
+                None => format!("a {}", <F::Model as Model>::MODEL_NAME),
+            };
+            self.violations.push(format!(
+                "{} {} {} of user {}: expected {}, got {:?}",
+                describe_user(user),
+                action,
+                object,
+                owner,
+                expected,
+                result
+            ));
+        }
+    }
+
+    async fn expect_objects_of(
+        &mut self,
+        action: &str,
+        conn: &mut <F::Model as Model>::Conn,
+        user: i64,
+        models: Vec<F::Model>,
+    ) {
+        let ids: Vec<_> = models.iter().map(F::id).collect();
+        for id in &ids {
+            // Objects that weren't created by the check, like fixtures, are checked by owner
+            let owner = match self.objects.iter().find(|(_, model)| F::id(model) == *id) {
+                Some((owner, _)) => Some(*owner),
+                None => F::Model::owner(conn, *id).await.ok().flatten(),
+            };
+            if owner != Some(user) {
+                self.violations.push(format!(
+                    "{} for user {} returned {} {:?} of {}",
+                    action,
+                    user,
+                    <F::Model as Model>::MODEL_NAME,
+                    id,
+                    owner.map_or("no user".to_string(), |owner| format!("user {}", owner))
+                ));
+            }
+        }
+        for (owner, model) in &self.objects {
+            if *owner == user && !ids.contains(&F::id(model)) {
+                self.violations.push(format!(
+                    "{} for user {} didn't return {} {:?}",
+                    action,
+                    user,
+                    <F::Model as Model>::MODEL_NAME,
+                    F::id(model)
+                ));
+            }
+        }
+    }
+}
+
+fn describe_user(user: Option<UserId>) -> String {
+    match user {
+        Some(user) => format!("user {}", *user),
+        None => "no user".to_string(),
+    }
+}
+
+/// Small PRNG, so the checks are reproducible from their seed.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
@@ -0,0 +1,174 @@
+use crate::models::{Model, ModelCreate, ModelFactory};
+use serde::de::DeserializeOwned;
+use std::any::TypeId;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Last number given to each `ModelFactory` type, shared by all the tests of the binary.
+static SEQUENCES: Mutex<BTreeMap<TypeId, u64>> = Mutex::new(BTreeMap::new());
+
+fn next_sequence<F: ModelFactory>() -> u64 {
+    let mut sequences = SEQUENCES.lock().unwrap();
+    let n = sequences.entry(TypeId::of::<F>()).or_insert(0);
+    *n += 1;
+    *n
+}
+
+type Override<F> = Box<dyn Fn(&mut F, u64) + Send + Sync>;
+
+/// Creates objects of a model from the defaults of its `ModelFactory`, with overrides for the
+/// fields a test cares about:
+///
+/// ```ignore
+/// let item = Factory::<TodoItemCreate>::new()
+///     .with(|item| item.description = "Buy milk".to_string())
+///     .create(&mut conn)
+///     .await;
+/// let items = Factory::<TodoItemCreate>::new().create_many(&mut conn, 3).await;
+/// ```
+///
+/// Creating an object panics if `ModelCreate::create` fails.
+pub struct Factory<F: ModelFactory> {
+    overrides: Vec<Override<F>>,
+}
+
+impl<F: ModelFactory> Default for Factory<F> {
+    fn default() -> Self {
+        Self {
+            overrides: Vec::new(),
+        }
+    }
+}
+
+impl<F: ModelFactory> Factory<F> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Changes the data of every object created by the factory, after the defaults are built.
+    pub fn with(mut self, f: impl Fn(&mut F) + Send + Sync + 'static) -> Self {
+        self.overrides.push(Box::new(move |data, _| f(data)));
+        self
+    }
+
+    /// Like `with`, with the number of the object, see `ModelFactory::build`.
+    pub fn sequence(mut self, f: impl Fn(&mut F, u64) + Send + Sync + 'static) -> Self {
+        self.overrides.push(Box::new(f));
+        self
+    }
+
+    /// The data of the next object, without creating it, e.g. to submit it in a form.
+    pub fn build(&self) -> F {
+        let n = next_sequence::<F>();
+        let mut data = F::build(n);
+        for f in &self.overrides {
+            f(&mut data, n);
+        }
+        data
+    }
+
+    pub async fn create(&self, conn: &mut <F::Model as Model>::Conn) -> F::Model {
+        match F::Model::create(conn, self.build()).await {
+            Ok(model) => model,
+            Err(err) => panic!(
+                "factory failed to create {}: {}",
+                <F::Model as Model>::MODEL_NAME,
+                err
+            ),
+        }
+    }
+
+    pub async fn create_many(
+        &self,
+        conn: &mut <F::Model as Model>::Conn,
+        count: usize,
+    ) -> Vec<F::Model> {
+        let mut models = Vec::with_capacity(count);
+        for _ in 0..count {
+            models.push(self.create(conn).await);
+        }
+        models
+    }
+}
+
+/// Objects created from a fixture file, by name, so tests can refer to them by name instead of
+/// hardcoding the ids the database gives them:
+///
+/// ```ignore
+/// let items = Fixtures::<TodoItemCreate>::load(&mut conn, "tests/fixtures/todo_items.yaml").await;
+/// model_read_test_cases!(TodoItemModel, &mut conn, [
+///     "existing item": items.id("milk") => Some(TodoItemModel { done: false, .. }),
+/// ]);
+/// ```
+pub struct Fixtures<F: ModelFactory> {
+    rows: Vec<(String, F::Model)>,
+}
+
+impl<F: ModelFactory + DeserializeOwned> Fixtures<F> {
+    /// Creates the objects in the fixture file at `path`, relative to the crate when run by
+    /// `cargo test`, see `parse` for the format.
+    pub async fn load(conn: &mut <F::Model as Model>::Conn, path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        match std::fs::read_to_string(path) {
+            Ok(source) => Self::parse(conn, &source).await,
+            Err(err) => panic!("failed to read fixtures {}: {}", path.display(), err),
+        }
+    }
+
+    /// Creates the objects in `source`, a YAML (or JSON, which is also valid YAML) mapping from
+    /// the name of each object to its `ModelCreate::Create` data, in the order of the mapping.
+    pub async fn parse(conn: &mut <F::Model as Model>::Conn, source: &str) -> Self {
+        let mapping: serde_yaml::Mapping = match serde_yaml::from_str(source) {
+            Ok(mapping) => mapping,
+            Err(err) => panic!(
+                "fixtures of {} should be a mapping from names to objects: {}",
+                <F::Model as Model>::MODEL_NAME,
+                err
+            ),
+        };
+
+        let mut rows = Vec::with_capacity(mapping.len());
+        for (name, value) in mapping {
+            let Some(name) = name.as_str().map(str::to_string) else {
+                panic!("fixture names should be strings, got {:?}", name);
+            };
+            let data: F = match serde_yaml::from_value(value) {
+                Ok(data) => data,
+                Err(err) => panic!("invalid fixture {:?}: {}", name, err),
+            };
+            match F::Model::create(conn, data).await {
+                Ok(model) => rows.push((name, model)),
+                Err(err) => panic!("failed to create fixture {:?}: {}", name, err),
+            }
+        }
+        Self { rows }
+    }
+}
+
+impl<F: ModelFactory> Fixtures<F> {
+    /// The object created for the fixture `name`, panics if there's no such fixture.
+    pub fn get(&self, name: &str) -> &F::Model {
+        match self.rows.iter().find(|(row, _)| row == name) {
+            Some((_, model)) => model,
+            None => panic!(
+                "no fixture {:?}, the fixtures are {:?}",
+                name,
+                self.rows.iter().map(|(row, _)| row).collect::<Vec<_>>()
+            ),
+        }
+    }
+
+    pub fn id(&self, name: &str) -> <F::Model as Model>::ID {
+        F::id(self.get(name))
+    }
+
+    /// Ids of all the objects, in the order of the fixture file.
+    pub fn ids(&self) -> Vec<<F::Model as Model>::ID> {
+        self.rows.iter().map(|(_, model)| F::id(model)).collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &F::Model)> {
+        self.rows.iter().map(|(name, model)| (name.as_str(), model))
+    }
+}
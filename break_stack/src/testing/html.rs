@@ -0,0 +1,360 @@
+use crate::components::ComponentAsRef;
+use scraper::{ElementRef, Node, Selector};
+
+/// Parsed html, like the output of a component or a response body, that can be queried with
+/// css selectors. The `assert_*` methods panic with the html in the message, and return the
+/// `Dom` so they can be chained.
+///
+/// ```ignore
+/// Dom::render(&TodoItemViewComponent { item })
+///     .assert_text("li span", "Buy milk")
+///     .assert_attr("li button", "hx-delete", "/htmx/items/1")
+///     .assert_form_fields("form", &["description"]);
+/// ```
+pub struct Dom {
+    pub(super) html: scraper::Html,
+}
+
+impl Dom {
+    pub fn parse(html: &str) -> Self {
+        Self {
+            html: scraper::Html::parse_fragment(html),
+        }
+    }
+
+    /// Renders a component, passed by reference (`&component`), or a `*Ref` of a component.
+    pub fn render<C: ComponentAsRef>(component: C) -> Self
+    where
+        C::Ref: askama::Template,
+    {
+        match askama::Template::render(&ComponentAsRef::as_ref(component)) {
+            Ok(html) => Self::parse(&html),
+            Err(err) => panic!("failed to render component: {}", err),
+        }
+    }
+
+    /// All elements matching `selector`, in document order.
+    pub fn select(&self, selector: &str) -> Vec<Element<'_>> {
+        // Selecting from the root element, instead of the `Html`, keeps the document order, and
+        // skips nodes detached by `Page`
+        self.html
+            .root_element()
+            .select(&parse_selector(selector))
+            .map(Element)
+            .collect()
+    }
+
+    /// The element matching `selector`, panics if there isn't exactly one.
+    pub fn find(&self, selector: &str) -> Element<'_> {
+        let mut elements = self.select(selector);
+        if elements.len() != 1 {
+            panic!(
+                "expected one element matching {:?}, found {}\n{}",
+                selector,
+                elements.len(),
+                self
+            );
+        }
+        elements.remove(0)
+    }
+
+    pub fn count(&self, selector: &str) -> usize {
+        self.select(selector).len()
+    }
+
+    pub fn assert_count(&self, selector: &str, count: usize) -> &Self {
+        assert_eq!(
+            self.count(selector),
+            count,
+            "unexpected number of elements matching {:?}\n{}",
+            selector,
+            self
+        );
+        self
+    }
+
+    pub fn assert_exists(&self, selector: &str) -> &Self {
+        assert!(
+            self.count(selector) > 0,
+            "no element matches {:?}\n{}",
+            selector,
+            self
+        );
+        self
+    }
+
+    pub fn assert_missing(&self, selector: &str) -> &Self {
+        self.assert_count(selector, 0)
+    }
+
+    /// Checks the text of the element matching `selector`, with whitespace collapsed.
+    pub fn assert_text(&self, selector: &str, text: &str) -> &Self {
+        let element = self.find(selector);
+        let expected = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        if element.text() != expected {
+            panic!(
+                "unexpected text of {:?}\n{}\n{}",
+                selector,
+                diff(&expected, &element.text()),
+                self
+            );
+        }
+        self
+    }
+
+    pub fn assert_attr(&self, selector: &str, attr: &str, value: &str) -> &Self {
+        let element = self.find(selector);
+        assert_eq!(
+            element.attr(attr),
+            Some(value),
+            "unexpected {} attribute of {:?}\n{}",
+            attr,
+            selector,
+            self
+        );
+        self
+    }
+
+    /// The values the form matching `selector` would submit, in the order of the fields:
+    /// enabled inputs (checkboxes and radio buttons only if they're checked, and no buttons),
+    /// textareas and selects.
+    pub fn form_values(&self, selector: &str) -> Vec<(String, String)> {
+        form_values(self.find(selector).0)
+    }
+
+    /// Checks that the form matching `selector` submits exactly the fields `names`, in any order.
+    pub fn assert_form_fields(&self, selector: &str, names: &[&str]) -> &Self {
+        let mut actual: Vec<String> = self
+            .form_values(selector)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        actual.sort();
+        actual.dedup();
+        let mut expected: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        expected.sort();
+        if actual != expected {
+            panic!(
+                "unexpected fields of {:?}\n{}\n{}",
+                selector,
+                diff(&expected.join("\n"), &actual.join("\n")),
+                self
+            );
+        }
+        self
+    }
+
+    /// Checks the value the form matching `selector` submits for the field `name`.
+    pub fn assert_form_value(&self, selector: &str, name: &str, value: &str) -> &Self {
+        let values = self.form_values(selector);
+        let actual = values
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_str());
+        assert_eq!(
+            actual,
+            Some(value),
+            "unexpected value of field {} of {:?}\n{}",
+            name,
+            selector,
+            self
+        );
+        self
+    }
+}
+
+impl std::fmt::Display for Dom {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&pretty(self.html.root_element()))
+    }
+}
+
+/// An element of a `Dom`.
+#[derive(Clone, Copy)]
+pub struct Element<'a>(pub(super) ElementRef<'a>);
+
+impl<'a> Element<'a> {
+    pub fn name(&self) -> &'a str {
+        self.0.value().name()
+    }
+
+    pub fn attr(&self, attr: &str) -> Option<&'a str> {
+        self.0.attr(attr)
+    }
+
+    /// Text content of the element, with whitespace collapsed.
+    pub fn text(&self) -> String {
+        self.0
+            .text()
+            .collect::<String>()
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    pub fn html(&self) -> String {
+        self.0.html()
+    }
+
+    pub fn inner_html(&self) -> String {
+        self.0.inner_html()
+    }
+
+    /// Descendants of the element matching `selector`.
+    pub fn select(&self, selector: &str) -> Vec<Element<'a>> {
+        self.0
+            .select(&parse_selector(selector))
+            .map(Element)
+            .collect()
+    }
+}
+
+impl std::fmt::Display for Element<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&pretty(self.0))
+    }
+}
+
+impl std::fmt::Debug for Element<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.html())
+    }
+}
+
+/// Checks that two pieces of html are equivalent, ignoring whitespace between tags and the order
+/// of attributes, and panics with a line diff if they aren't.
+pub fn assert_html_eq(actual: &str, expected: &str) {
+    let actual = Dom::parse(actual).to_string();
+    let expected = Dom::parse(expected).to_string();
+    if actual != expected {
+        panic!("html doesn't match\n{}", diff(&expected, &actual));
+    }
+}
+
+/// The values `form` would submit, see `Dom::form_values`.
+pub(super) fn form_values(form: ElementRef) -> Vec<(String, String)> {
+    form.select(&parse_selector("input, textarea, select"))
+        .filter_map(|field| {
+            let name = field.attr("name")?;
+            if field.attr("disabled").is_some() {
+                return None;
+            }
+            let value = match field.value().name() {
+                "textarea" => field.text().collect(),
+                "select" => field
+                    .select(&parse_selector("option[selected]"))
+                    .chain(field.select(&parse_selector("option")))
+                    .next()
+                    .map(|option| {
+                        option
+                            .attr("value")
+                            .map(str::to_string)
+                            .unwrap_or_else(|| Element(option).text())
+                    })
+                    .unwrap_or_default(),
+                _ => match field.attr("type").unwrap_or("text") {
+                    "submit" | "button" | "reset" | "image" | "file" => return None,
+                    "checkbox" | "radio" => {
+                        field.attr("checked")?;
+                        field.attr("value").unwrap_or("on").to_string()
+                    }
+                    _ => field.attr("value").unwrap_or_default().to_string(),
+                },
+            };
+            Some((name.to_string(), value))
+        })
+        .collect()
+}
+
+pub(super) fn parse_selector(selector: &str) -> Selector {
+    match Selector::parse(selector) {
+        Ok(selector) => selector,
+        Err(err) => panic!("invalid selector {:?}: {}", selector, err),
+    }
+}
+
+/// Elements that can't have content, and don't have a closing tag.
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track",
+    "wbr",
+];
+
+/// Html of the children of `element`, with one tag or text per line, indented by depth, sorted
+/// attributes and collapsed whitespace, so equivalent html is printed the same way.
+fn pretty(element: ElementRef) -> String {
+    fn write_children(out: &mut String, element: ElementRef, depth: usize) {
+        let indent = "  ".repeat(depth);
+        for child in element.children() {
+            match child.value() {
+                Node::Element(value) => {
+                    let mut attrs: Vec<_> = value.attrs().collect();
+                    attrs.sort();
+                    out.push_str(&indent);
+                    out.push('<');
+                    out.push_str(value.name());
+                    for (name, value) in attrs {
+                        out.push_str(&format!(" {}=\"{}\"", name, value.replace('"', "&quot;")));
+                    }
+                    out.push_str(">\n");
+                    if VOID_ELEMENTS.contains(&value.name()) {
+                        continue;
+                    }
+                    if let Some(child) = ElementRef::wrap(child) {
+                        write_children(out, child, depth + 1);
+                    }
+                    out.push_str(&format!("{}</{}>\n", indent, value.name()));
+                }
+                Node::Text(text) => {
+                    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                    if !text.is_empty() {
+                        out.push_str(&format!("{}{}\n", indent, text));
+                    }
+                }
+                Node::Comment(comment) => {
+                    out.push_str(&format!("{}<!--{}-->\n", indent, &**comment));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut out = String::new();
+    write_children(&mut out, element, 0);
+    out
+}
+
+/// Line diff of `expected` and `actual`, with `-` for lines only in `expected`, and `+` for
+/// lines only in `actual`.
+pub(super) fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+
+    // Longest common subsequence of the lines, from the end
+    let mut lcs = vec![vec![0usize; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            lcs[i][j] = if expected[i] == actual[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = String::from("--- expected\n+++ actual\n");
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            out.push_str(&format!("  {}\n", expected[i]));
+            i += 1;
+            j += 1;
+        } else if i < expected.len() && (j == actual.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out.push_str(&format!("- {}\n", expected[i]));
+            i += 1;
+        } else {
+            out.push_str(&format!("+ {}\n", actual[j]));
+            j += 1;
+        }
+    }
+    out
+}
@@ -0,0 +1,551 @@
+use super::html::{form_values, parse_selector};
+use super::{Dom, TestApp, TestResponse};
+use crate::auth::UserId;
+use crate::htmx::{
+    HX_CURRENT_URL, HX_PUSH_URL, HX_REFRESH, HX_RESWAP, HX_RETARGET, HX_TARGET, HX_TRIGGER,
+    HX_TRIGGER_AFTER_SETTLE, HX_TRIGGER_AFTER_SWAP, HX_TRIGGER_NAME,
+};
+use axum::http::Method;
+use ego_tree::{NodeId, NodeRef, Tree};
+use html5ever::{LocalName, Namespace, QualName};
+use scraper::{node::Text, ElementRef, Node};
+use std::collections::{HashSet, VecDeque};
+
+/// Most requests one interaction can cause, through the events triggered by the responses, to
+/// catch listeners that trigger each other.
+const MAX_REQUESTS: usize = 20;
+
+const VERBS: &[(&str, Method)] = &[
+    ("hx-get", Method::GET),
+    ("hx-post", Method::POST),
+    ("hx-put", Method::PUT),
+    ("hx-patch", Method::PATCH),
+    ("hx-delete", Method::DELETE),
+];
+
+/// A page of a `TestApp`, that can be interacted with the way htmx would in a browser, opened
+/// with `TestRequest::open`.
+///
+/// Clicking or submitting an element with `hx-get`/`hx-post`/`hx-put`/`hx-patch`/`hx-delete`
+/// sends its request, with the values of its form, and swaps the response into the page using
+/// `hx-target` and `hx-swap` (or the `HX-Retarget` and `HX-Reswap` headers), including the out
+/// of band swaps. Events in the `HX-Trigger*` headers of the response send the requests of the
+/// elements listening for them with `hx-trigger`, either with `from:body`, or because the
+/// event bubbles up to them from the element that sent the request. `HX-Redirect` and
+/// `HX-Refresh` load the page again.
+///
+/// Not supported are other triggers (like `load` or `every`), `hx-select`, `hx-include`,
+/// and swap modifiers, and no javascript is run.
+///
+/// ```ignore
+/// let mut page = app.get("/").user(UserId(1)).open().await;
+/// page.fill("form [name=description]", "Buy milk");
+/// page.submit("form").await.assert_triggered("TodoItemCreated");
+/// page.dom().assert_text("#todo-items li:last-child span", "Buy milk");
+/// ```
+pub struct Page<'a> {
+    app: &'a TestApp,
+    user_id: Option<UserId>,
+    url: String,
+    dom: Dom,
+}
+
+impl<'a> Page<'a> {
+    pub(super) fn new(app: &'a TestApp, user_id: Option<UserId>, response: TestResponse) -> Self {
+        Self {
+            app,
+            user_id,
+            url: response.uri().to_string(),
+            dom: response.dom(),
+        }
+    }
+
+    /// Url of the page, updated by `HX-Push-Url`.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn dom(&self) -> &Dom {
+        &self.dom
+    }
+
+    /// Loads `url` as a new page, like following a link.
+    pub async fn visit(&mut self, url: &str) -> TestResponse {
+        let mut request = self.app.get(url);
+        request.user_id = self.user_id;
+        let response = request.send().await;
+        self.url = response.uri().to_string();
+        self.dom = response.dom();
+        response
+    }
+
+    /// Clicks the element matching `selector`, which needs to have one of the `hx-*` request
+    /// attributes, be a submit button of a form that has one, or be a link. Returns the
+    /// response to the request of the element.
+    pub async fn click(&mut self, selector: &str) -> TestResponse {
+        let element = self.dom.find(selector).0;
+        if verb(element).is_some() {
+            return self.interact(element.id(), Vec::new()).await;
+        }
+
+        let is_submit = match element.value().name() {
+            "button" => element.attr("type").unwrap_or("submit") == "submit",
+            "input" => element.attr("type") == Some("submit"),
+            _ => false,
+        };
+        if let Some(form) = closest(element, "form").filter(|_| is_submit) {
+            let value = element.attr("name").map(|name| {
+                (
+                    name.to_string(),
+                    element.attr("value").unwrap_or_default().to_string(),
+                )
+            });
+            return self.interact(form.id(), value.into_iter().collect()).await;
+        }
+        if let Some(href) = element
+            .attr("href")
+            .filter(|_| element.value().name() == "a")
+        {
+            let href = href.to_string();
+            return self.visit(&href).await;
+        }
+        panic!(
+            "element matching {:?} doesn't send a request\n{}",
+            selector, self.dom
+        );
+    }
+
+    /// Submits the form matching `selector`, with the values currently in its fields.
+    pub async fn submit(&mut self, selector: &str) -> TestResponse {
+        let form = self.dom.find(selector).0;
+        if form.value().name() != "form" || verb(form).is_none() {
+            panic!(
+                "element matching {:?} isn't a form with a request attribute\n{}",
+                selector, self.dom
+            );
+        }
+        self.interact(form.id(), Vec::new()).await
+    }
+
+    /// Sets the value of the input, textarea or select matching `selector`.
+    pub fn fill(&mut self, selector: &str, value: &str) -> &mut Self {
+        let field = self.dom.find(selector).0;
+        let id = field.id();
+        match field.value().name() {
+            "textarea" => {
+                let children: Vec<NodeId> = field.children().map(|child| child.id()).collect();
+                let tree = &mut self.dom.html.tree;
+                for child in children {
+                    tree.get_mut(child).unwrap().detach();
+                }
+                tree.get_mut(id)
+                    .unwrap()
+                    .append(Node::Text(Text { text: value.into() }));
+            }
+            "select" => {
+                let options: Vec<(NodeId, bool)> = field
+                    .select(&parse_selector("option"))
+                    .map(|option| {
+                        let option_value = option
+                            .attr("value")
+                            .map(str::to_string)
+                            .unwrap_or_else(|| option.text().collect());
+                        (option.id(), option_value == value)
+                    })
+                    .collect();
+                if !options.iter().any(|(_, selected)| *selected) {
+                    panic!(
+                        "select matching {:?} doesn't have an option {:?}",
+                        selector, value
+                    );
+                }
+                for (option, selected) in options {
+                    self.set_attr(option, "selected", selected.then_some(""));
+                }
+            }
+            _ => self.set_attr(id, "value", Some(value)),
+        }
+        self
+    }
+
+    /// Checks or unchecks the checkbox or radio button matching `selector`. Checking a radio
+    /// button unchecks the others with the same name in its form.
+    pub fn check(&mut self, selector: &str, checked: bool) -> &mut Self {
+        let field = self.dom.find(selector).0;
+        let id = field.id();
+        let mut unchecked = Vec::new();
+        if let Some(name) = field
+            .attr("name")
+            .filter(|_| checked && field.attr("type") == Some("radio"))
+        {
+            let scope = closest(field, "form").unwrap_or(self.dom.html.root_element());
+            unchecked = scope
+                .select(&parse_selector("input[type=radio]"))
+                .filter(|radio| radio.attr("name") == Some(name) && radio.id() != id)
+                .map(|radio| radio.id())
+                .collect();
+        }
+        for radio in unchecked {
+            self.set_attr(radio, "checked", None);
+        }
+        self.set_attr(id, "checked", checked.then_some(""));
+        self
+    }
+
+    fn set_attr(&mut self, id: NodeId, name: &str, value: Option<&str>) {
+        let mut node = self.dom.html.tree.get_mut(id).unwrap();
+        let Node::Element(element) = node.value() else {
+            return;
+        };
+        let name = QualName::new(None, Namespace::from(""), LocalName::from(name));
+        match value {
+            Some(value) => {
+                element.attrs.insert(name, value.into());
+            }
+            None => {
+                element.attrs.remove(&name);
+            }
+        }
+    }
+
+    fn element(&self, id: NodeId) -> Option<ElementRef<'_>> {
+        let node = self.dom.html.tree.get(id)?;
+        // Elements that were swapped out are still in the tree, but not attached to the root
+        if node.ancestors().last()?.id() != self.dom.html.tree.root().id() {
+            return None;
+        }
+        ElementRef::wrap(node)
+    }
+
+    /// Sends the request of the element `id`, and of the listeners of the events triggered by
+    /// the responses, returning the response to the first request.
+    async fn interact(&mut self, id: NodeId, extra_values: Vec<(String, String)>) -> TestResponse {
+        let mut queue = VecDeque::from([(id, extra_values)]);
+        let mut first = None;
+        let mut requests = 0;
+        while let Some((id, extra_values)) = queue.pop_front() {
+            // The listener might have been swapped out by an earlier response
+            let Some(element) = self.element(id) else {
+                continue;
+            };
+            requests += 1;
+            if requests > MAX_REQUESTS {
+                panic!(
+                    "more than {} requests, events might be triggering each other",
+                    MAX_REQUESTS
+                );
+            }
+
+            let target = self.target(element);
+            let swap = closest_attr(element, "hx-swap")
+                .map(|(_, swap)| {
+                    swap.split_whitespace()
+                        .next()
+                        .unwrap_or("innerHTML")
+                        .to_string()
+                })
+                .unwrap_or_else(|| "innerHTML".to_string());
+            // Events bubble up from the element that sent the request
+            let bubbles_to: HashSet<NodeId> = element
+                .ancestors()
+                .map(|node| node.id())
+                .chain([id])
+                .collect();
+
+            let response = self.send(element, target, extra_values).await;
+            let events = self.apply(&response, target, &swap).await;
+            for event in events {
+                for listener in self.listeners(&event, &bubbles_to) {
+                    queue.push_back((listener, Vec::new()));
+                }
+            }
+            first.get_or_insert(response);
+        }
+        first.expect("element wasn't found")
+    }
+
+    async fn send(
+        &self,
+        element: ElementRef<'_>,
+        target: Option<NodeId>,
+        extra_values: Vec<(String, String)>,
+    ) -> TestResponse {
+        let (method, url) = verb(element).expect("element doesn't send a request");
+
+        let mut values = if element.value().name() == "form" {
+            form_values(element)
+        } else if method != Method::GET {
+            closest(element, "form")
+                .map(form_values)
+                .unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        if element.value().name() != "form" {
+            if let Some(name) = element.attr("name") {
+                values.push((
+                    name.to_string(),
+                    element.attr("value").unwrap_or_default().to_string(),
+                ));
+            }
+        }
+        values.extend(extra_values);
+        if let Some(vals) = element.attr("hx-vals") {
+            let vals: serde_json::Map<String, serde_json::Value> =
+                serde_json::from_str(vals).expect("hx-vals isn't a json object");
+            values.extend(vals.into_iter().map(|(name, value)| match value {
+                serde_json::Value::String(value) => (name, value),
+                value => (name, value.to_string()),
+            }));
+        }
+
+        let url = if method == Method::GET && !values.is_empty() {
+            let query = serde_urlencoded::to_string(&values).expect("failed to encode values");
+            let separator = if url.contains('?') { '&' } else { '?' };
+            format!("{}{}{}", url, separator, query)
+        } else {
+            url.to_string()
+        };
+        let mut request = self
+            .app
+            .request(method.clone(), url)
+            .htmx()
+            .no_follow()
+            .header(HX_CURRENT_URL, &self.url);
+        request.user_id = self.user_id;
+        if method != Method::GET {
+            request = request.form(&values);
+        }
+        if let Some(id) = element.value().id() {
+            request = request.header(HX_TRIGGER, id);
+        }
+        if let Some(name) = element.attr("name") {
+            request = request.header(HX_TRIGGER_NAME, name);
+        }
+        if let Some(id) = target
+            .and_then(|target| self.element(target))
+            .and_then(|target| target.value().id())
+        {
+            request = request.header(HX_TARGET, id);
+        }
+        request.send().await
+    }
+
+    /// The element the response to the request of `element` is swapped into.
+    fn target(&self, element: ElementRef) -> Option<NodeId> {
+        let Some((holder, target)) = closest_attr(element, "hx-target") else {
+            return Some(element.id());
+        };
+        let found = if target == "this" {
+            Some(holder)
+        } else if let Some(selector) = target.strip_prefix("closest ") {
+            closest(element, selector)
+        } else if let Some(selector) = target.strip_prefix("find ") {
+            element.select(&parse_selector(selector)).next()
+        } else {
+            self.select_first(target)
+        };
+        found.map(|found| found.id())
+    }
+
+    fn select_first(&self, selector: &str) -> Option<ElementRef<'_>> {
+        self.dom
+            .html
+            .root_element()
+            .select(&parse_selector(selector))
+            .next()
+    }
+
+    /// Applies the response to the page, and returns the events it triggered.
+    async fn apply(
+        &mut self,
+        response: &TestResponse,
+        target: Option<NodeId>,
+        swap: &str,
+    ) -> Vec<String> {
+        if response.hx_redirect().is_some() {
+            let url = response.hx_redirect().unwrap().to_string();
+            self.visit(&url).await;
+            return Vec::new();
+        }
+        if response.header(HX_REFRESH) == Some("true") {
+            let url = self.url.clone();
+            self.visit(&url).await;
+            return Vec::new();
+        }
+        // htmx doesn't swap error responses by default
+        if !response.status().is_success() {
+            return Vec::new();
+        }
+
+        let target = match response.header(HX_RETARGET) {
+            Some(selector) => self.select_first(selector).map(|target| target.id()),
+            None => target,
+        };
+        let swap = response
+            .header(HX_RESWAP)
+            .and_then(|swap| swap.split_whitespace().next())
+            .unwrap_or(swap)
+            .to_string();
+
+        let fragment = scraper::Html::parse_fragment(response.text());
+        let mut content = Vec::new();
+        for node in fragment.root_element().children() {
+            let oob = node
+                .value()
+                .as_element()
+                .and_then(|element| element.attr("hx-swap-oob"));
+            let Some(oob) = oob else {
+                content.push(node);
+                continue;
+            };
+            let (oob_swap, selector) = match oob.split_once(':') {
+                Some((oob_swap, selector)) => (oob_swap.to_string(), selector.to_string()),
+                None => {
+                    let oob_swap = if oob == "true" { "outerHTML" } else { oob };
+                    // Without an id there's nothing to swap, htmx ignores these too
+                    let Some(id) = node.value().as_element().and_then(|element| element.id())
+                    else {
+                        continue;
+                    };
+                    (oob_swap.to_string(), format!("#{}", id))
+                }
+            };
+            let oob_target = self.select_first(&selector).map(|target| target.id());
+            if let Some(oob_target) = oob_target {
+                // For outerHTML the element itself is swapped in, otherwise its content
+                let nodes = if oob_swap == "outerHTML" {
+                    vec![node]
+                } else {
+                    node.children().collect()
+                };
+                swap_nodes(&mut self.dom.html.tree, oob_target, &oob_swap, &nodes);
+            }
+        }
+        if response.status() != axum::http::StatusCode::NO_CONTENT {
+            if let Some(target) = target.filter(|target| self.element(*target).is_some()) {
+                swap_nodes(&mut self.dom.html.tree, target, &swap, &content);
+            }
+        }
+
+        if let Some(url) = response.header(HX_PUSH_URL).filter(|url| *url != "false") {
+            self.url = url.to_string();
+        }
+
+        [HX_TRIGGER, HX_TRIGGER_AFTER_SWAP, HX_TRIGGER_AFTER_SETTLE]
+            .into_iter()
+            .flat_map(|header| response.header_events(header))
+            .collect()
+    }
+
+    /// Elements with `hx-trigger` listening for `event`.
+    fn listeners(&self, event: &str, bubbles_to: &HashSet<NodeId>) -> Vec<NodeId> {
+        self.dom
+            .html
+            .root_element()
+            .select(&parse_selector("[hx-trigger]"))
+            .filter(|element| verb(*element).is_some())
+            .filter(|element| {
+                element
+                    .attr("hx-trigger")
+                    .unwrap_or_default()
+                    .split(',')
+                    .any(|spec| {
+                        let mut parts = spec.split_whitespace();
+                        let name = parts.next().unwrap_or_default();
+                        let name = name.split('[').next().unwrap_or_default();
+                        let from_body = parts.any(|part| {
+                            matches!(part, "from:body" | "from:document" | "from:window")
+                        });
+                        name == event && (from_body || bubbles_to.contains(&element.id()))
+                    })
+            })
+            .map(|element| element.id())
+            .collect()
+    }
+}
+
+fn verb<'a>(element: ElementRef<'a>) -> Option<(Method, &'a str)> {
+    VERBS
+        .iter()
+        .find_map(|(attr, method)| Some((method.clone(), element.attr(attr)?)))
+}
+
+/// The element or its closest ancestor matching `selector`.
+fn closest<'a>(element: ElementRef<'a>, selector: &str) -> Option<ElementRef<'a>> {
+    let selector = parse_selector(selector);
+    std::iter::once(element)
+        .chain(element.ancestors().filter_map(ElementRef::wrap))
+        .find(|element| selector.matches(element))
+}
+
+/// The element or its closest ancestor with `attr`, since htmx attributes like `hx-target` are
+/// inherited, with the element the attribute is on.
+fn closest_attr<'a>(element: ElementRef<'a>, attr: &str) -> Option<(ElementRef<'a>, &'a str)> {
+    std::iter::once(element)
+        .chain(element.ancestors().filter_map(ElementRef::wrap))
+        .find_map(|element| Some((element, element.attr(attr)?)))
+}
+
+#[derive(Clone, Copy)]
+enum Position {
+    Append(NodeId),
+    Before(NodeId),
+}
+
+/// Copies `node` and its descendants from another tree into `tree`.
+fn insert_copy(tree: &mut Tree<Node>, node: NodeRef<Node>, position: Position) {
+    let id = match position {
+        Position::Append(parent) => tree
+            .get_mut(parent)
+            .unwrap()
+            .append(node.value().clone())
+            .id(),
+        Position::Before(sibling) => tree
+            .get_mut(sibling)
+            .unwrap()
+            .insert_before(node.value().clone())
+            .id(),
+    };
+    for child in node.children() {
+        insert_copy(tree, child, Position::Append(id));
+    }
+}
+
+/// Swaps `nodes` into `target` the way htmx does for the `hx-swap` value `swap`.
+fn swap_nodes(tree: &mut Tree<Node>, target: NodeId, swap: &str, nodes: &[NodeRef<Node>]) {
+    let node = tree.get(target).unwrap();
+    let first_child = node.first_child().map(|child| child.id());
+    let next_sibling = node.next_sibling().map(|sibling| sibling.id());
+    let parent = node.parent().map(|parent| parent.id());
+    let children: Vec<NodeId> = node.children().map(|child| child.id()).collect();
+
+    let position = match swap {
+        "innerHTML" => {
+            for child in children {
+                tree.get_mut(child).unwrap().detach();
+            }
+            Position::Append(target)
+        }
+        "outerHTML" | "beforebegin" => Position::Before(target),
+        "afterbegin" => first_child.map_or(Position::Append(target), Position::Before),
+        "beforeend" => Position::Append(target),
+        "afterend" => match (next_sibling, parent) {
+            (Some(sibling), _) => Position::Before(sibling),
+            (None, Some(parent)) => Position::Append(parent),
+            (None, None) => return,
+        },
+        "delete" => {
+            tree.get_mut(target).unwrap().detach();
+            return;
+        }
+        "none" => return,
+        swap => panic!("unsupported hx-swap {:?}", swap),
+    };
+    for node in nodes {
+        insert_copy(tree, *node, position);
+    }
+    if swap == "outerHTML" {
+        tree.get_mut(target).unwrap().detach();
+    }
+}
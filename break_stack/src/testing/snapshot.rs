@@ -0,0 +1,98 @@
+use super::html::diff;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Env var that makes the snapshot assertions overwrite the stored snapshots that don't match,
+/// instead of failing, e.g. `BREAK_STACK_UPDATE_SNAPSHOTS=1 cargo test`.
+pub const UPDATE_SNAPSHOTS_ENV: &str = "BREAK_STACK_UPDATE_SNAPSHOTS";
+
+thread_local! {
+    /// Number of snapshots taken by the test running on this thread, per test.
+    static SNAPSHOT_COUNTS: RefCell<HashMap<String, usize>> = RefCell::new(HashMap::new());
+}
+
+/// Name of the next snapshot of the current test: the module and name of the test, with a
+/// number appended for every snapshot after the first. The test harness runs each test on a
+/// thread named after the test, which is where the name comes from.
+#[doc(hidden)]
+pub fn next_snapshot_name(module_path: &str) -> String {
+    let thread = std::thread::current();
+    let test = thread.name().unwrap_or("snapshot");
+    let name = format!("{}::{}", module_path, test).replace("::", "__");
+    let count = SNAPSHOT_COUNTS.with(|counts| {
+        let mut counts = counts.borrow_mut();
+        let count = counts.entry(name.clone()).or_insert(0);
+        *count += 1;
+        *count
+    });
+    if count == 1 {
+        name
+    } else {
+        format!("{}-{}", name, count)
+    }
+}
+
+/// Compares `html` with the snapshot `name` stored in `dir`. Snapshots that don't match fail with
+/// a diff, unless `UPDATE_SNAPSHOTS_ENV` is set, in which case they're overwritten. Missing
+/// snapshots are written and pass, except on CI (when `CI` is set), where they fail unless
+/// `UPDATE_SNAPSHOTS_ENV` is set, so snapshots that weren't committed don't pass unchecked. Use
+/// `assert_component_snapshot!` for components.
+pub fn assert_snapshot(dir: impl AsRef<Path>, name: &str, html: &str) {
+    let path = dir.as_ref().join(format!("{}.html", name));
+    let update = std::env::var_os(UPDATE_SNAPSHOTS_ENV).is_some_and(|value| value != "0");
+    let ci = std::env::var_os("CI").is_some();
+
+    match std::fs::read_to_string(&path) {
+        Ok(stored) if stored == html => {}
+        Ok(stored) if !update => panic!(
+            "snapshot {} doesn't match, run with {}=1 to update it\n{}",
+            path.display(),
+            UPDATE_SNAPSHOTS_ENV,
+            diff(&stored, html)
+        ),
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            panic!("failed to read snapshot {}: {}", path.display(), err)
+        }
+        Err(_) if ci && !update => panic!(
+            "snapshot {} is missing, run with {}=1 to write it\n{}",
+            path.display(),
+            UPDATE_SNAPSHOTS_ENV,
+            html
+        ),
+        _ => {
+            if let Err(err) =
+                std::fs::create_dir_all(dir.as_ref()).and_then(|()| std::fs::write(&path, html))
+            {
+                panic!("failed to write snapshot {}: {}", path.display(), err);
+            }
+        }
+    }
+}
+
+/// Renders a component (by reference, or a `*Ref` of it), and compares it with the snapshot
+/// stored in `tests/snapshots` of the crate, see `assert_snapshot`. The html is normalized the
+/// same way as by `Dom`, so changes in whitespace between tags don't break the snapshots. The
+/// snapshot is named after the test, or can be named explicitly:
+///
+/// ```ignore
+/// assert_component_snapshot!(TodoItemViewComponentRef::new(&item));
+/// assert_component_snapshot!("todo_item_done", TodoItemViewComponentRef::new(&done_item));
+/// ```
+#[macro_export]
+macro_rules! assert_component_snapshot {
+    ($name:expr, $component:expr) => {
+        $crate::testing::assert_snapshot(
+            ::std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots"),
+            $name,
+            &$crate::testing::Dom::render($component).to_string(),
+        )
+    };
+    ($component:expr) => {
+        $crate::assert_component_snapshot!(
+            &$crate::testing::next_snapshot_name(module_path!()),
+            $component
+        )
+    };
+}
+pub use assert_component_snapshot;
@@ -0,0 +1,166 @@
+use crate::auth::UserId;
+use crate::components::*;
+use crate::controllers::ModelController;
+use crate::errors::*;
+use crate::models::{Connection, DBConn, DBPool, Model, ModelDelete, ModelSoftDelete};
+use crate::utils::unix_timestamp;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Records that the hidden object `id` of `M` should be deleted permanently once `window` has
+/// passed. Deleting an object that is already pending restarts its window.
+pub(crate) async fn schedule_purge<M: Model<ID: Display>>(
+    conn: &mut DBConn,
+    id: M::ID,
+    user_id: Option<UserId>,
+    window: Duration,
+) -> Result<(), ModelError> {
+    let now = unix_timestamp();
+    sqlx::query(
+        "INSERT INTO break_stack_pending_deletes (model_name, model_id, user_id, purge_at, created_at) VALUES (?1, ?2, ?3, ?4, ?5)
+        ON CONFLICT (model_name, model_id) DO UPDATE SET user_id = ?3, purge_at = ?4, created_at = ?5",
+    )
+    .bind(M::MODEL_NAME)
+    .bind(id.to_string())
+    .bind(user_id.map(|user_id| *user_id))
+    .bind(now + window.as_secs() as i64)
+    .bind(now)
+    .execute(&mut **conn)
+    .await?;
+    Ok(())
+}
+
+/// Checks if the permanent delete of `id` is pending, and its undo window hasn't passed yet.
+pub(crate) async fn is_purge_pending<M: Model<ID: Display>>(
+    conn: &mut DBConn,
+    id: M::ID,
+) -> Result<bool, ModelError> {
+    Ok(sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM break_stack_pending_deletes WHERE model_name = ? AND model_id = ? AND purge_at > ?)",
+    )
+    .bind(M::MODEL_NAME)
+    .bind(id.to_string())
+    .bind(unix_timestamp())
+    .fetch_one(&mut **conn)
+    .await?)
+}
+
+/// Cancels the permanent delete of `id`.
+pub(crate) async fn cancel_purge<M: Model<ID: Display>>(
+    conn: &mut DBConn,
+    id: M::ID,
+) -> Result<(), ModelError> {
+    sqlx::query("DELETE FROM break_stack_pending_deletes WHERE model_name = ? AND model_id = ?")
+        .bind(M::MODEL_NAME)
+        .bind(id.to_string())
+        .execute(&mut **conn)
+        .await?;
+    Ok(())
+}
+
+/// Permanently deletes the objects hidden by `model_controller_delete_undoable` whose undo
+/// window has passed, calling `H::after_delete` for each of them. Returns the number of
+/// deleted objects. This should run regularly in the background, with `spawn_purge_loop`, or
+/// e.g. as a scheduled task:
+///
+/// ```ignore
+/// Scheduler::new(db_pool.clone())
+///     .task("purge_todo_items", "*/10 * * * * *", |mut conn| async move {
+///         undo::purge_expired::<TodoItemController>(&mut conn).await?;
+///         Ok(())
+///     })
+/// ```
+pub async fn purge_expired<H: ModelController<Model: ModelSoftDelete<ID: FromStr>>>(
+    conn: &mut <H::Model as Model>::Conn,
+) -> AppResult<usize> {
+    let expired: Vec<(String, Option<i64>)> = sqlx::query_as(
+        "SELECT model_id, user_id FROM break_stack_pending_deletes WHERE model_name = ? AND purge_at <= ? ORDER BY purge_at",
+    )
+    .bind(<H::Model as Model>::MODEL_NAME)
+    .bind(unix_timestamp())
+    .fetch_all(&mut **conn.db()?)
+    .await?;
+
+    let mut count = 0;
+    for (model_id, user_id) in expired {
+        // Not matched directly, since the parse error isn't necessarily `Send`
+        let id: Option<<H::Model as Model>::ID> = model_id.parse().ok();
+        if let Some(id) = id {
+            match <H::Model as ModelDelete>::delete(conn, id).await {
+                Ok(item) => {
+                    H::after_delete(conn, user_id.map(UserId), &item).await?;
+                    count += 1;
+                }
+                // Already deleted, e.g. by another instance of the app
+                Err(ModelError::NotFound) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        sqlx::query(
+            "DELETE FROM break_stack_pending_deletes WHERE model_name = ? AND model_id = ?",
+        )
+        .bind(<H::Model as Model>::MODEL_NAME)
+        .bind(&model_id)
+        .execute(&mut **conn.db()?)
+        .await?;
+    }
+    Ok(count)
+}
+
+/// Runs `purge_expired` for the objects of `H` forever, every `interval`. Errors are logged with
+/// `tracing`, and the next run tries again.
+///
+/// ```ignore
+/// undo::spawn_purge_loop::<TodoItemController>(db_pool.clone(), Duration::from_secs(10));
+/// ```
+pub fn spawn_purge_loop<H>(pool: DBPool, interval: Duration) -> tokio::task::JoinHandle<()>
+where
+    H: ModelController<Model: ModelSoftDelete<ID: FromStr, Conn = DBConn>> + 'static,
+{
+    tokio::spawn(async move {
+        loop {
+            let result = match pool.acquire().await {
+                Ok(conn) => purge_expired::<H>(&mut conn.into()).await.map(|_| ()),
+                Err(err) => Err(err.into()),
+            };
+            if let Err(err) = result {
+                tracing::error!(
+                    "failed to purge deleted {}: {:?}",
+                    <H::Model as Model>::MODEL_NAME,
+                    err
+                );
+            }
+            tokio::time::sleep(interval).await;
+        }
+    })
+}
+
+/// Component returned by `model_controller_delete_undoable` in place of the deleted object, with
+/// a message and an "Undo" button posting to `restore_url`. The restored object replaces the
+/// toast, and the toast removes itself when the undo window has passed. It can be styled with
+/// the `break-stack-undo` class.
+#[derive(Debug, Clone, Component)]
+#[template(
+    source = r#"<div class="break-stack-undo" role="status" hx-on::load="setTimeout(() => this.remove(), {{ window_ms }})"><span>{{ message }}</span> <button type="button" hx-post="{{ restore_url }}" hx-target="closest .break-stack-undo" hx-swap="outerHTML">Undo</button></div>"#,
+    ext = "html"
+)]
+pub struct UndoToast {
+    pub message: String,
+    pub restore_url: String,
+    pub window_ms: u64,
+}
+
+impl UndoToast {
+    pub fn new(
+        message: impl Into<String>,
+        restore_url: impl Into<String>,
+        window: Duration,
+    ) -> Self {
+        Self {
+            message: message.into(),
+            restore_url: restore_url.into(),
+            window_ms: window.as_millis() as u64,
+        }
+    }
+}
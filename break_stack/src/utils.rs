@@ -1,5 +1,12 @@
 pub use break_stack_macros::bundle_files;
 
+pub(crate) fn unix_timestamp() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
 pub mod serde {
     use serde::de::IntoDeserializer;
     use serde::Deserialize;
@@ -0,0 +1,213 @@
+use crate::auth::UserId;
+use crate::components::Component;
+use crate::controllers::ModelController;
+use crate::errors::*;
+use crate::htmx::HtmxRequest;
+use crate::models::{AuthModelCreate, DBConn, ModelCreate};
+use crate::session::SessionId;
+use crate::utils::unix_timestamp;
+use axum::response::Response;
+use serde::{de::DeserializeOwned, Serialize};
+use std::time::Duration;
+
+/// Form field that makes `wizard_controller_submit` go back to the previous step, without
+/// validating the submitted form, e.g. `<button name="wizard_back" value="1">Back</button>`.
+pub const WIZARD_BACK_FIELD: &str = "wizard_back";
+/// Form field with the index of the step the form belongs to, `wizard_controller_submit` rejects
+/// forms of other steps, e.g. when the form of a step is submitted again from another tab.
+pub const WIZARD_STEP_FIELD: &str = "wizard_step";
+
+/// Passed to the form component of the current step.
+#[derive(Debug, Clone, Default)]
+pub struct WizardStepContext {
+    /// Index of the step, starting at 0.
+    pub step: usize,
+    pub steps: usize,
+    /// Validation errors from the last submission of the step.
+    pub errors: Vec<String>,
+}
+
+impl WizardStepContext {
+    pub fn is_first(&self) -> bool {
+        self.step == 0
+    }
+
+    pub fn is_last(&self) -> bool {
+        self.step + 1 >= self.steps
+    }
+
+    /// Hidden input with the index of the step, which needs to be included in the form.
+    pub fn step_input(&self) -> String {
+        format!(
+            r#"<input type="hidden" name="{}" value="{}" />"#,
+            WIZARD_STEP_FIELD, self.step
+        )
+    }
+}
+
+/// A step of a `Wizard`, the form of the step is deserialized into this type, and then merged
+/// into the draft `D` with `apply`.
+pub trait WizardStep<D>: DeserializeOwned + Send + Sized + 'static {
+    type Component: Component;
+
+    /// The form of the step. The draft contains the data of the previous steps, and of this
+    /// step if the user went back to it, so it can be used to prefill the form.
+    fn component(draft: &D, context: WizardStepContext) -> Self::Component;
+
+    /// Checks the submitted form, the errors are shown with the form of the step again.
+    fn validate(&self, _draft: &D) -> Result<(), Vec<String>> {
+        Ok(())
+    }
+
+    fn apply(self, draft: &mut D);
+}
+
+/// A registered step, created with `wizard::step::<_, S>()`.
+pub struct WizardStepHandler<D> {
+    render: fn(&D, WizardStepContext, &HtmxRequest) -> Response,
+    submit: fn(&[u8], &mut D) -> Result<(), Vec<String>>,
+}
+
+fn render_step<D, S: WizardStep<D>>(
+    draft: &D,
+    context: WizardStepContext,
+    htmx: &HtmxRequest,
+) -> Response {
+    S::component(draft, context).into_page_response(htmx)
+}
+
+fn submit_step<D, S: WizardStep<D>>(body: &[u8], draft: &mut D) -> Result<(), Vec<String>> {
+    let data: S =
+        serde_urlencoded::from_bytes(body).map_err(|e| vec![format!("Invalid form: {}", e)])?;
+    data.validate(draft)?;
+    data.apply(draft);
+    Ok(())
+}
+
+pub fn step<D, S: WizardStep<D>>() -> WizardStepHandler<D> {
+    WizardStepHandler {
+        render: render_step::<D, S>,
+        submit: submit_step::<D, S>,
+    }
+}
+
+impl<D> WizardStepHandler<D> {
+    pub(crate) fn render(
+        &self,
+        draft: &D,
+        context: WizardStepContext,
+        htmx: &HtmxRequest,
+    ) -> Response {
+        (self.render)(draft, context, htmx)
+    }
+
+    pub(crate) fn submit(&self, body: &[u8], draft: &mut D) -> Result<(), Vec<String>> {
+        (self.submit)(body, draft)
+    }
+}
+
+/// A create flow spanning several forms, handled by `wizard_controller_show` and
+/// `wizard_controller_submit`. The data of the submitted steps is kept in a `Draft`, which is
+/// stored in the `break_stack_wizard_drafts` table (see `migrations::run`) for the session of
+/// the user. After the last step the draft is turned into the data for `ModelCreate::create`,
+/// and the object is created the same way as with `model_controller_create`, using the
+/// `ModelController` implementation of the wizard to render the response.
+///
+/// ```ignore
+/// impl Wizard for TodoItemWizard {
+///     const NAME: &'static str = "todo_item";
+///     type Draft = TodoItemDraft;
+///
+///     fn steps() -> Vec<WizardStepHandler<TodoItemDraft>> {
+///         vec![wizard::step::<_, DescriptionStep>(), wizard::step::<_, ConfirmStep>()]
+///     }
+///
+///     fn create_data(draft: &TodoItemDraft) -> Result<TodoItemCreate, Vec<String>> {
+///         Ok(TodoItemCreate { description: draft.description.clone() })
+///     }
+/// }
+/// ```
+pub trait Wizard: ModelController<Model: AuthModelCreate> {
+    /// Name the drafts are stored with, needs to be unique among the wizards of the app.
+    const NAME: &'static str;
+    /// Drafts that haven't been updated for this long are discarded.
+    const DRAFT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+    type Draft: Serialize + DeserializeOwned + Default + Send + Sync;
+
+    fn steps() -> Vec<WizardStepHandler<Self::Draft>>;
+
+    /// Data for `ModelCreate::create` from the completed draft, errors are shown with the form
+    /// of the last step.
+    fn create_data(
+        draft: &Self::Draft,
+    ) -> Result<<Self::Model as ModelCreate>::Create, Vec<String>>;
+}
+
+pub(crate) struct WizardState<D> {
+    pub step: usize,
+    pub draft: D,
+}
+
+/// Loads the draft of the session, or a new draft if there isn't one, or if it was stored for
+/// another user, is expired, or doesn't match the current steps or `Draft` type anymore.
+pub(crate) async fn load<W: Wizard>(
+    conn: &mut DBConn,
+    session: &SessionId,
+    user_id: Option<UserId>,
+    steps: usize,
+) -> Result<WizardState<W::Draft>, ModelError> {
+    let stored: Option<(i64, String)> = sqlx::query_as(
+        "SELECT step, data FROM break_stack_wizard_drafts WHERE session_id = ? AND wizard = ? AND user_id IS ? AND updated_at > ?",
+    )
+    .bind(session.as_str())
+    .bind(W::NAME)
+    .bind(user_id.map(|user_id| *user_id))
+    .bind(unix_timestamp() - W::DRAFT_TTL.as_secs() as i64)
+    .fetch_optional(&mut **conn)
+    .await?;
+
+    Ok(stored
+        .and_then(|(step, data)| {
+            let step = usize::try_from(step).ok().filter(|step| *step < steps)?;
+            let draft = serde_json::from_str(&data).ok()?;
+            Some(WizardState { step, draft })
+        })
+        .unwrap_or_else(|| WizardState {
+            step: 0,
+            draft: W::Draft::default(),
+        }))
+}
+
+pub(crate) async fn save<W: Wizard>(
+    conn: &mut DBConn,
+    session: &SessionId,
+    user_id: Option<UserId>,
+    state: &WizardState<W::Draft>,
+) -> AppResult<()> {
+    let data = serde_json::to_string(&state.draft)
+        .map_err(|e| AppError::Internal(format!("failed to serialize wizard draft: {}", e)))?;
+    sqlx::query(
+        "INSERT INTO break_stack_wizard_drafts (session_id, wizard, user_id, step, data, updated_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+        ON CONFLICT (session_id, wizard) DO UPDATE SET user_id = ?3, step = ?4, data = ?5, updated_at = ?6",
+    )
+    .bind(session.as_str())
+    .bind(W::NAME)
+    .bind(user_id.map(|user_id| *user_id))
+    .bind(state.step as i64)
+    .bind(data)
+    .bind(unix_timestamp())
+    .execute(&mut **conn)
+    .await?;
+    Ok(())
+}
+
+/// Discards the draft of `W` for the session, e.g. when the user cancels the wizard.
+pub async fn discard<W: Wizard>(conn: &mut DBConn, session: &SessionId) -> Result<(), ModelError> {
+    sqlx::query("DELETE FROM break_stack_wizard_drafts WHERE session_id = ? AND wizard = ?")
+        .bind(session.as_str())
+        .bind(W::NAME)
+        .execute(&mut **conn)
+        .await?;
+    Ok(())
+}
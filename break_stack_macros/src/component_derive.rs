@@ -1,4 +1,4 @@
-use super::utils::get_field_attr;
+use super::utils::{get_field_attr, get_input_attr};
 use proc_macro2::TokenStream;
 use quote::{format_ident, quote, quote_spanned};
 use std::collections::BTreeMap;
@@ -15,6 +15,7 @@ pub fn impl_component_macro(ast: &syn::DeriveInput) -> TokenStream {
     let component_ref_impl = component_ref_impl(ast);
     let component_ref_impl_component = component_ref_impl_component(ast);
     let component_ref_impl_component_as_ref = component_ref_impl_component_as_ref(ast);
+    let component_ref_impl_reloadable_template = component_ref_impl_reloadable_template(ast);
 
     let gen = quote! {
         #component_impl_component
@@ -26,6 +27,7 @@ pub fn impl_component_macro(ast: &syn::DeriveInput) -> TokenStream {
         #component_ref_impl
         #component_ref_impl_component
         #component_ref_impl_component_as_ref
+        #component_ref_impl_reloadable_template
     };
 
     if std::env::var("BREAK_STACK_PRINT_DERIVE")
@@ -66,7 +68,7 @@ fn declare_component_ref(ast: &syn::DeriveInput) -> TokenStream {
     };
     let generics = component_ref_generics_from_types(fields);
 
-    let generics_decl = generics.values().map(|g| component_ref_generic_decl(g));
+    let generics_decl = generics.values().map(component_ref_generic_decl);
 
     let fields_decl = fields.named.iter().map(|field| {
         let ident = field
@@ -107,7 +109,7 @@ fn component_ref_impl(ast: &syn::DeriveInput) -> TokenStream {
     };
     let generics = component_ref_generics_from_types(fields);
 
-    let generics_decl = generics.values().map(|g| component_ref_generic_decl(g));
+    let generics_decl = generics.values().map(component_ref_generic_decl);
 
     let generic_names = generics.values().map(|(generic, _)| generic);
 
@@ -159,15 +161,25 @@ fn component_ref_impl_component(ast: &syn::DeriveInput) -> TokenStream {
     };
     let generics = component_ref_generics_from_types(fields);
 
-    let generics_decl = generics.values().map(|g| component_ref_generic_decl(g));
+    let generics_decl = generics.values().map(component_ref_generic_decl);
 
     let generic_names = generics.values().map(|(generic, _)| generic);
 
     let lifetime = input_needs_lifetime(ast).then(|| quote! {'a}).into_iter();
     let lifetime2 = lifetime.clone();
 
+    let page_response = component_layout(ast).map(|layout| {
+        quote! {
+            fn into_page_response(self, htmx: &::break_stack::htmx::HtmxRequest) -> ::axum::response::Response {
+                ::break_stack::components::page_response::<#layout, _>(self, htmx)
+            }
+        }
+    });
+
     let decl = quote! {
-        impl<#(#lifetime)* #(, #generics_decl)*> Component for #name<#(#lifetime2)* #(, #generic_names)*> {}
+        impl<#(#lifetime)* #(, #generics_decl)*> Component for #name<#(#lifetime2)* #(, #generic_names)*> {
+            #page_response
+        }
     };
     decl.into()
 }
@@ -184,7 +196,7 @@ fn component_ref_impl_component_as_ref(ast: &syn::DeriveInput) -> TokenStream {
     };
     let generics = component_ref_generics_from_types(fields);
 
-    let generics_decl = generics.values().map(|g| component_ref_generic_decl(g));
+    let generics_decl = generics.values().map(component_ref_generic_decl);
 
     let generic_names = generics.values().map(|(generic, _)| generic);
 
@@ -203,6 +215,82 @@ fn component_ref_impl_component_as_ref(ast: &syn::DeriveInput) -> TokenStream {
     decl.into()
 }
 
+fn component_ref_impl_reloadable_template(ast: &syn::DeriveInput) -> TokenStream {
+    let component_name = &ast.ident;
+    let name = format_ident!("{}Ref", component_name);
+    let fields = match &ast.data {
+        syn::Data::Struct(DataStruct {
+            fields: Fields::Named(fields),
+            ..
+        }) => fields,
+        _ => todo!(),
+    };
+    let generics = component_ref_generics_from_types(fields);
+
+    let generics_decl = generics.values().map(component_ref_generic_decl);
+
+    let generic_names = generics.values().map(|(generic, _)| generic);
+
+    let lifetime = input_needs_lifetime(ast).then(|| quote! {'a}).into_iter();
+    let lifetime2 = lifetime.clone();
+
+    let template_attrs = get_input_attr(ast, "template").unwrap_or_default();
+    let source = match (
+        template_attrs.get("path"),
+        template_attrs.get("source"),
+        template_attrs.get("ext"),
+    ) {
+        _ if template_attrs.contains_key("block") => {
+            quote! {::break_stack::template_reload::TemplateSource::Compiled}
+        }
+        (Some(path), _, _) => quote! {::break_stack::template_reload::TemplateSource::Path(#path)},
+        (None, Some(source), Some(ext)) => quote! {
+            ::break_stack::template_reload::TemplateSource::Source { source: #source, ext: #ext }
+        },
+        _ => quote! {::break_stack::template_reload::TemplateSource::Compiled},
+    };
+
+    let context_fields = fields.named.iter().map(|field| {
+        let ident = field
+            .ident
+            .as_ref()
+            .expect("only named fields are supported");
+        let key = ident.to_string();
+        if generics.contains_key(ident) {
+            quote! {
+                (#key, ::break_stack::template_reload::Value::from(
+                    ::std::clone::Clone::clone(&self.#ident)
+                        .into_iter()
+                        .map(|item| (&&::break_stack::template_reload::ContextField(item)).to_context_value())
+                        .collect::<Option<Vec<_>>>()?
+                ))
+            }
+        } else {
+            quote! {
+                (#key, (&&::break_stack::template_reload::ContextField(&self.#ident)).to_context_value()?)
+            }
+        }
+    });
+
+    let decl = quote! {
+        impl<#(#lifetime)* #(, #generics_decl)*> ::break_stack::template_reload::ReloadableTemplate for #name<#(#lifetime2)* #(, #generic_names)*> {
+            const NAME: &'static str = stringify!(#component_name);
+            const PATH: &'static str = concat!(module_path!(), "::", stringify!(#component_name));
+            const SOURCE: ::break_stack::template_reload::TemplateSource = #source;
+            const TEMPLATES_DIR: &'static str = concat!(env!("CARGO_MANIFEST_DIR"), "/templates");
+
+            ::break_stack::__template_reload_items! {
+                fn context(&self) -> Option<::break_stack::template_reload::Value> {
+                    #[allow(unused_imports)]
+                    use ::break_stack::template_reload::{ToContextValue as _, ToContextValueFallback as _};
+                    Some(::break_stack::template_reload::context([#(#context_fields),*]))
+                }
+            }
+        }
+    };
+    decl
+}
+
 fn component_ref_impl_from_component(ast: &syn::DeriveInput) -> TokenStream {
     let name = &ast.ident;
     let name_ref = format_ident!("{}Ref", name);
@@ -389,11 +477,30 @@ fn field_is_primitive(field: &Field) -> bool {
     }
 }
 
+fn component_layout(ast: &syn::DeriveInput) -> Option<TokenStream> {
+    let attrs = get_input_attr(ast, "component")?;
+    let layout_str = attrs.get("layout")?;
+    let layout = layout_str
+        .parse::<Type>()
+        .expect("layout should be string containing valid type");
+    Some(quote_spanned! {layout_str.span()=>#layout})
+}
+
 fn component_impl_component(ast: &syn::DeriveInput) -> TokenStream {
     let name = &ast.ident;
 
+    let page_response = component_layout(ast).map(|_| {
+        quote! {
+            fn into_page_response(self, htmx: &::break_stack::htmx::HtmxRequest) -> ::axum::response::Response {
+                <&#name as ComponentAsRef>::as_ref(&self).into_page_response(htmx)
+            }
+        }
+    });
+
     let decl = quote! {
-        impl Component for #name {}
+        impl Component for #name {
+            #page_response
+        }
     };
     decl.into()
 }
@@ -433,7 +540,7 @@ fn component_impl_into_response(ast: &syn::DeriveInput) -> TokenStream {
     let decl = quote! {
         impl ::axum::response::IntoResponse for #name {
             fn into_response(self) -> askama_axum::Response {
-                <&#name as ComponentAsRef>::as_ref(&self).into_response()
+                ::break_stack::template_reload::into_response(<&#name as ComponentAsRef>::as_ref(&self))
             }
         }
     };
@@ -706,6 +813,44 @@ mod test {
         assert_eq!(result, expected);
     }
 
+    #[test]
+    fn test_component_ref_impl_component_with_layout() {
+        let derive_input = syn::parse_str(
+            r#"
+        #[component(layout = "PageLayout")]
+        struct MyComponent {
+            pub field_a: A,
+            pub field_b: bool,
+        }
+        "#,
+        )
+        .unwrap();
+
+        let result = remove_whitespace(&component_ref_impl_component(&derive_input).to_string());
+        let expected = remove_whitespace(
+            r#"
+        impl<'a> Component for MyComponentRef<'a> {
+            fn into_page_response(self, htmx: &::break_stack::htmx::HtmxRequest) -> ::axum::response::Response {
+                ::break_stack::components::page_response::<PageLayout, _>(self, htmx)
+            }
+        }
+            "#,
+        );
+        assert_eq!(result, expected);
+
+        let result = remove_whitespace(&component_impl_component(&derive_input).to_string());
+        let expected = remove_whitespace(
+            r#"
+        impl Component for MyComponent {
+            fn into_page_response(self, htmx: &::break_stack::htmx::HtmxRequest) -> ::axum::response::Response {
+                <&MyComponent as ComponentAsRef>::as_ref(&self).into_page_response(htmx)
+            }
+        }
+            "#,
+        );
+        assert_eq!(result, expected);
+    }
+
     #[test]
     fn test_component_impl_component_as_ref() {
         let derive_input = syn::parse_str(
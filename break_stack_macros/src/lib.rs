@@ -45,6 +45,13 @@ pub fn model_delete_derive(input: proc_macro::TokenStream) -> proc_macro::TokenS
     model_derive::impl_model_delete_macro(&ast).into()
 }
 
+#[proc_macro_derive(ModelFactory, attributes(model_factory, factory))]
+pub fn model_factory_derive(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let ast = syn::parse(input).unwrap();
+
+    model_derive::impl_model_factory_macro(&ast).into()
+}
+
 #[proc_macro_derive(WithOwnerModel, attributes(with_owner_model))]
 pub fn with_owner_model_derive(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let ast = syn::parse(input).unwrap();
@@ -7,7 +7,7 @@ use syn::{
     parse::Parser, punctuated::Punctuated, token::Comma, Attribute, DataStruct, Expr, Field,
     Fields, FieldsNamed, Ident, Lit, LitStr, MetaList, MetaNameValue, Type,
 };
-use super::utils::get_input_attr;
+use super::utils::{get_field_attr, get_input_attr};
 
 pub fn impl_model_macro(ast: &syn::DeriveInput) -> TokenStream {
     let name = &ast.ident;
@@ -22,11 +22,28 @@ pub fn impl_model_macro(ast: &syn::DeriveInput) -> TokenStream {
         .map(|s| s.parse::<Type>().expect("id_type needs to be a valid type"))
         .map(|ty| quote_spanned! {ty.span()=>#ty})
         .unwrap_or_else(|| quote! {i64});
+    let model_conn_type = args
+        .get("conn")
+        .map(|s| s.parse::<Type>().expect("conn needs to be a valid type"))
+        .map(|ty| quote_spanned! {ty.span()=>#ty})
+        .unwrap_or_else(|| quote! {::break_stack::models::DBConn});
+    let model_version = args.get("version").map(|field| {
+        let field = field
+            .parse::<Ident>()
+            .expect("version needs to be the name of a field");
+        quote! {
+            fn version(&self) -> Option<String> {
+                Some(self.#field.to_string())
+            }
+        }
+    });
 
     let gen = quote! {
         impl Model for #name {
             type ID = #model_id_type;
+            type Conn = #model_conn_type;
             const MODEL_NAME: &'static str = #model_name;
+            #model_version
         }
     };
 
@@ -280,6 +297,74 @@ pub fn impl_with_owner_model_macro(ast: &syn::DeriveInput) -> TokenStream {
     gen.into()
 }
 
+pub fn impl_model_factory_macro(ast: &syn::DeriveInput) -> TokenStream {
+    let name = &ast.ident;
+
+    let args = get_input_attr(ast, "model_factory")
+        .expect("deriving ModelFactory requires a model_factory attribute");
+
+    let model = args
+        .get("model")
+        .expect("model_factory attribute requires a field called model")
+        .parse::<Type>()
+        .expect("model needs to be a valid type");
+    let id_field = args
+        .get("id")
+        .map(|field| field.parse::<Ident>().expect("id needs to be the name of a field"))
+        .unwrap_or_else(|| format_ident!("id"));
+
+    let fields = match &ast.data {
+        syn::Data::Struct(DataStruct {
+            fields: Fields::Named(fields),
+            ..
+        }) => fields,
+        _ => panic!("ModelFactory can only be derived for structs with named fields"),
+    };
+    let fields = fields.named.iter().map(|field| {
+        let ident = field
+            .ident
+            .as_ref()
+            .expect("only named fields are supported");
+        let attrs = get_field_attr(field, "factory").unwrap_or_default();
+        if let Some(default) = attrs.get("default") {
+            let default = default
+                .parse::<Expr>()
+                .expect("default should be string containing valid expression");
+            quote_spanned! {default.span()=>#ident: #default}
+        } else if let Some(sequence) = attrs.get("sequence") {
+            quote_spanned! {sequence.span()=>#ident: format!(#sequence, n)}
+        } else {
+            quote! {#ident: ::std::default::Default::default()}
+        }
+    });
+
+    let gen = quote! {
+        impl ModelFactory for #name {
+            type Model = #model;
+
+            #[allow(unused_variables)]
+            fn build(n: u64) -> Self {
+                Self {
+                    #(#fields,)*
+                }
+            }
+
+            fn id(model: &#model) -> <#model as Model>::ID {
+                model.#id_field
+            }
+        }
+    };
+
+    if std::env::var("BREAK_STACK_PRINT_DERIVE")
+        .map(|s| s == "1")
+        .unwrap_or(false)
+    {
+        println!("Generated code: {}", gen);
+    }
+
+    gen
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -305,6 +390,7 @@ mod test {
         let expected = r#"
             impl Model for TestModel {
                 type ID = i64;
+                type Conn = ::break_stack::models::DBConn;
 
                 const MODEL_NAME: &'static str = "Test";
             }
@@ -316,6 +402,69 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_impl_model_macro_with_conn() {
+        let input = syn::parse_str::<syn::DeriveInput>(
+            r#"
+            #[derive(Model)]
+            #[model(name = "Test", conn = "MockConn")]
+            struct TestModel {
+                pub id: i64,
+            }
+            "#,
+        )
+        .unwrap();
+
+        let result = impl_model_macro(&input);
+        let expected = r#"
+            impl Model for TestModel {
+                type ID = i64;
+                type Conn = MockConn;
+
+                const MODEL_NAME: &'static str = "Test";
+            }
+            "#;
+
+        assert_eq!(
+            remove_whitespace(&result.to_string()),
+            remove_whitespace(expected)
+        );
+    }
+
+    #[test]
+    fn test_impl_model_macro_with_version() {
+        let input = syn::parse_str::<syn::DeriveInput>(
+            r#"
+            #[derive(Model)]
+            #[model(name = "Test", version = "updated_at")]
+            struct TestModel {
+                pub id: i64,
+                pub updated_at: i64,
+            }
+            "#,
+        )
+        .unwrap();
+
+        let result = impl_model_macro(&input);
+        let expected = r#"
+            impl Model for TestModel {
+                type ID = i64;
+                type Conn = ::break_stack::models::DBConn;
+
+                const MODEL_NAME: &'static str = "Test";
+
+                fn version(&self) -> Option<String> {
+                    Some(self.updated_at.to_string())
+                }
+            }
+            "#;
+
+        assert_eq!(
+            remove_whitespace(&result.to_string()),
+            remove_whitespace(expected)
+        );
+    }
+
     #[test]
     fn test_impl_model_read_macro() {
         let input = syn::parse_str::<syn::DeriveInput>(
@@ -523,4 +672,47 @@ mod test {
             remove_whitespace(&expected.to_string())
         );
     }
+
+    #[test]
+    fn test_impl_model_factory_macro() {
+        let input = syn::parse_str::<syn::DeriveInput>(
+            r#"
+            #[derive(ModelFactory)]
+            #[model_factory(model = "TestModel")]
+            struct TestModelCreate {
+                #[factory(sequence = "field {}")]
+                pub field: String,
+                #[factory(default = "n as i64 * 2")]
+                pub number: i64,
+                pub done: bool,
+            }
+            "#,
+        )
+        .unwrap();
+
+        let result = impl_model_factory_macro(&input);
+        let expected = r#"
+            impl ModelFactory for TestModelCreate {
+                type Model = TestModel;
+
+                #[allow(unused_variables)]
+                fn build(n: u64) -> Self {
+                    Self {
+                        field: format!("field {}", n),
+                        number: n as i64 * 2,
+                        done: ::std::default::Default::default(),
+                    }
+                }
+
+                fn id(model: &TestModel) -> <TestModel as Model>::ID {
+                    model.id
+                }
+            }
+            "#;
+
+        assert_eq!(
+            remove_whitespace(&result.to_string()),
+            remove_whitespace(expected)
+        );
+    }
 }
@@ -3,6 +3,26 @@ use break_stack::components::*;
 use break_stack::models::*;
 use break_stack::utils::askama::filters;
 
+#[derive(Component)]
+#[template(
+    source = r#"
+        {% extends "layout.html" %}
+
+        {% block body %}
+            {{ content|safe }}
+        {% endblock %}
+        "#,
+    ext = "html"
+)]
+pub struct PageLayoutComponent {
+    pub content: String,
+}
+impl Layout for PageLayoutComponent {
+    fn from_content(content: String) -> Self {
+        Self { content }
+    }
+}
+
 #[derive(Component)]
 #[template(
     source = r#"
@@ -12,13 +32,17 @@ use break_stack::utils::askama::filters;
             {% else %}
                 <span>{{ item.description }}</span>
             {% endif %}
-            <button type="button" hx-get="{{ crate::routes::route_paths::htmx_items_id_edit(item.id) }}">
+            <button type="button" hx-get="{{ crate::routes::htmx_items::route_paths::edit(item.id) }}">
                 Click To Edit
             </button>
+            <button type="button" hx-delete="{{ crate::routes::htmx_items::route_paths::id(item.id) }}">
+                Delete
+            </button>
         </div>
         "#,
     ext = "html"
 )]
+#[component(layout = "PageLayoutComponent")]
 pub struct TodoItemViewComponent {
     pub item: TodoItemModel,
 }
@@ -31,7 +55,7 @@ impl From<TodoItemModel> for TodoItemViewComponent {
 #[derive(Component)]
 #[template(
     source = r#"
-        <form hx-put="/htmx/items/{{ item.id }}" hx-target="this" hx-swap="outerHTML">
+        <form hx-put="{{ crate::routes::htmx_items::route_paths::id(item.id) }}" hx-target="this" hx-swap="outerHTML">
             <div>
                 <label for="description">Description</label>
                 <input type="text" id="description" name="description" value="{{ item.description }}" />
@@ -40,12 +64,13 @@ impl From<TodoItemModel> for TodoItemViewComponent {
                 <label for="done">Done</label>
                 <input type="checkbox" id="done" name="done" value="true" {{ item.done|string_if_true("checked") }} />
             </div>
-            <button type="button" hx-get="/htmx/items/{{ item.id }}">Cancel</button>
+            <button type="button" hx-get="{{ crate::routes::htmx_items::route_paths::id(item.id) }}">Cancel</button>
             <button type="submit">Update</button>
         </form>
         "#,
     ext = "html"
 )]
+#[component(layout = "PageLayoutComponent")]
 pub struct TodoItemEditComponent {
     pub item: TodoItemModel,
 }
@@ -58,28 +83,54 @@ impl From<TodoItemModel> for TodoItemEditComponent {
 #[derive(Component)]
 #[template(
     source = r#"
-        <form hx-post="/htmx/items" hx-target="this" hx-swap="outerHTML">
+        <form hx-post="{{ crate::routes::htmx_items::route_paths::index() }}" hx-target="this" hx-swap="outerHTML">
+            {{ break_stack::idempotency::idempotency_key_input()|safe }}
             <div>
                 <label for="description">Description</label>
                 <input type="text" id="description" name="description" />
             </div>
-            <button type="button" hx-get="/htmx/items/button-new">Cancel</button>
+            <button type="button" hx-get="{{ crate::routes::route_paths::htmx_items_button_new() }}">Cancel</button>
             <button type="submit">Create</button>
         </form>
         "#,
     ext = "html"
 )]
+#[component(layout = "PageLayoutComponent")]
+#[derive(Default)]
 pub struct TodoItemNewComponent {}
 
 #[derive(Component)]
 #[template(
     source = r#"
-        <button type="button" hx-get="/htmx/items/new" hx-target="this" hx-swap="outerHTML">New Todo Item</button>
+        <button type="button" hx-get="{{ crate::routes::htmx_items::route_paths::new() }}" hx-target="this" hx-swap="outerHTML">New Todo Item</button>
         "#,
     ext = "html"
 )]
+#[component(layout = "PageLayoutComponent")]
+#[derive(Default)]
 pub struct TodoItemButtonNewComponent {}
 
+#[derive(Component)]
+#[template(
+    source = r#"
+        <div>
+            {% for item in items.clone() %}
+                {{ TodoItemViewComponentRef::new(item)|safe }}
+            {% endfor %}
+        </div>
+        "#,
+    ext = "html"
+)]
+#[component(layout = "PageLayoutComponent")]
+pub struct TodoItemListComponent {
+    pub items: Vec<TodoItemModel>,
+}
+impl From<Vec<TodoItemModel>> for TodoItemListComponent {
+    fn from(items: Vec<TodoItemModel>) -> Self {
+        Self { items }
+    }
+}
+
 #[derive(Component)]
 #[template(
     source = r#"
@@ -4,21 +4,38 @@ use axum::{
     extract::State,
     response::{IntoResponse, Response},
 };
-use break_stack::controllers::*;
+use break_stack::auth::UserId;
+use break_stack::components::Component;
+use break_stack::controllers::ModelController;
 use break_stack::errors::*;
+use break_stack::htmx::HtmxRequest;
+use break_stack::models::DBConn;
 
-pub type HtmxTodoItemViewController =
-    ComponentFromModelController<TodoItemModel, TodoItemViewComponent>;
+/// Create controller that ignores repeated submissions of the same new item form.
+pub struct TodoItemCreateController;
+impl ModelController for TodoItemCreateController {
+    type Model = TodoItemModel;
 
-pub type HtmxTodoItemEditController =
-    ComponentFromModelController<TodoItemModel, TodoItemEditComponent>;
+    const IDEMPOTENCY_WINDOW: Option<std::time::Duration> =
+        Some(std::time::Duration::from_secs(10 * 60));
+    const FLASH_CREATED: Option<&'static str> = Some("Todo item added");
 
-pub async fn get_htmx_items_new() -> AppResult<Response> {
-    Ok(TodoItemNewComponentRef::new().into_response())
-}
+    async fn build_response(
+        _conn: &mut DBConn,
+        _user_id: Option<UserId>,
+        item: Self::Model,
+    ) -> AppResult<Response> {
+        Ok(TodoItemViewComponent { item }.into_response())
+    }
 
-pub async fn get_htmx_items_button_new() -> AppResult<Response> {
-    Ok(TodoItemButtonNewComponentRef::new().into_response())
+    async fn build_page_response(
+        _conn: &mut DBConn,
+        _user_id: Option<UserId>,
+        htmx: &HtmxRequest,
+        item: Self::Model,
+    ) -> AppResult<Response> {
+        Ok(TodoItemViewComponent { item }.into_page_response(htmx))
+    }
 }
 
 pub async fn get_index_page(state: State<crate::AppState>) -> AppResult<Response> {
@@ -5,9 +5,12 @@ mod routes;
 
 use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
 use break_stack::auth::UserId;
+use break_stack::dev_toolbar;
 use break_stack::errors::*;
+use break_stack::flash;
 use break_stack::hot_reload;
 use break_stack::models::DBConn;
+use break_stack::queries;
 use sqlx::sqlite::SqlitePool;
 
 #[derive(Clone)]
@@ -21,7 +24,7 @@ impl AppState {
         AppState { db_pool }
     }
     pub async fn conn(&self) -> sqlx::Result<DBConn> {
-        self.db_pool.acquire().await
+        Ok(self.db_pool.acquire().await?.into())
     }
 }
 
@@ -36,10 +39,12 @@ impl FromRequestParts<AppState> for DBConn {
     type Rejection = AppError;
 
     async fn from_request_parts(
-        _parts: &mut Parts,
+        parts: &mut Parts,
         state: &AppState,
     ) -> Result<Self, Self::Rejection> {
-        Ok(state.conn().await?)
+        let mut conn = state.conn().await?;
+        queries::attach(parts, &mut conn).await?;
+        Ok(conn)
     }
 }
 
@@ -65,12 +70,22 @@ async fn main() {
 
     {
         let mut conn = app_state.conn().await.unwrap();
-        sqlx::migrate!().run(&mut conn).await.unwrap();
+        sqlx::migrate!().run(&mut *conn).await.unwrap();
+        break_stack::migrations::run(&mut conn).await.unwrap();
     }
 
-    let app = routes::router()
+    let mut app = routes::router()
+        .merge(routes::htmx_items::router())
         .nest("/reload", hot_reload::reload_router())
-        .with_state(app_state);
+        .layer(axum::middleware::from_fn(flash::flash_errors));
+    // Recording the queries of every request is only worth it during development
+    if cfg!(debug_assertions) || hot_reload::hot_reload_enabled() {
+        app = app.layer(axum::middleware::from_fn(queries::log_queries));
+    }
+    if hot_reload::hot_reload_enabled() {
+        app = app.layer(axum::middleware::from_fn(dev_toolbar::record_requests));
+    }
+    let app = app.with_state(app_state);
 
     let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
         .await
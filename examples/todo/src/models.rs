@@ -3,12 +3,12 @@ use break_stack::errors::*;
 use break_stack::models::*;
 use serde::Deserialize;
 
-#[derive(Deserialize, Model, ModelRead, ModelWrite, ModelCreate)]
-#[model(name = "TodoItem")]
+#[derive(Deserialize, Model, ModelRead, ModelWrite, ModelCreate, ModelDelete)]
+#[model(name = "TodoItem", version = "version")]
 #[model_read(query = "SELECT * FROM todo_items WHERE id = ?")]
 #[model_write(
     data_type = "TodoItemWrite",
-    query = "UPDATE todo_items SET description = ?, done = ? WHERE id = ? RETURNING *",
+    query = "UPDATE todo_items SET description = ?, done = ?, version = version + 1 WHERE id = ? RETURNING *",
     fields = "data.description, data.done, id"
 )]
 #[model_create(
@@ -16,10 +16,12 @@ use serde::Deserialize;
     query = "INSERT INTO todo_items (description, done) VALUES (?, FALSE) RETURNING *",
     fields = "data.description"
 )]
+#[model_delete(query = "DELETE FROM todo_items WHERE id = ? RETURNING *")]
 pub struct TodoItemModel {
     pub id: i64,
     pub description: String,
     pub done: bool,
+    pub version: i64,
 }
 
 impl TodoItemModel {
@@ -39,8 +41,10 @@ pub struct TodoItemWrite {
     pub done: bool,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ModelFactory)]
+#[model_factory(model = "TodoItemModel")]
 pub struct TodoItemCreate {
+    #[factory(sequence = "Todo item {}")]
     pub description: String,
 }
 
@@ -74,3 +78,19 @@ impl AuthModelCreate for TodoItemModel {
         Ok(())
     }
 }
+
+impl AuthModelDelete for TodoItemModel {
+    async fn can_delete(
+        _conn: &mut DBConn,
+        _id: i64,
+        _user_id: Option<UserId>,
+    ) -> Result<(), AuthError> {
+        Ok(())
+    }
+}
+
+impl AuthModelList for TodoItemModel {
+    async fn list(conn: &mut DBConn, _user_id: Option<UserId>) -> Result<Vec<Self>, AuthError> {
+        Ok(Self::all(conn).await?)
+    }
+}
@@ -1,18 +1,24 @@
+use crate::components::*;
 use crate::controllers::*;
+use crate::models::*;
 use crate::AppState;
-use axum::{
-    routing::{get, post},
-    Router,
-};
-use break_stack::controllers::*;
-use break_stack::routes::build_router;
+use axum::routing::get;
+use break_stack::controllers::{component_controller_default, model_controller_create_idempotent};
+use break_stack::routes::{build_router, resource};
 
 build_router! {
     AppState,
     (index, "/", (), get(get_index_page)),
-    (htmx_items, "/htmx/items", (), post(model_controller_create::<HtmxTodoItemViewController>)),
-    (htmx_items_new, "/htmx/items/new", (), get(get_htmx_items_new)),
-    (htmx_items_button_new, "/htmx/items/button-new", (), get(get_htmx_items_button_new)),
-    (htmx_items_id, "/htmx/items/{}", (path -> id: &i64 => ":id"), get(model_controller_read::<HtmxTodoItemViewController>).put(model_controller_write::<HtmxTodoItemViewController>)),
-    (htmx_items_id_edit, "/htmx/items/{}/edit", (path -> id: &i64 => ":id"), get(model_controller_read::<HtmxTodoItemEditController>)),
+    (htmx_items_button_new, "/htmx/items/button-new", (), get(component_controller_default::<TodoItemButtonNewComponent>)),
+}
+
+resource! {
+    AppState, htmx_items, "/htmx/items", TodoItemModel,
+    view: TodoItemViewComponent,
+    edit: TodoItemEditComponent,
+    new: TodoItemNewComponent,
+    list: TodoItemListComponent,
+    overrides {
+        create: model_controller_create_idempotent::<TodoItemCreateController>,
+    }
 }
@@ -0,0 +1,99 @@
+use axum::http::StatusCode;
+use break_stack::components::*;
+use break_stack::testing::TestResponse;
+
+#[derive(Component)]
+#[template(source = r#"<li id="item-{{ id }}">{{ name }}</li>"#, ext = "html")]
+pub struct ItemComponent {
+    id: i64,
+    name: String,
+}
+
+#[derive(Component)]
+#[template(source = r#"{{ count }} items"#, ext = "html")]
+pub struct CountComponent {
+    count: usize,
+}
+
+#[derive(Component)]
+#[template(source = r#"<p title="a id=b">{{ text }}</p>"#, ext = "html")]
+pub struct IdInTitleComponent {
+    text: String,
+}
+
+#[derive(Component)]
+#[template(
+    source = "<p class=\"a\n  b\" data-x='1 > 0'\n  id = \"text\">{{ text }}</p>",
+    ext = "html"
+)]
+pub struct SpacedIdComponent {
+    text: String,
+}
+
+#[tokio::test]
+async fn test_oob_response() {
+    let item = ItemComponent {
+        id: 1,
+        name: "A".into(),
+    };
+    let response = OobResponse::new(&item)
+        .oob(ItemComponentRef::new(2, "B"))
+        .oob_target(CountComponentRef::new(2), OobSwap::InnerHtml, "#count")
+        .oob_target(&item, OobSwap::BeforeEnd, "#items")
+        .oob_target(&item, OobSwap::OuterHtml, "#item-3");
+    assert_eq!(
+        TestResponse::from_response(response)
+            .await
+            .assert_ok()
+            .text(),
+        [
+            r#"<li id="item-1">A</li>"#,
+            r#"<li hx-swap-oob="true" id="item-2">B</li>"#,
+            r##"<div hx-swap-oob="innerHTML:#count">2 items</div>"##,
+            r##"<div hx-swap-oob="beforeend:#items"><li id="item-1">A</li></div>"##,
+            r##"<li hx-swap-oob="outerHTML:#item-3" id="item-1">A</li>"##,
+        ]
+        .join("\n")
+    );
+}
+
+#[tokio::test]
+async fn test_oob_response_without_main_component() {
+    let response = OobResponse::empty().oob(ItemComponentRef::new(2, "B"));
+    assert_eq!(
+        TestResponse::from_response(response)
+            .await
+            .assert_ok()
+            .text(),
+        r#"<li hx-swap-oob="true" id="item-2">B</li>"#
+    );
+}
+
+#[tokio::test]
+async fn test_oob_response_without_root_id() {
+    // htmx can't find the element to swap, `oob_target` must be used instead
+    let response = OobResponse::empty()
+        .oob(ItemComponentRef::new(2, "B"))
+        .oob(CountComponentRef::new(0));
+    TestResponse::from_response(response)
+        .await
+        .assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[tokio::test]
+async fn test_oob_response_root_id_parsing() {
+    let response = OobResponse::empty().oob(SpacedIdComponentRef::new("A"));
+    assert_eq!(
+        TestResponse::from_response(response)
+            .await
+            .assert_ok()
+            .text(),
+        "<p hx-swap-oob=\"true\" class=\"a\n  b\" data-x='1 > 0'\n  id = \"text\">A</p>"
+    );
+
+    // `id=` inside another attribute's value isn't an id
+    let response = OobResponse::empty().oob(IdInTitleComponentRef::new("A"));
+    TestResponse::from_response(response)
+        .await
+        .assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+}
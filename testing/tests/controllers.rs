@@ -6,6 +6,10 @@ use axum::{
 use break_stack::auth::*;
 use break_stack::controllers::*;
 use break_stack::errors::*;
+use break_stack::etag::IfNoneMatch;
+use break_stack::htmx::HtmxRequest;
+use break_stack::idempotency::IdempotentForm;
+use break_stack::models::testutils::MockConn;
 use break_stack::models::*;
 use http_body_util::BodyExt;
 
@@ -15,11 +19,12 @@ struct TestModel {
 }
 impl Model for TestModel {
     type ID = i64;
+    type Conn = MockConn;
 
     const MODEL_NAME: &'static str = "Test";
 }
 impl ModelRead for TestModel {
-    async fn read(_conn: &mut DBConn, id: i64) -> Result<Option<Self>, ModelError> {
+    async fn read(_conn: &mut MockConn, id: i64) -> Result<Option<Self>, ModelError> {
         match id {
             10..20 => Ok(None),
             20..30 => Err(ModelError::DB(sqlx::Error::WorkerCrashed)),
@@ -29,7 +34,7 @@ impl ModelRead for TestModel {
 }
 impl AuthModelRead for TestModel {
     async fn can_read(
-        _conn: &mut DBConn,
+        _conn: &mut MockConn,
         id: i64,
         user_id: Option<UserId>,
     ) -> Result<(), AuthError> {
@@ -45,7 +50,7 @@ impl ModelWrite for TestModel {
     type Write = i64;
 
     async fn write(
-        _conn: &mut DBConn,
+        _conn: &mut MockConn,
         id: i64,
         data: Self::Write,
     ) -> Result<Option<Self>, ModelError> {
@@ -58,7 +63,7 @@ impl ModelWrite for TestModel {
 }
 impl AuthModelWrite for TestModel {
     async fn can_write(
-        _conn: &mut DBConn,
+        _conn: &mut MockConn,
         id: i64,
         user_id: Option<UserId>,
         data: &<Self as ModelWrite>::Write,
@@ -74,7 +79,7 @@ impl AuthModelWrite for TestModel {
 impl ModelCreate for TestModel {
     type Create = i64;
 
-    async fn create(_conn: &mut DBConn, data: Self::Create) -> Result<Self, ModelError> {
+    async fn create(_conn: &mut MockConn, data: Self::Create) -> Result<Self, ModelError> {
         match data {
             20..30 => Err(ModelError::DB(sqlx::Error::WorkerCrashed)),
             _ => Ok(Self { id: data, field: 0 }),
@@ -83,7 +88,7 @@ impl ModelCreate for TestModel {
 }
 impl AuthModelCreate for TestModel {
     async fn can_create(
-        _conn: &mut DBConn,
+        _conn: &mut MockConn,
         user_id: Option<UserId>,
         data: &<Self as ModelCreate>::Create,
     ) -> Result<(), AuthError> {
@@ -96,13 +101,36 @@ impl AuthModelCreate for TestModel {
     }
 }
 
+/// Ids passed to `TestModel::delete`, only used by the delete hook tests.
+static DELETED: std::sync::Mutex<Vec<i64>> = std::sync::Mutex::new(Vec::new());
+
+impl ModelDelete for TestModel {
+    async fn delete(_conn: &mut MockConn, id: i64) -> Result<Self, ModelError> {
+        DELETED.lock().unwrap().push(id);
+        Ok(Self { id, field: -1 })
+    }
+}
+impl AuthModelDelete for TestModel {
+    async fn can_delete(
+        _conn: &mut MockConn,
+        id: i64,
+        user_id: Option<UserId>,
+    ) -> Result<(), AuthError> {
+        match (id, user_id) {
+            (_, None) => Err(AuthError::Unauthenticated),
+            (id, Some(user_id)) if id == *user_id => Ok(()),
+            _ => Err(AuthError::Unauthorized),
+        }
+    }
+}
+
 struct TestModelController;
 
 impl ModelController for TestModelController {
     type Model = TestModel;
 
     async fn build_response(
-        _conn: &mut DBConn,
+        _conn: &mut MockConn,
         user_id: Option<UserId>,
         m: Self::Model,
     ) -> AppResult<Response> {
@@ -111,8 +139,8 @@ impl ModelController for TestModelController {
     }
 }
 
-#[sqlx::test]
-async fn test_model_controller_read(pool: sqlx::pool::Pool<sqlx::Sqlite>) {
+#[tokio::test]
+async fn test_model_controller_read() {
     for (case, id, user_id, expect) in [
         ("User 0 can read own id", 0, Some(0), Ok("0:0:0")),
         ("User 1 can read own id", 1, Some(1), Ok("1:1:0")),
@@ -183,9 +211,15 @@ async fn test_model_controller_read(pool: sqlx::pool::Pool<sqlx::Sqlite>) {
         ),
     ] {
         println!("Running test case '{}'", case);
-        let conn = pool.acquire().await.unwrap();
-        let output =
-            model_controller_read::<TestModelController>(conn, Path(id), user_id.map(UserId)).await;
+        let conn = MockConn::new();
+        let output = model_controller_read::<TestModelController>(
+            conn,
+            Path(id),
+            user_id.map(UserId),
+            HtmxRequest::default(),
+            IfNoneMatch::default(),
+        )
+        .await;
         match (&output, &expect) {
             (Ok(response), Ok(expected)) => {
                 assert_eq!(response.status(), 200);
@@ -218,8 +252,8 @@ async fn test_model_controller_read(pool: sqlx::pool::Pool<sqlx::Sqlite>) {
     }
 }
 
-#[sqlx::test]
-async fn test_model_controller_write(pool: sqlx::pool::Pool<sqlx::Sqlite>) {
+#[tokio::test]
+async fn test_model_controller_write() {
     for (case, id, user_id, data, expect) in [
         (
             "User 0 can write legal data to own id",
@@ -339,11 +373,12 @@ async fn test_model_controller_write(pool: sqlx::pool::Pool<sqlx::Sqlite>) {
         ),
     ] {
         println!("Running test case '{}'", case);
-        let conn = pool.acquire().await.unwrap();
+        let conn = MockConn::new();
         let output = model_controller_write::<TestModelController>(
             conn,
             Path(id),
             user_id.map(UserId),
+            HtmxRequest::default(),
             Form(data),
         )
         .await;
@@ -379,8 +414,8 @@ async fn test_model_controller_write(pool: sqlx::pool::Pool<sqlx::Sqlite>) {
     }
 }
 
-#[sqlx::test]
-async fn test_model_controller_create(pool: sqlx::pool::Pool<sqlx::Sqlite>) {
+#[tokio::test]
+async fn test_model_controller_create() {
     for (case, user_id, data, expect) in [
         ("User 0 can create legal data", Some(0), 1, Ok("0:1:0")),
         ("User 1 can create legal data", Some(1), 1, Ok("1:1:0")),
@@ -439,10 +474,14 @@ async fn test_model_controller_create(pool: sqlx::pool::Pool<sqlx::Sqlite>) {
         ),
     ] {
         println!("Running test case '{}'", case);
-        let conn = pool.acquire().await.unwrap();
-        let output =
-            model_controller_create::<TestModelController>(conn, user_id.map(UserId), Form(data))
-                .await;
+        let conn = MockConn::new();
+        let output = model_controller_create::<TestModelController>(
+            conn,
+            user_id.map(UserId),
+            HtmxRequest::default(),
+            Form(data),
+        )
+        .await;
         match (&output, &expect) {
             (Ok(response), Ok(expected)) => {
                 assert_eq!(response.status(), 200);
@@ -474,3 +513,468 @@ async fn test_model_controller_create(pool: sqlx::pool::Pool<sqlx::Sqlite>) {
         }
     }
 }
+
+struct HookedTestModelController;
+
+impl ModelController for HookedTestModelController {
+    type Model = TestModel;
+
+    async fn build_response(
+        conn: &mut MockConn,
+        user_id: Option<UserId>,
+        m: Self::Model,
+    ) -> AppResult<Response> {
+        TestModelController::build_response(conn, user_id, m).await
+    }
+
+    async fn before_create(
+        _conn: &mut MockConn,
+        _user_id: Option<UserId>,
+        data: &i64,
+    ) -> AppResult<()> {
+        match data {
+            5 => Err(AppError::BadRequest(
+                "rejected by before_create".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    async fn after_create(
+        _conn: &mut MockConn,
+        _user_id: Option<UserId>,
+        item: &TestModel,
+    ) -> AppResult<()> {
+        match item.id {
+            6 => Err(AppError::Internal("rejected by after_create".to_string())),
+            _ => Ok(()),
+        }
+    }
+
+    async fn before_write(
+        _conn: &mut MockConn,
+        _user_id: Option<UserId>,
+        _id: i64,
+        data: &i64,
+    ) -> AppResult<()> {
+        match data {
+            5 => Err(AppError::BadRequest("rejected by before_write".to_string())),
+            _ => Ok(()),
+        }
+    }
+
+    async fn before_delete(
+        _conn: &mut MockConn,
+        _user_id: Option<UserId>,
+        id: i64,
+    ) -> AppResult<()> {
+        match id {
+            5 => Err(AppError::BadRequest(
+                "rejected by before_delete".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    async fn after_delete(
+        _conn: &mut MockConn,
+        _user_id: Option<UserId>,
+        item: &TestModel,
+    ) -> AppResult<()> {
+        AFTER_DELETE.lock().unwrap().push((item.id, item.field));
+        Ok(())
+    }
+}
+
+/// Items passed to `HookedTestModelController::after_delete`.
+static AFTER_DELETE: std::sync::Mutex<Vec<(i64, i64)>> = std::sync::Mutex::new(Vec::new());
+
+#[tokio::test]
+async fn test_model_controller_hooks() {
+    for (case, user_id, data, expect) in [
+        ("Hooks allow create", Some(0), 1, Ok("0:1:0")),
+        (
+            "before_create rejects create",
+            Some(0),
+            5,
+            Err(AppError::BadRequest(String::new())),
+        ),
+        (
+            "before_create isn't called for unauthenticated users",
+            None,
+            5,
+            Err(AppError::Auth(AuthError::Unauthenticated)),
+        ),
+        (
+            "after_create rejects created item",
+            Some(0),
+            6,
+            Err(AppError::Internal(String::new())),
+        ),
+    ] {
+        println!("Running test case '{}'", case);
+        let conn = MockConn::new();
+        let output = model_controller_create::<HookedTestModelController>(
+            conn,
+            user_id.map(UserId),
+            HtmxRequest::default(),
+            Form(data),
+        )
+        .await;
+        match (output, expect) {
+            (Ok(response), Ok(expected)) => {
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                assert_eq!(String::from_utf8(body.to_vec()).unwrap(), expected);
+            }
+            (Err(err), Err(expected)) => {
+                assert_eq!(
+                    std::mem::discriminant(&err),
+                    std::mem::discriminant(&expected)
+                );
+            }
+            (output, expect) => {
+                panic!("Got response:\n{:?}\nExpected:\n{:?}", output, expect);
+            }
+        }
+    }
+
+    let conn = MockConn::new();
+    let output = model_controller_write::<HookedTestModelController>(
+        conn,
+        Path(0),
+        Some(UserId(0)),
+        HtmxRequest::default(),
+        Form(5),
+    )
+    .await;
+    assert!(matches!(output, Err(AppError::BadRequest(_))));
+}
+
+#[tokio::test]
+async fn test_model_controller_delete_hooks() {
+    let delete = |id: i64, user_id: Option<i64>| {
+        model_controller_delete::<HookedTestModelController>(
+            MockConn::new(),
+            Path(id),
+            user_id.map(UserId),
+            HtmxRequest::default(),
+        )
+    };
+
+    // before_delete aborts the delete
+    let output = delete(5, Some(5)).await;
+    assert!(matches!(output, Err(AppError::BadRequest(_))));
+    // and isn't called for unauthenticated users
+    let output = delete(5, None).await;
+    assert!(matches!(
+        output,
+        Err(AppError::Auth(AuthError::Unauthenticated))
+    ));
+    assert!(DELETED.lock().unwrap().is_empty());
+    assert!(AFTER_DELETE.lock().unwrap().is_empty());
+
+    let response = delete(1, Some(1)).await.unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(String::from_utf8(body.to_vec()).unwrap(), "1:1:-1");
+    assert_eq!(*DELETED.lock().unwrap(), [1]);
+    // after_delete gets the item returned by the delete
+    assert_eq!(*AFTER_DELETE.lock().unwrap(), [(1, -1)]);
+}
+
+static IDEMPOTENT_CREATE_RESPONSES: std::sync::atomic::AtomicUsize =
+    std::sync::atomic::AtomicUsize::new(0);
+
+struct IdempotentTestModelController;
+
+impl ModelController for IdempotentTestModelController {
+    type Model = TestModel;
+
+    const IDEMPOTENCY_WINDOW: Option<std::time::Duration> =
+        Some(std::time::Duration::from_secs(60));
+
+    async fn build_response(
+        _conn: &mut MockConn,
+        _user_id: Option<UserId>,
+        m: Self::Model,
+    ) -> AppResult<Response> {
+        let count = IDEMPOTENT_CREATE_RESPONSES.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        Ok(format!("{}:{}", m.id, count).into_response())
+    }
+}
+
+#[sqlx::test]
+async fn test_model_controller_create_idempotent(pool: sqlx::pool::Pool<sqlx::Sqlite>) {
+    break_stack::migrations::run(&mut pool.acquire().await.unwrap().into())
+        .await
+        .unwrap();
+
+    for (case, user_id, key, data, expect) in [
+        ("First request is handled", Some(0), Some("a"), 1, Ok("1:0")),
+        ("Same key is replayed", Some(0), Some("a"), 2, Ok("1:0")),
+        (
+            "Same key for other user is handled",
+            Some(1),
+            Some("a"),
+            1,
+            Ok("1:1"),
+        ),
+        (
+            "Request without key is handled",
+            Some(0),
+            None,
+            1,
+            Ok("1:2"),
+        ),
+        (
+            "Request without key is handled again",
+            Some(0),
+            None,
+            1,
+            Ok("1:3"),
+        ),
+        (
+            "Failed request",
+            Some(0),
+            Some("b"),
+            20,
+            Err(AppError::Model(ModelError::DB(sqlx::Error::WorkerCrashed))),
+        ),
+        (
+            "Failed request can be retried",
+            Some(0),
+            Some("b"),
+            2,
+            Ok("2:4"),
+        ),
+        (
+            "Unauthorized request doesn't get the stored response",
+            Some(0),
+            Some("a"),
+            -1,
+            Err(AppError::Auth(AuthError::Unauthorized)),
+        ),
+    ] {
+        println!("Running test case '{}'", case);
+        let conn = MockConn::with_db(pool.acquire().await.unwrap().into());
+        let output = model_controller_create_idempotent::<IdempotentTestModelController>(
+            conn,
+            user_id.map(UserId),
+            HtmxRequest::default(),
+            IdempotentForm {
+                key: key.map(|key| key.to_string()),
+                data,
+            },
+        )
+        .await;
+        match (output, expect) {
+            (Ok(response), Ok(expected)) => {
+                assert_eq!(response.status(), 200);
+                assert_eq!(
+                    response.headers().get("HX-Trigger").unwrap(),
+                    &TestModel::event_created()
+                );
+                let body = response.into_body().collect().await.unwrap().to_bytes();
+                assert_eq!(String::from_utf8(body.to_vec()).unwrap(), expected);
+            }
+            (Err(AppError::Model(ref err)), Err(AppError::Model(ref expected))) => {
+                assert_eq!(format!("{:?}", err), format!("{:?}", expected));
+            }
+            (Err(AppError::Auth(ref err)), Err(AppError::Auth(ref expected))) => {
+                assert_eq!(format!("{:?}", err), format!("{:?}", expected));
+            }
+            (output, expect) => panic!("expected {:?}, got {:?}", expect, output.map(|_| ())),
+        }
+    }
+}
+
+#[sqlx::test]
+async fn test_idempotency_key_in_progress(pool: sqlx::pool::Pool<sqlx::Sqlite>) {
+    let mut conn: DBConn = pool.acquire().await.unwrap().into();
+    break_stack::migrations::run(&mut conn).await.unwrap();
+    let window = std::time::Duration::from_secs(60);
+
+    let first = break_stack::idempotency::begin(&mut conn, "scope", "key", window)
+        .await
+        .unwrap();
+    assert!(first.is_none());
+    let second = break_stack::idempotency::begin(&mut conn, "scope", "key", window)
+        .await
+        .unwrap();
+    assert_eq!(second.unwrap().status(), 409);
+
+    // Keys outside the window are handled again
+    let expired =
+        break_stack::idempotency::begin(&mut conn, "scope", "key", std::time::Duration::ZERO)
+            .await
+            .unwrap();
+    assert!(expired.is_none());
+}
+
+#[sqlx::test]
+async fn test_idempotency_key_abandoned(pool: sqlx::pool::Pool<sqlx::Sqlite>) {
+    let mut conn: DBConn = pool.acquire().await.unwrap().into();
+    break_stack::migrations::run(&mut conn).await.unwrap();
+    let window = std::time::Duration::from_secs(60 * 60);
+
+    let begin = break_stack::idempotency::begin;
+
+    assert!(begin(&mut conn, "scope", "key", window)
+        .await
+        .unwrap()
+        .is_none());
+    // The first request never stored its response, e.g. the app was restarted
+    sqlx::query("UPDATE break_stack_idempotency_keys SET created_at = created_at - ?")
+        .bind(break_stack::idempotency::IN_PROGRESS_TIMEOUT.as_secs() as i64)
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+    assert!(begin(&mut conn, "scope", "key", window)
+        .await
+        .unwrap()
+        .is_none());
+    let second = begin(&mut conn, "scope", "key", window).await.unwrap();
+    assert_eq!(second.unwrap().status(), 409);
+}
+
+#[tokio::test]
+async fn test_idempotent_form_extractor() {
+    use axum::extract::FromRequest;
+
+    #[derive(serde::Deserialize)]
+    struct Data {
+        description: String,
+    }
+
+    let request = axum::http::Request::post("/")
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(axum::body::Body::from(
+            "description=test&idempotency_key=abc",
+        ))
+        .unwrap();
+    let form = IdempotentForm::<Data>::from_request(request, &())
+        .await
+        .unwrap();
+    assert_eq!(form.key.as_deref(), Some("abc"));
+    assert_eq!(form.data.description, "test");
+
+    let request = axum::http::Request::post("/")
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Idempotency-Key", "def")
+        .body(axum::body::Body::from(
+            "description=test&idempotency_key=abc",
+        ))
+        .unwrap();
+    let form = IdempotentForm::<Data>::from_request(request, &())
+        .await
+        .unwrap();
+    assert_eq!(form.key.as_deref(), Some("def"));
+
+    let request = axum::http::Request::post("/")
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(axum::body::Body::from("description=test"))
+        .unwrap();
+    let form = IdempotentForm::<Data>::from_request(request, &())
+        .await
+        .unwrap();
+    assert_eq!(form.key, None);
+
+    let request = axum::http::Request::post("/")
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(axum::body::Body::from("idempotency_key=abc"))
+        .unwrap();
+    let rejection = IdempotentForm::<Data>::from_request(request, &())
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(rejection.status(), 422);
+}
+
+struct FlashTestModelController;
+
+impl ModelController for FlashTestModelController {
+    type Model = TestModel;
+
+    const FLASH_UPDATED: Option<&'static str> = Some("Saved");
+
+    async fn build_response(
+        _conn: &mut MockConn,
+        _user_id: Option<UserId>,
+        m: Self::Model,
+    ) -> AppResult<Response> {
+        Ok(format!("{}", m.id).into_response())
+    }
+}
+
+#[tokio::test]
+async fn test_model_controller_flash() {
+    let response = model_controller_write::<FlashTestModelController>(
+        MockConn::new(),
+        Path(0),
+        Some(UserId(0)),
+        HtmxRequest {
+            is_htmx: true,
+            ..Default::default()
+        },
+        Form(1),
+    )
+    .await
+    .unwrap();
+    let trigger: serde_json::Value = serde_json::from_str(
+        response
+            .headers()
+            .get("HX-Trigger")
+            .unwrap()
+            .to_str()
+            .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(
+        trigger,
+        serde_json::json!({
+            "TestUpdated": null,
+            "flash": {"messages": [{"level": "success", "message": "Saved"}]},
+        })
+    );
+
+    // Controllers without flashes only trigger the model event
+    let response = model_controller_write::<TestModelController>(
+        MockConn::new(),
+        Path(0),
+        Some(UserId(0)),
+        HtmxRequest {
+            is_htmx: true,
+            ..Default::default()
+        },
+        Form(1),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.headers().get("HX-Trigger").unwrap(), "TestUpdated");
+}
+
+#[tokio::test]
+async fn test_mock_conn() {
+    let mut conn = MockConn::new().with_rows([1i64, 2]);
+    conn.rows::<i64>().push(3);
+    conn.rows::<String>().push("a".to_string());
+    assert_eq!(conn.rows::<i64>(), &vec![1, 2, 3]);
+    assert_eq!(conn.rows::<String>(), &vec!["a".to_string()]);
+    assert!(conn.rows::<u8>().is_empty());
+
+    // Subsystems needing a database fail without one
+    assert!(matches!(conn.db(), Err(ModelError::Internal(_))));
+    let output = model_controller_create_idempotent::<IdempotentTestModelController>(
+        conn,
+        Some(UserId(0)),
+        HtmxRequest::default(),
+        IdempotentForm {
+            key: Some("a".to_string()),
+            data: 1,
+        },
+    )
+    .await;
+    assert!(matches!(
+        output,
+        Err(AppError::Model(ModelError::Internal(_)))
+    ));
+}
@@ -1,4 +1,5 @@
 use break_stack::components::*;
+use http_body_util::BodyExt;
 
 #[derive(Component)]
 #[template(source = r#"Hello"#, ext = "html")]
@@ -106,3 +107,46 @@ fn test_recursive() {
         "1 2 3 4 5 ABC"
     );
 }
+
+#[derive(Component)]
+#[template(source = r#"<main>{{ content|safe }}</main>"#, ext = "html")]
+pub struct TestingLayout {
+    content: String,
+}
+impl Layout for TestingLayout {
+    fn from_content(content: String) -> Self {
+        Self { content }
+    }
+}
+
+#[derive(Component)]
+#[template(source = r#"<p>Hello {{ name }}</p>"#, ext = "html")]
+#[component(layout = "TestingLayout")]
+pub struct TestingComponentWithLayout {
+    name: String,
+}
+
+async fn page_body(htmx: &break_stack::htmx::HtmxRequest) -> String {
+    let response = TestingComponentWithLayout {
+        name: "World".into(),
+    }
+    .into_page_response(htmx);
+    assert_eq!(
+        response.headers().get("Vary").unwrap(),
+        "HX-Request, HX-Boosted"
+    );
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn test_component_with_layout() {
+    let mut htmx = break_stack::htmx::HtmxRequest::default();
+    assert_eq!(page_body(&htmx).await, "<main><p>Hello World</p></main>");
+
+    htmx.is_htmx = true;
+    assert_eq!(page_body(&htmx).await, "<p>Hello World</p>");
+
+    htmx.is_boosted = true;
+    assert_eq!(page_body(&htmx).await, "<main><p>Hello World</p></main>");
+}
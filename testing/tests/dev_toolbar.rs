@@ -0,0 +1,285 @@
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use break_stack::auth::*;
+use break_stack::components::*;
+use break_stack::controllers::*;
+use break_stack::dev_toolbar::{self, RECENT_REQUESTS};
+use break_stack::errors::*;
+use break_stack::htmx::HtmxRequest;
+use break_stack::models::*;
+use break_stack::queries;
+use break_stack::testing::TestApp;
+use serde::Deserialize;
+
+#[derive(Clone)]
+struct AppState {
+    db_pool: DBPool,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for DBConn {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let mut conn: DBConn = state.db_pool.acquire().await?.into();
+        queries::attach(parts, &mut conn).await?;
+        Ok(conn)
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for UserId {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<UserId>()
+            .copied()
+            .ok_or(AppError::Auth(AuthError::Unauthenticated))
+    }
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+struct NoteModel {
+    id: i64,
+    text: String,
+}
+impl Model for NoteModel {
+    type ID = i64;
+    type Conn = DBConn;
+
+    const MODEL_NAME: &'static str = "Note";
+}
+impl ModelRead for NoteModel {
+    async fn read(conn: &mut DBConn, id: i64) -> Result<Option<Self>, ModelError> {
+        Ok(sqlx::query_as("SELECT * FROM notes WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&mut **conn)
+            .await?)
+    }
+}
+impl ModelWrite for NoteModel {
+    type Write = NoteWrite;
+
+    async fn write(
+        conn: &mut DBConn,
+        id: i64,
+        data: NoteWrite,
+    ) -> Result<Option<Self>, ModelError> {
+        Ok(
+            sqlx::query_as("UPDATE notes SET text = ? WHERE id = ? RETURNING *")
+                .bind(data.text)
+                .bind(id)
+                .fetch_optional(&mut **conn)
+                .await?,
+        )
+    }
+}
+impl WithOwnerModel for NoteModel {
+    async fn owner(conn: &mut DBConn, id: i64) -> Result<Option<i64>, ModelError> {
+        Ok(sqlx::query_scalar("SELECT owner FROM notes WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&mut **conn)
+            .await?)
+    }
+    async fn all_for_owner(conn: &mut DBConn, user_id: i64) -> Result<Vec<Self>, ModelError> {
+        Ok(sqlx::query_as("SELECT * FROM notes WHERE owner = ?")
+            .bind(user_id)
+            .fetch_all(&mut **conn)
+            .await?)
+    }
+}
+impl OwnerAuthModelRead for NoteModel {}
+impl OwnerAuthModelWrite for NoteModel {}
+
+#[derive(Deserialize)]
+struct NoteWrite {
+    text: String,
+}
+
+#[derive(Component)]
+#[template(source = r#"<p id="note-{{ id }}">{{ text }}</p>"#, ext = "html")]
+struct NoteComponent {
+    id: i64,
+    text: String,
+}
+
+#[derive(Component)]
+#[template(source = r#"<main>{{ content|safe }}</main>"#, ext = "html")]
+struct NotesLayout {
+    content: String,
+}
+impl Layout for NotesLayout {
+    fn from_content(content: String) -> Self {
+        Self { content }
+    }
+}
+
+#[derive(Component)]
+#[template(source = r#"<h1>{{ title }}</h1>"#, ext = "html")]
+#[component(layout = "NotesLayout")]
+struct NotesPageComponent {
+    title: String,
+}
+
+struct NoteController;
+
+impl ModelController for NoteController {
+    type Model = NoteModel;
+
+    async fn build_response(
+        _conn: &mut DBConn,
+        _user_id: Option<UserId>,
+        m: NoteModel,
+    ) -> AppResult<Response> {
+        Ok(NoteComponent {
+            id: m.id,
+            text: m.text,
+        }
+        .into_response())
+    }
+}
+
+#[tokio::test]
+async fn test_dev_toolbar() {
+    let app = TestApp::new(|db_pool| {
+        Router::new()
+            .route(
+                "/notes/:id",
+                get(model_controller_read::<NoteController>)
+                    .post(model_controller_write::<NoteController>),
+            )
+            .route(
+                "/notes",
+                get(|htmx: HtmxRequest| async move {
+                    NotesPageComponent {
+                        title: "Notes".into(),
+                    }
+                    .into_page_response(&htmx)
+                }),
+            )
+            .route(
+                "/toolbar",
+                get(|| async { dev_toolbar::render_toolbar() })
+                    .layer(axum::middleware::from_fn(dev_toolbar::skip_recording)),
+            )
+            .layer(axum::middleware::from_fn(dev_toolbar::record_requests))
+            .with_state(AppState { db_pool })
+    })
+    .await;
+    let mut conn = app.conn().await;
+    for sql in [
+        "CREATE TABLE notes (id INTEGER PRIMARY KEY, owner INTEGER NOT NULL, text TEXT NOT NULL)",
+        "INSERT INTO notes (owner, text) VALUES (1, 'Milk')",
+    ] {
+        sqlx::query(sql).execute(&mut *conn).await.unwrap();
+    }
+    drop(conn);
+
+    app.get("/notes/1")
+        .user(UserId(1))
+        .htmx()
+        .send()
+        .await
+        .assert_ok();
+    let request = &dev_toolbar::recent_requests()[0];
+    assert_eq!(
+        (request.method.as_str(), request.uri.as_str()),
+        ("GET", "/notes/1")
+    );
+    assert!(request.is_htmx);
+    assert_eq!(request.status, 200);
+    assert_eq!(request.controllers, ["dev_toolbar::NoteController"]);
+    assert_eq!(request.components, ["dev_toolbar::NoteComponent"]);
+    assert_eq!(request.auth, ["can_read Note for user 1: Ok(())"]);
+    assert_eq!(
+        request
+            .queries
+            .iter()
+            .map(|query| query.expanded.as_str())
+            .collect::<Vec<_>>(),
+        [
+            "SELECT owner FROM notes WHERE id = 1",
+            "SELECT * FROM notes WHERE id = 1"
+        ]
+    );
+    assert!(request.queries.iter().all(|query| query.duration.is_some()));
+    assert!(request.triggers.is_empty());
+
+    app.post("/notes/1")
+        .user(UserId(2))
+        .form(&[("text", "Eggs")])
+        .send()
+        .await
+        .assert_status(StatusCode::FORBIDDEN);
+    let request = &dev_toolbar::recent_requests()[0];
+    assert_eq!(
+        request.auth,
+        ["can_write Note for user 2: Err(Unauthorized)"]
+    );
+    assert!(request.components.is_empty());
+
+    app.post("/notes/1")
+        .user(UserId(1))
+        .htmx()
+        .form(&[("text", "<b>Eggs</b>")])
+        .send()
+        .await
+        .assert_ok()
+        .assert_triggered("NoteUpdated");
+    let request = &dev_toolbar::recent_requests()[0];
+    assert_eq!(request.triggers, ["NoteUpdated"]);
+    assert_eq!(request.queries.len(), 2);
+
+    let html = dev_toolbar::render_toolbar();
+    assert!(html.contains("POST /notes/1 200 htmx"));
+    assert!(html.contains("<li>NoteUpdated</li>"));
+    assert!(html.contains(
+        "<code>UPDATE notes SET text = &#x27;&lt;b&gt;Eggs&lt;/b&gt;&#x27; WHERE id = 1 RETURNING *</code>"
+    ));
+    assert!(html.contains("POST /notes/1 403"));
+    assert!(html.contains(r#"<details open>"#));
+
+    // Components rendered as pages are recorded, with their layout for full pages
+    app.get("/notes").htmx().send().await.assert_ok();
+    assert_eq!(
+        dev_toolbar::recent_requests()[0].components,
+        ["dev_toolbar::NotesPageComponent"]
+    );
+    app.get("/notes").send().await.assert_ok();
+    assert_eq!(
+        dev_toolbar::recent_requests()[0].components,
+        [
+            "dev_toolbar::NotesPageComponent",
+            "dev_toolbar::NotesLayout"
+        ]
+    );
+
+    // Only the most recent requests are kept, and the requests marked by `skip_recording`
+    // aren't recorded
+    for _ in 0..RECENT_REQUESTS {
+        app.get("/notes/1").user(UserId(1)).send().await.assert_ok();
+    }
+    app.get("/toolbar")
+        .send()
+        .await
+        .assert_ok()
+        .assert_contains("GET /notes/1 200");
+    let requests = dev_toolbar::recent_requests();
+    assert_eq!(requests.len(), RECENT_REQUESTS);
+    assert!(requests.iter().all(|request| request.uri == "/notes/1"));
+    assert!(requests.iter().all(|request| request.method == "GET"));
+}
@@ -0,0 +1,194 @@
+use axum::{
+    extract::Path,
+    http::header,
+    response::{IntoResponse, Response},
+};
+use break_stack::auth::*;
+use break_stack::controllers::*;
+use break_stack::errors::*;
+use break_stack::etag::*;
+use break_stack::htmx::HtmxRequest;
+use break_stack::models::*;
+use http_body_util::BodyExt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[test]
+fn test_if_none_match() {
+    let strong = ETag::from_body(b"body");
+    let weak = ETag::from_version(&["1"]);
+    assert!(strong.as_str().starts_with('"'));
+    assert!(weak.as_str().starts_with("W/\""));
+    assert_eq!(strong, ETag::from_body(b"body"));
+    assert_ne!(strong, ETag::from_body(b"other body"));
+    // The tags are stable across Rust releases
+    assert_eq!(strong.as_str(), "\"cd4de79bc6c93295\"");
+    assert_ne!(
+        ETag::from_version(&["ab", "c"]),
+        ETag::from_version(&["a", "bc"])
+    );
+
+    assert!(!IfNoneMatch::default().matches(&strong));
+    assert!(IfNoneMatch::new(strong.as_str()).matches(&strong));
+    assert!(IfNoneMatch::new(format!("W/{}", strong.as_str())).matches(&strong));
+    assert!(IfNoneMatch::new(weak.as_str()).matches(&weak));
+    assert!(IfNoneMatch::new(format!("\"a\", {}", weak.as_str())).matches(&weak));
+    assert!(IfNoneMatch::new("*").matches(&weak));
+    assert!(!IfNoneMatch::new(weak.as_str()).matches(&strong));
+}
+
+#[tokio::test]
+async fn test_if_none_match_respond() {
+    let etag = ETag::from_body(b"body");
+
+    let response = IfNoneMatch::default()
+        .respond("body".into_response())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers().get(header::ETAG).unwrap(), etag.as_str());
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(&body[..], b"body");
+
+    let response = IfNoneMatch::new(etag.as_str())
+        .respond("body".into_response())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 304);
+    assert_eq!(response.headers().get(header::ETAG).unwrap(), etag.as_str());
+
+    // The 304 has the same `Vary` as the full response
+    let response = IfNoneMatch::new(etag.as_str())
+        .respond(([(header::VARY, "HX-Request")], "body").into_response())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 304);
+    assert_eq!(response.headers().get(header::VARY).unwrap(), "HX-Request");
+
+    // Large bodies aren't buffered
+    let large = "a".repeat(MAX_TAGGED_BODY_SIZE as usize + 1);
+    let response = IfNoneMatch::default()
+        .respond(large.into_response())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert!(response.headers().get(header::ETAG).is_none());
+
+    let response = IfNoneMatch::new(etag.as_str())
+        .respond((axum::http::StatusCode::NOT_FOUND, "body").into_response())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+    assert!(response.headers().get(header::ETAG).is_none());
+}
+
+struct TestModel {
+    id: i64,
+    version: Option<i64>,
+}
+impl Model for TestModel {
+    type ID = i64;
+    type Conn = DBConn;
+
+    const MODEL_NAME: &'static str = "Test";
+
+    fn version(&self) -> Option<String> {
+        self.version.map(|version| version.to_string())
+    }
+}
+impl ModelRead for TestModel {
+    async fn read(_conn: &mut DBConn, id: i64) -> Result<Option<Self>, ModelError> {
+        // Odd ids are versioned
+        Ok(Some(Self {
+            id,
+            version: (id % 2 == 1).then_some(1),
+        }))
+    }
+}
+impl AuthModelRead for TestModel {
+    async fn can_read(
+        _conn: &mut DBConn,
+        _id: i64,
+        _user_id: Option<UserId>,
+    ) -> Result<(), AuthError> {
+        Ok(())
+    }
+}
+
+static RENDERS: AtomicUsize = AtomicUsize::new(0);
+
+struct TestModelController;
+impl ModelController for TestModelController {
+    type Model = TestModel;
+
+    async fn build_response(
+        _conn: &mut DBConn,
+        _user_id: Option<UserId>,
+        m: Self::Model,
+    ) -> AppResult<Response> {
+        RENDERS.fetch_add(1, Ordering::SeqCst);
+        Ok(format!("{}", m.id).into_response())
+    }
+}
+
+async fn read(
+    pool: &sqlx::Pool<sqlx::Sqlite>,
+    id: i64,
+    user_id: Option<i64>,
+    if_none_match: IfNoneMatch,
+) -> Response {
+    model_controller_read::<TestModelController>(
+        pool.acquire().await.unwrap().into(),
+        Path(id),
+        user_id.map(UserId),
+        HtmxRequest::default(),
+        if_none_match,
+    )
+    .await
+    .unwrap()
+}
+
+fn etag(response: &Response) -> String {
+    response
+        .headers()
+        .get(header::ETAG)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string()
+}
+
+#[sqlx::test]
+async fn test_model_controller_read_etag(pool: sqlx::Pool<sqlx::Sqlite>) {
+    // Unversioned models are tagged from the body
+    let response = read(&pool, 0, Some(0), IfNoneMatch::default()).await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(etag(&response), ETag::from_body(b"0").as_str());
+
+    let response = read(&pool, 0, Some(0), IfNoneMatch::new(etag(&response))).await;
+    assert_eq!(response.status(), 304);
+
+    // Versioned models skip rendering when the version matches
+    let response = read(&pool, 1, Some(0), IfNoneMatch::default()).await;
+    assert_eq!(response.status(), 200);
+    let versioned = etag(&response);
+    assert!(versioned.starts_with("W/"));
+    assert_eq!(
+        response.headers().get(header::VARY).unwrap(),
+        "HX-Request, HX-Boosted"
+    );
+
+    let renders = RENDERS.load(Ordering::SeqCst);
+    let response = read(&pool, 1, Some(0), IfNoneMatch::new(&versioned)).await;
+    assert_eq!(response.status(), 304);
+    assert_eq!(etag(&response), versioned);
+    assert_eq!(RENDERS.load(Ordering::SeqCst), renders);
+    assert_eq!(
+        response.headers().get(header::VARY).unwrap(),
+        "HX-Request, HX-Boosted"
+    );
+
+    // Other users get another tag
+    let response = read(&pool, 1, Some(1), IfNoneMatch::new(&versioned)).await;
+    assert_eq!(response.status(), 200);
+    assert_ne!(etag(&response), versioned);
+}
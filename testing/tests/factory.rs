@@ -0,0 +1,139 @@
+use break_stack::errors::ModelError;
+use break_stack::models::testutils::{model_read_test_cases, MockConn};
+use break_stack::models::*;
+use break_stack::testing::{Factory, Fixtures};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, PartialEq)]
+struct PostModel {
+    id: i64,
+    title: String,
+    views: i64,
+    published: bool,
+}
+impl Model for PostModel {
+    type ID = i64;
+    type Conn = MockConn;
+
+    const MODEL_NAME: &'static str = "Post";
+}
+impl ModelRead for PostModel {
+    async fn read(conn: &mut MockConn, id: i64) -> Result<Option<Self>, ModelError> {
+        Ok(conn.rows::<Self>().iter().find(|m| m.id == id).cloned())
+    }
+}
+impl ModelCreate for PostModel {
+    type Create = PostCreate;
+
+    async fn create(conn: &mut MockConn, data: PostCreate) -> Result<Self, ModelError> {
+        if data.title.is_empty() {
+            return Err(ModelError::Internal("title is required".to_string()));
+        }
+        let rows = conn.rows::<Self>();
+        let post = Self {
+            // Ids that don't start at 1, like in a database shared by several tests
+            id: 100 + rows.len() as i64,
+            title: data.title,
+            views: data.views,
+            published: data.published,
+        };
+        rows.push(post.clone());
+        Ok(post)
+    }
+}
+
+#[derive(Deserialize, ModelFactory)]
+#[model_factory(model = "PostModel")]
+struct PostCreate {
+    #[factory(sequence = "Post {}")]
+    title: String,
+    #[serde(default)]
+    views: i64,
+    #[serde(default = "published")]
+    #[factory(default = "true")]
+    published: bool,
+}
+
+fn published() -> bool {
+    true
+}
+
+#[tokio::test]
+async fn test_factory() {
+    let mut conn = MockConn::new();
+
+    let post = Factory::<PostCreate>::new().create(&mut conn).await;
+    assert!(post.title.starts_with("Post "));
+    assert_eq!((post.views, post.published), (0, true));
+
+    let posts = Factory::<PostCreate>::new()
+        .with(|post| post.published = false)
+        .sequence(|post, n| post.views = n as i64 * 10)
+        .create_many(&mut conn, 3)
+        .await;
+    assert_eq!(posts.len(), 3);
+    for pair in posts.windows(2) {
+        // The sequence is shared with the other tests, so the numbers only have to increase
+        assert!(pair[0].views < pair[1].views);
+        assert_ne!(pair[0].title, pair[1].title);
+    }
+    assert!(posts.iter().all(|post| !post.published));
+    assert_eq!(conn.rows::<PostModel>().len(), 4);
+
+    let data = Factory::<PostCreate>::new()
+        .with(|post| post.title = "Hello".to_string())
+        .build();
+    assert_eq!(data.title, "Hello");
+    assert_eq!(conn.rows::<PostModel>().len(), 4);
+}
+
+#[tokio::test]
+#[should_panic(expected = "factory failed to create Post: internal error: title is required")]
+async fn test_factory_create_error() {
+    Factory::<PostCreate>::new()
+        .with(|post| post.title.clear())
+        .create(&mut MockConn::new())
+        .await;
+}
+
+#[tokio::test]
+async fn test_fixtures() {
+    let mut conn = MockConn::new().with_rows([PostModel {
+        id: 1,
+        title: "Existing".to_string(),
+        views: 0,
+        published: true,
+    }]);
+
+    let posts = Fixtures::<PostCreate>::load(&mut conn, "tests/fixtures/posts.json").await;
+    let more = Fixtures::<PostCreate>::parse(
+        &mut conn,
+        r#"
+popular:
+  title: Popular
+  views: 1000
+"#,
+    )
+    .await;
+
+    assert_eq!(posts.ids(), vec![101, 102]);
+    assert_eq!(posts.get("draft").title, "Draft");
+    assert_eq!(
+        posts.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+        vec!["welcome", "draft"]
+    );
+
+    model_read_test_cases!(PostModel, &mut conn, [
+        "fixture from json": posts.id("welcome") => Some(PostModel { views: 10, published: true, .. }),
+        "default overridden by fixture": posts.id("draft") => Some(PostModel { published: false, .. }),
+        "fixture from yaml": more.id("popular") => Some(PostModel { views: 1000, .. }),
+    ]);
+}
+
+#[tokio::test]
+#[should_panic(expected = "no fixture \"missing\", the fixtures are [\"welcome\", \"draft\"]")]
+async fn test_fixtures_missing() {
+    let mut conn = MockConn::new();
+    let posts = Fixtures::<PostCreate>::load(&mut conn, "tests/fixtures/posts.json").await;
+    posts.id("missing");
+}
@@ -0,0 +1,212 @@
+use axum::{
+    body::Body,
+    extract::FromRequestParts,
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use break_stack::errors::*;
+use break_stack::flash::*;
+use break_stack::htmx::{HtmxRequest, HtmxResponse};
+use tower::ServiceExt;
+
+fn htmx() -> HtmxRequest {
+    HtmxRequest {
+        is_htmx: true,
+        ..Default::default()
+    }
+}
+
+fn trigger(response: &Response) -> serde_json::Value {
+    serde_json::from_str(
+        response
+            .headers()
+            .get("HX-Trigger")
+            .unwrap()
+            .to_str()
+            .unwrap(),
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_flash_response_htmx() {
+    let inner = HtmxResponse::new("body")
+        .trigger("TodoItemUpdated")
+        .into_response();
+    let inner = FlashResponse::new(inner, &htmx())
+        .success("Saved")
+        .into_response();
+    let response = FlashResponse::new(inner, &htmx())
+        .flash(Flash::warning("Almost full"))
+        .into_response();
+    assert_eq!(
+        trigger(&response),
+        serde_json::json!({
+            "TodoItemUpdated": null,
+            "flash": {"messages": [
+                {"level": "success", "message": "Saved"},
+                {"level": "warning", "message": "Almost full"},
+            ]},
+        })
+    );
+    assert!(response.headers().get(header::SET_COOKIE).is_none());
+}
+
+#[test]
+fn test_flash_response_cookie() {
+    let response = FlashResponse::new("body", &HtmxRequest::default())
+        .error("Failed; \"try again\"")
+        .into_response();
+    assert!(response.headers().get("HX-Trigger").is_none());
+    let cookie = response
+        .headers()
+        .get(header::SET_COOKIE)
+        .unwrap()
+        .to_str()
+        .unwrap();
+    let value = cookie
+        .strip_prefix("break_stack_flash=")
+        .unwrap()
+        .split(';')
+        .next()
+        .unwrap();
+    assert!(!value.contains([' ', '"', ',', ';']));
+    let (signature, value) = value.split_once('.').unwrap();
+    assert_eq!(signature.len(), 64);
+    assert_eq!(
+        value,
+        "%7B%22messages%22%3A%5B%7B%22level%22%3A%22error%22%2C%22message%22%3A%22Failed%3B%20%5C%22try%20again%5C%22%22%7D%5D%7D"
+    );
+
+    let response = FlashResponse::new("body", &HtmxRequest::default()).into_response();
+    assert!(response.headers().get(header::SET_COOKIE).is_none());
+}
+
+#[tokio::test]
+async fn test_cookie_flashes() {
+    let mut inner = "body".into_response();
+    inner.headers_mut().append(
+        header::SET_COOKIE,
+        HeaderValue::from_static("break_stack_session=abc; Path=/"),
+    );
+    let response = FlashResponse::new(inner, &HtmxRequest::default())
+        .error("<b>Failed</b>")
+        .into_response();
+    let cookies: Vec<_> = response
+        .headers()
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .map(|cookie| cookie.to_str().unwrap().split(';').next().unwrap())
+        .collect();
+    assert_eq!(cookies.len(), 2);
+    assert!(cookies[0].starts_with("break_stack_session="));
+
+    let (mut parts, _) = Request::get("/")
+        .header(header::COOKIE, format!("theme=dark; {}", cookies[1]))
+        .body(())
+        .unwrap()
+        .into_parts();
+    let flashes = CookieFlashes::from_request_parts(&mut parts, &())
+        .await
+        .unwrap();
+    assert_eq!(flashes.flashes(), &[Flash::error("<b>Failed</b>")]);
+    let html = FlashToastsComponentRef::new(flashes.flashes()).to_string();
+    assert!(html.contains(
+        r#"<div class="break-stack-flash break-stack-flash-error" data-break-stack-flash="error">&lt;b&gt;Failed&lt;/b&gt;</div>"#
+    ));
+    assert!(html.contains(flash_toasts_tag()));
+    let response = flashes.apply("page".into_response());
+    assert_eq!(
+        response.headers().get(header::SET_COOKIE).unwrap(),
+        "break_stack_flash=; Path=/; Max-Age=0"
+    );
+
+    let (mut parts, _) = Request::get("/").body(()).unwrap().into_parts();
+    let flashes = CookieFlashes::from_request_parts(&mut parts, &())
+        .await
+        .unwrap();
+    assert!(flashes.flashes().is_empty());
+    let response = flashes.apply("page".into_response());
+    assert!(response.headers().get(header::SET_COOKIE).is_none());
+
+    // Cookies that weren't signed by the app are ignored, and cleared
+    let (_, value) = cookies[1].split_once('.').unwrap();
+    for forged in [value.to_string(), format!("{}.{}", "00".repeat(32), value)] {
+        let (mut parts, _) = Request::get("/")
+            .header(header::COOKIE, format!("break_stack_flash={}", forged))
+            .body(())
+            .unwrap()
+            .into_parts();
+        let flashes = CookieFlashes::from_request_parts(&mut parts, &())
+            .await
+            .unwrap();
+        assert!(flashes.flashes().is_empty());
+        let response = flashes.apply("page".into_response());
+        assert!(response.headers().get(header::SET_COOKIE).is_some());
+    }
+}
+
+#[tokio::test]
+async fn test_flash_errors() {
+    let app = Router::new()
+        .route("/ok", get(|| async { "ok" }))
+        .route("/not-found", get(|| async { AppError::NotFound }))
+        .route(
+            "/html-error",
+            get(|| async { (StatusCode::BAD_REQUEST, "<p>Invalid</p>") }),
+        )
+        .route(
+            "/flashed-error",
+            get(|| async {
+                FlashResponse::new(StatusCode::CONFLICT, &htmx()).error("Already exists")
+            }),
+        )
+        .layer(middleware::from_fn(flash_errors));
+    let request = |uri: &str, is_htmx: bool| {
+        let mut request = Request::get(uri.to_string());
+        if is_htmx {
+            request = request.header("HX-Request", "true");
+        }
+        request.body(Body::empty()).unwrap()
+    };
+
+    let response = app.clone().oneshot(request("/ok", true)).await.unwrap();
+    assert!(response.headers().get("HX-Trigger").is_none());
+
+    let response = app
+        .clone()
+        .oneshot(request("/not-found", false))
+        .await
+        .unwrap();
+    assert!(response.headers().get("HX-Trigger").is_none());
+
+    let response = app
+        .clone()
+        .oneshot(request("/not-found", true))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        trigger(&response),
+        serde_json::json!({"flash": {"messages": [{"level": "error", "message": "Not found"}]}})
+    );
+
+    let response = app
+        .clone()
+        .oneshot(request("/html-error", true))
+        .await
+        .unwrap();
+    assert_eq!(
+        trigger(&response),
+        serde_json::json!({"flash": {"messages": [{"level": "error", "message": "Something went wrong"}]}})
+    );
+
+    let response = app.oneshot(request("/flashed-error", true)).await.unwrap();
+    assert_eq!(
+        trigger(&response),
+        serde_json::json!({"flash": {"messages": [{"level": "error", "message": "Already exists"}]}})
+    );
+}
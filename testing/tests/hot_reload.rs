@@ -0,0 +1,22 @@
+use axum::response::IntoResponse;
+use break_stack::hot_reload::{build_events, hot_reload_build_id};
+use http_body_util::BodyExt;
+
+#[tokio::test]
+async fn test_build_events() {
+    let response = build_events().await.into_response();
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "text/event-stream"
+    );
+
+    let mut body = response.into_body();
+    let frame = body.frame().await.unwrap().unwrap();
+    assert_eq!(
+        std::str::from_utf8(frame.data_ref().unwrap()).unwrap(),
+        format!("event: build\ndata: {}\n\n", hot_reload_build_id())
+    );
+    // The connection stays open, the script reconnects when the server restarts
+    let next = tokio::time::timeout(std::time::Duration::from_millis(50), body.frame()).await;
+    assert!(next.is_err());
+}
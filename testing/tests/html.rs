@@ -0,0 +1,113 @@
+use break_stack::components::*;
+use break_stack::testing::{assert_html_eq, Dom};
+
+#[derive(Component)]
+#[template(
+    source = r#"<form hx-post="/items/{{ id }}" hx-target="closest li">
+    <input type="hidden" name="id" value="{{ id }}">
+    <input type="text" name="name" value="{{ name }}">
+    <textarea name="notes">{{ notes }}</textarea>
+    <select name="priority">
+        <option value="low">Low</option>
+        <option value="high" {% if high %}selected{% endif %}>High</option>
+    </select>
+    <input type="checkbox" name="done" {% if done %}checked{% endif %}>
+    <input type="text" name="legacy" disabled>
+    <button type="submit" name="save">Save</button>
+</form>
+<ul>
+    {% for tag in tags.clone() %}<li class="tag">  {{ tag }}
+    </li>{% endfor %}
+</ul>"#,
+    ext = "html"
+)]
+struct ItemFormComponent {
+    id: i64,
+    name: String,
+    notes: String,
+    high: bool,
+    done: bool,
+    tags: Vec<String>,
+}
+
+fn component() -> ItemFormComponent {
+    ItemFormComponent {
+        id: 1,
+        name: "Milk & eggs".to_string(),
+        notes: "From the store".to_string(),
+        high: true,
+        done: false,
+        tags: vec!["food".to_string(), "weekly shop".to_string()],
+    }
+}
+
+#[test]
+fn test_dom_queries() {
+    let item = component();
+    for dom in [
+        Dom::render(&item),
+        Dom::render(ItemFormComponentRef::from(&item)),
+    ] {
+        dom.assert_attr("form", "hx-post", "/items/1")
+            .assert_attr("form", "hx-target", "closest li")
+            .assert_attr("input[type=text]:not([disabled])", "name", "name")
+            .assert_count("li.tag", 2)
+            .assert_text("li.tag:last-child", "weekly shop")
+            .assert_exists("button[name=save]")
+            .assert_missing("a")
+            .assert_form_fields("form", &["id", "name", "notes", "priority"])
+            .assert_form_value("form", "name", "Milk & eggs")
+            .assert_form_value("form", "priority", "high");
+
+        let tags: Vec<String> = dom.select("li.tag").iter().map(|li| li.text()).collect();
+        assert_eq!(tags, vec!["food", "weekly shop"]);
+        assert_eq!(dom.find("form").select("input").len(), 4);
+    }
+
+    let dom = Dom::render(&ItemFormComponent {
+        high: false,
+        done: true,
+        ..component()
+    });
+    assert_eq!(
+        dom.form_values("form"),
+        vec![
+            ("id".to_string(), "1".to_string()),
+            ("name".to_string(), "Milk & eggs".to_string()),
+            ("notes".to_string(), "From the store".to_string()),
+            ("priority".to_string(), "low".to_string()),
+            ("done".to_string(), "on".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_assert_html_eq() {
+    assert_html_eq(
+        r#"<div  class="a" id="b">
+            <span>Hello
+            world</span>
+        </div>"#,
+        r#"<div id="b" class="a"><span>Hello world</span></div>"#,
+    );
+}
+
+#[test]
+#[should_panic(
+    expected = "  <div>\n-   <span>\n+   <b>\n      Hello\n-   </span>\n+   </b>\n  </div>"
+)]
+fn test_assert_html_eq_diff() {
+    assert_html_eq("<div><b>Hello</b></div>", "<div><span>Hello</span></div>");
+}
+
+#[test]
+#[should_panic(expected = "--- expected\n+++ actual\n- weekly\n+ weekly shop")]
+fn test_assert_text_diff() {
+    Dom::render(&component()).assert_text("li.tag:last-child", "weekly");
+}
+
+#[test]
+#[should_panic(expected = "expected one element matching \"li.tag\", found 2")]
+fn test_find_multiple() {
+    Dom::render(&component()).find("li.tag");
+}
@@ -0,0 +1,111 @@
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use break_stack::htmx::*;
+
+fn header<'a>(response: &'a axum::response::Response, name: &str) -> Option<&'a str> {
+    response
+        .headers()
+        .get(name)
+        .map(|value| value.to_str().unwrap())
+}
+
+#[test]
+fn test_htmx_request_from_headers() {
+    let request = HtmxRequest::from_headers(&HeaderMap::new());
+    assert!(!request.is_htmx);
+    assert!(!request.is_boosted);
+    assert_eq!(request.target, None);
+
+    let mut headers = HeaderMap::new();
+    headers.insert("HX-Request", "true".parse().unwrap());
+    headers.insert("HX-Boosted", "true".parse().unwrap());
+    headers.insert("HX-Target", "item-1".parse().unwrap());
+    headers.insert("HX-Trigger", "edit-button".parse().unwrap());
+    headers.insert("HX-Current-URL", "http://localhost/".parse().unwrap());
+    let request = HtmxRequest::from_headers(&headers);
+    assert!(request.is_htmx);
+    assert!(request.is_boosted);
+    assert!(!request.is_history_restore);
+    assert_eq!(request.target.as_deref(), Some("item-1"));
+    assert_eq!(request.trigger.as_deref(), Some("edit-button"));
+    assert_eq!(request.current_url.as_deref(), Some("http://localhost/"));
+    assert_eq!(request.trigger_name, None);
+}
+
+#[test]
+fn test_htmx_response_triggers() {
+    let response = HtmxResponse::new("body")
+        .trigger("TodoItemCreated")
+        .trigger("TodoItemUpdated")
+        .trigger_after_settle("Settled")
+        .into_response();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        header(&response, "HX-Trigger"),
+        Some("TodoItemCreated, TodoItemUpdated")
+    );
+    assert_eq!(
+        header(&response, "HX-Trigger-After-Settle"),
+        Some("Settled")
+    );
+    assert_eq!(header(&response, "HX-Trigger-After-Swap"), None);
+}
+
+#[test]
+fn test_htmx_response_trigger_with_detail() {
+    let response = HtmxResponse::new("body")
+        .trigger("TodoItemCreated")
+        .trigger_with_detail("showMessage", ("info", "Saved"))
+        .into_response();
+    let trigger: serde_json::Value =
+        serde_json::from_str(header(&response, "HX-Trigger").unwrap()).unwrap();
+    assert_eq!(
+        trigger,
+        serde_json::json!({"TodoItemCreated": null, "showMessage": ["info", "Saved"]})
+    );
+}
+
+#[test]
+fn test_htmx_response_merges_existing_triggers() {
+    let inner = HtmxResponse::new("body")
+        .trigger_with_detail("showMessage", "Saved")
+        .into_response();
+    let response = HtmxResponse::new(inner)
+        .trigger("TodoItemUpdated")
+        .into_response();
+    let trigger: serde_json::Value =
+        serde_json::from_str(header(&response, "HX-Trigger").unwrap()).unwrap();
+    assert_eq!(
+        trigger,
+        serde_json::json!({"showMessage": "Saved", "TodoItemUpdated": null})
+    );
+
+    let inner = ([("HX-Trigger", "a, b")], "body").into_response();
+    let response = HtmxResponse::new(inner).trigger("c").into_response();
+    assert_eq!(header(&response, "HX-Trigger"), Some("a, b, c"));
+}
+
+#[test]
+fn test_htmx_response_headers() {
+    let response = HtmxResponse::new("body")
+        .redirect("/login")
+        .push_url("/items/1")
+        .retarget("#items")
+        .reswap("beforeend")
+        .refresh()
+        .into_response();
+    assert_eq!(header(&response, "HX-Redirect"), Some("/login"));
+    assert_eq!(header(&response, "HX-Push-Url"), Some("/items/1"));
+    assert_eq!(header(&response, "HX-Retarget"), Some("#items"));
+    assert_eq!(header(&response, "HX-Reswap"), Some("beforeend"));
+    assert_eq!(header(&response, "HX-Refresh"), Some("true"));
+    assert_eq!(header(&response, "HX-Trigger"), None);
+}
+
+#[test]
+fn test_htmx_response_invalid_header() {
+    let response = HtmxResponse::new("body")
+        .redirect("/\n")
+        .try_into_response();
+    assert!(response.is_err());
+}
@@ -0,0 +1,160 @@
+use break_stack::errors::*;
+use break_stack::jobs::{self, testutils::*, Job, Worker};
+use break_stack::models::DBConn;
+use serde::{Deserialize, Serialize};
+
+/// Logs a run to `job_runs`, and fails until it has been run more than `fail_times` times.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct LogJob {
+    key: String,
+    fail_times: i64,
+}
+
+impl Job for LogJob {
+    const NAME: &'static str = "log";
+    const MAX_ATTEMPTS: u32 = 3;
+
+    async fn run(self, conn: &mut DBConn) -> AppResult<()> {
+        sqlx::query("INSERT INTO job_runs (key) VALUES (?)")
+            .bind(&self.key)
+            .execute(&mut **conn)
+            .await?;
+        let runs: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM job_runs WHERE key = ?")
+            .bind(&self.key)
+            .fetch_one(&mut **conn)
+            .await?;
+        if runs <= self.fail_times {
+            return Err(AppError::Internal(format!("failed run {}", runs)));
+        }
+        Ok(())
+    }
+}
+
+async fn setup(pool: &sqlx::Pool<sqlx::Sqlite>) -> DBConn {
+    let mut conn: DBConn = pool.acquire().await.unwrap().into();
+    break_stack::migrations::run(&mut conn).await.unwrap();
+    // Running the migrations twice is a no-op
+    break_stack::migrations::run(&mut conn).await.unwrap();
+    sqlx::query("CREATE TABLE job_runs (key TEXT NOT NULL)")
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+    conn
+}
+
+async fn runs(conn: &mut DBConn, key: &str) -> i64 {
+    sqlx::query_scalar("SELECT COUNT(*) FROM job_runs WHERE key = ?")
+        .bind(key)
+        .fetch_one(&mut **conn)
+        .await
+        .unwrap()
+}
+
+fn log_job(key: &str, fail_times: i64) -> LogJob {
+    LogJob {
+        key: key.to_string(),
+        fail_times,
+    }
+}
+
+#[sqlx::test]
+async fn test_jobs_run(pool: sqlx::Pool<sqlx::Sqlite>) {
+    let mut conn = setup(&pool).await;
+    let worker = Worker::new(pool.clone()).register::<LogJob>();
+
+    jobs::enqueue(&mut conn, &log_job("a", 0)).await.unwrap();
+    jobs::enqueue(&mut conn, &log_job("b", 0)).await.unwrap();
+    assert_eq!(
+        queued_jobs::<LogJob>(&mut conn).await.unwrap(),
+        vec![log_job("a", 0), log_job("b", 0)]
+    );
+
+    assert_eq!(worker.run_pending().await.unwrap(), 2);
+    assert_eq!(runs(&mut conn, "a").await, 1);
+    assert_eq!(runs(&mut conn, "b").await, 1);
+    assert!(queued_jobs::<LogJob>(&mut conn).await.unwrap().is_empty());
+    assert!(!worker.run_next().await.unwrap());
+}
+
+#[sqlx::test]
+async fn test_jobs_claimed_once(pool: sqlx::Pool<sqlx::Sqlite>) {
+    let mut conn = setup(&pool).await;
+    jobs::enqueue(&mut conn, &log_job("a", 0)).await.unwrap();
+
+    let worker_a = Worker::new(pool.clone()).register::<LogJob>();
+    let worker_b = Worker::new(pool.clone()).register::<LogJob>();
+    let (ran_a, ran_b) = tokio::join!(worker_a.run_next(), worker_b.run_next());
+    assert!(ran_a.unwrap() ^ ran_b.unwrap());
+    assert_eq!(runs(&mut conn, "a").await, 1);
+}
+
+#[sqlx::test]
+async fn test_jobs_delayed(pool: sqlx::Pool<sqlx::Sqlite>) {
+    let mut conn = setup(&pool).await;
+    let worker = Worker::new(pool.clone()).register::<LogJob>();
+
+    jobs::enqueue_in(
+        &mut conn,
+        &log_job("a", 0),
+        std::time::Duration::from_secs(60),
+    )
+    .await
+    .unwrap();
+    assert_eq!(worker.run_pending().await.unwrap(), 0);
+    assert_eq!(runs(&mut conn, "a").await, 0);
+
+    assert_eq!(run_all_jobs(&worker).await.unwrap(), 1);
+    assert_eq!(runs(&mut conn, "a").await, 1);
+}
+
+#[sqlx::test]
+async fn test_jobs_retry(pool: sqlx::Pool<sqlx::Sqlite>) {
+    let mut conn = setup(&pool).await;
+    let worker = Worker::new(pool.clone()).register::<LogJob>();
+
+    jobs::enqueue(&mut conn, &log_job("a", 2)).await.unwrap();
+
+    // The failed job is scheduled with a backoff, so it isn't run again right away
+    assert_eq!(worker.run_pending().await.unwrap(), 1);
+    assert_eq!(runs(&mut conn, "a").await, 1);
+    assert_eq!(queued_jobs::<LogJob>(&mut conn).await.unwrap().len(), 1);
+
+    assert_eq!(run_all_jobs(&worker).await.unwrap(), 2);
+    assert_eq!(runs(&mut conn, "a").await, 3);
+    assert!(queued_jobs::<LogJob>(&mut conn).await.unwrap().is_empty());
+    assert!(failed_jobs::<LogJob>(&mut conn).await.unwrap().is_empty());
+}
+
+#[sqlx::test]
+async fn test_jobs_fail_after_max_attempts(pool: sqlx::Pool<sqlx::Sqlite>) {
+    let mut conn = setup(&pool).await;
+    let worker = Worker::new(pool.clone()).register::<LogJob>();
+
+    jobs::enqueue(&mut conn, &log_job("a", 10)).await.unwrap();
+
+    assert_eq!(run_all_jobs(&worker).await.unwrap(), 3);
+    assert_eq!(runs(&mut conn, "a").await, 3);
+    assert!(queued_jobs::<LogJob>(&mut conn).await.unwrap().is_empty());
+    assert_eq!(
+        failed_jobs::<LogJob>(&mut conn).await.unwrap(),
+        vec![log_job("a", 10)]
+    );
+
+    let last_error: String = sqlx::query_scalar("SELECT last_error FROM break_stack_jobs")
+        .fetch_one(&mut *conn)
+        .await
+        .unwrap();
+    assert!(last_error.contains("failed run 3"), "{}", last_error);
+}
+
+#[sqlx::test]
+async fn test_jobs_unregistered(pool: sqlx::Pool<sqlx::Sqlite>) {
+    let mut conn = setup(&pool).await;
+    let worker = Worker::new(pool.clone());
+
+    jobs::enqueue(&mut conn, &log_job("a", 0)).await.unwrap();
+
+    assert_eq!(run_all_jobs(&worker).await.unwrap(), 3);
+    assert_eq!(runs(&mut conn, "a").await, 0);
+    assert_eq!(failed_jobs::<LogJob>(&mut conn).await.unwrap().len(), 1);
+}
@@ -0,0 +1,243 @@
+use axum::Router;
+use break_stack::auth::*;
+use break_stack::errors::*;
+use break_stack::models::testutils::MockConn;
+use break_stack::models::*;
+use break_stack::testing::{OwnerAuthCheck, TestApp};
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+struct NoteModel {
+    id: i64,
+    owner: i64,
+    text: String,
+}
+impl Model for NoteModel {
+    type ID = i64;
+    type Conn = DBConn;
+
+    const MODEL_NAME: &'static str = "Note";
+}
+impl ModelRead for NoteModel {
+    async fn read(conn: &mut DBConn, id: i64) -> Result<Option<Self>, ModelError> {
+        Ok(sqlx::query_as("SELECT * FROM notes WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&mut **conn)
+            .await?)
+    }
+}
+impl ModelWrite for NoteModel {
+    type Write = String;
+
+    async fn write(conn: &mut DBConn, id: i64, data: String) -> Result<Option<Self>, ModelError> {
+        Ok(
+            sqlx::query_as("UPDATE notes SET text = ? WHERE id = ? RETURNING *")
+                .bind(data)
+                .bind(id)
+                .fetch_optional(&mut **conn)
+                .await?,
+        )
+    }
+}
+impl ModelCreate for NoteModel {
+    type Create = NoteCreate;
+
+    async fn create(conn: &mut DBConn, data: NoteCreate) -> Result<Self, ModelError> {
+        Ok(
+            sqlx::query_as("INSERT INTO notes (owner, text) VALUES (?, ?) RETURNING *")
+                .bind(data.owner)
+                .bind(data.text)
+                .fetch_one(&mut **conn)
+                .await?,
+        )
+    }
+}
+impl ModelDelete for NoteModel {
+    async fn delete(conn: &mut DBConn, id: i64) -> Result<Self, ModelError> {
+        Ok(sqlx::query_as("DELETE FROM notes WHERE id = ? RETURNING *")
+            .bind(id)
+            .fetch_one(&mut **conn)
+            .await?)
+    }
+}
+impl WithOwnerModel for NoteModel {
+    async fn owner(conn: &mut DBConn, id: i64) -> Result<Option<i64>, ModelError> {
+        Ok(sqlx::query_scalar("SELECT owner FROM notes WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&mut **conn)
+            .await?)
+    }
+    async fn all_for_owner(conn: &mut DBConn, user_id: i64) -> Result<Vec<Self>, ModelError> {
+        Ok(sqlx::query_as("SELECT * FROM notes WHERE owner = ?")
+            .bind(user_id)
+            .fetch_all(&mut **conn)
+            .await?)
+    }
+}
+impl OwnerAuthModelRead for NoteModel {}
+impl OwnerAuthModelWrite for NoteModel {}
+impl OwnerAuthModelCreate for NoteModel {
+    async fn will_be_owner(_conn: &mut DBConn, data: &NoteCreate) -> Result<i64, ModelError> {
+        Ok(data.owner)
+    }
+}
+impl OwnerAuthModelDelete for NoteModel {}
+impl OwnerAuthModelList for NoteModel {}
+
+#[derive(ModelFactory)]
+#[model_factory(model = "NoteModel")]
+struct NoteCreate {
+    owner: i64,
+    #[factory(sequence = "Note {}")]
+    text: String,
+}
+
+#[tokio::test]
+async fn test_owner_auth_check() {
+    let app = TestApp::new(|_| Router::new()).await;
+    let mut conn = app.conn().await;
+    sqlx::query(
+        "CREATE TABLE notes (id INTEGER PRIMARY KEY, owner INTEGER NOT NULL, text TEXT NOT NULL)",
+    )
+    .execute(&mut *conn)
+    .await
+    .unwrap();
+    // A row that wasn't created by the check, owned by nobody the check knows
+    sqlx::query("INSERT INTO notes (owner, text) VALUES (0, 'Fixture')")
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+
+    let mut check =
+        OwnerAuthCheck::<NoteCreate>::new(&mut conn, |note, owner| note.owner = *owner).await;
+    assert_eq!(check.users().len(), 5);
+    assert_eq!(check.objects().count(), 12);
+    assert!(check.objects().all(|(owner, note)| *owner == note.owner));
+    check.read(&mut conn).await;
+    check
+        .write(&mut conn, |note| format!("{} edited", note.text))
+        .await;
+    check.create(&mut conn).await;
+    check.delete(&mut conn).await;
+    check.all_for_owner(&mut conn).await;
+    check.list(&mut conn).await;
+    assert_eq!(check.violations(), &[] as &[String]);
+    check.finish();
+
+    // The checks don't change anything
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM notes WHERE text LIKE 'Note %'")
+        .fetch_one(&mut *conn)
+        .await
+        .unwrap();
+    assert_eq!(count, 12);
+}
+
+/// Model with the kinds of bugs the check is for.
+#[derive(Debug, Clone)]
+struct LeakyNoteModel {
+    id: i64,
+    owner: i64,
+}
+impl Model for LeakyNoteModel {
+    type ID = i64;
+    type Conn = MockConn;
+
+    const MODEL_NAME: &'static str = "LeakyNote";
+}
+impl ModelCreate for LeakyNoteModel {
+    type Create = LeakyNoteCreate;
+
+    async fn create(conn: &mut MockConn, data: LeakyNoteCreate) -> Result<Self, ModelError> {
+        let rows = conn.rows::<Self>();
+        let note = Self {
+            id: rows.len() as i64 + 1,
+            owner: data.owner,
+        };
+        rows.push(note.clone());
+        Ok(note)
+    }
+}
+impl ModelRead for LeakyNoteModel {
+    async fn read(conn: &mut MockConn, id: i64) -> Result<Option<Self>, ModelError> {
+        Ok(conn.rows::<Self>().iter().find(|m| m.id == id).cloned())
+    }
+}
+impl ModelDelete for LeakyNoteModel {
+    async fn delete(_conn: &mut MockConn, _id: i64) -> Result<Self, ModelError> {
+        unreachable!()
+    }
+}
+impl WithOwnerModel for LeakyNoteModel {
+    async fn owner(conn: &mut MockConn, id: i64) -> Result<Option<i64>, ModelError> {
+        Ok(Self::read(conn, id).await?.map(|m| m.owner))
+    }
+    async fn all_for_owner(conn: &mut MockConn, _user_id: i64) -> Result<Vec<Self>, ModelError> {
+        Ok(conn.rows::<Self>().clone())
+    }
+}
+impl AuthModelRead for LeakyNoteModel {
+    async fn can_read(
+        _conn: &mut MockConn,
+        _id: i64,
+        user_id: Option<UserId>,
+    ) -> Result<(), AuthError> {
+        // Any logged in user can read
+        user_id.map(|_| ()).ok_or(AuthError::Unauthenticated)
+    }
+}
+impl AuthModelDelete for LeakyNoteModel {
+    async fn can_delete(
+        conn: &mut MockConn,
+        id: i64,
+        user_id: Option<UserId>,
+    ) -> Result<(), AuthError> {
+        // Missing the `Unauthenticated` case
+        if Self::owner(conn, id).await? == user_id.map(|user_id| *user_id) {
+            Ok(())
+        } else {
+            Err(AuthError::Unauthorized)
+        }
+    }
+}
+
+#[derive(ModelFactory)]
+#[model_factory(model = "LeakyNoteModel")]
+struct LeakyNoteCreate {
+    owner: i64,
+}
+
+#[tokio::test]
+async fn test_owner_auth_check_violations() {
+    let mut conn = MockConn::new();
+    let mut check = OwnerAuthCheck::<LeakyNoteCreate>::with_size(&mut conn, 2, 4, |note, owner| {
+        note.owner = *owner
+    })
+    .await;
+    check.read(&mut conn).await;
+    check.delete(&mut conn).await;
+    check.all_for_owner(&mut conn).await;
+
+    let violations = check.violations();
+    let count = |pattern: &str| violations.iter().filter(|v| v.contains(pattern)).count();
+    // 2 users (and the user without notes) who don't own each of the 4 notes
+    assert_eq!(count(" read LeakyNote "), 4 * 2);
+    assert_eq!(count("expected Err(Unauthorized), got Ok(())"), 4 * 2);
+    assert_eq!(count("no user delete LeakyNote "), 4);
+    assert_eq!(
+        count("expected Err(Unauthenticated), got Err(Unauthorized)"),
+        4
+    );
+    // Every user gets the notes of the other users
+    assert_eq!(count("all_for_owner for user "), 4 * 2);
+    // The delete rule is right for logged in users
+    assert_eq!(count(" delete "), 4);
+}
+
+#[tokio::test]
+#[should_panic(expected = "authorization violations for LeakyNote (seed ")]
+async fn test_owner_auth_check_finish() {
+    let mut conn = MockConn::new();
+    let mut check =
+        OwnerAuthCheck::<LeakyNoteCreate>::new(&mut conn, |note, owner| note.owner = *owner).await;
+    check.read(&mut conn).await;
+    check.finish();
+}
@@ -0,0 +1,178 @@
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Form, Router,
+};
+use break_stack::auth::UserId;
+use break_stack::htmx::HtmxResponse;
+use break_stack::testing::TestApp;
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+
+type Items = Arc<Mutex<Vec<(i64, String)>>>;
+
+fn item_html(id: i64, name: &str) -> String {
+    format!(
+        r#"<li id="item-{id}">{name} <button hx-delete="/items/{id}" hx-target="closest li" hx-swap="outerHTML">Delete</button></li>"#
+    )
+}
+
+async fn index(State(items): State<Items>) -> Response {
+    let items = items.lock().unwrap();
+    let list: String = items
+        .iter()
+        .map(|(id, name)| item_html(*id, name))
+        .collect();
+    format!(
+        r##"<html><body>
+<p>Items: <span id="count" hx-get="/count" hx-trigger="ItemsChanged from:body">{count}</span></p>
+<p>Last added: <span id="last"></span></p>
+<ul id="items">{list}</ul>
+<form hx-post="/items" hx-target="#items" hx-swap="beforeend">
+    <input name="name">
+    <select name="priority">
+        <option value="low">Low</option>
+        <option value="high">High</option>
+    </select>
+    <label><input type="checkbox" name="urgent" value="yes"> Urgent</label>
+    <button type="submit" name="action" value="add">Add</button>
+</form>
+<div hx-trigger="ItemsChanged"><button id="clear" hx-delete="/items" hx-vals='{{"confirm": true}}'>Clear</button></div>
+<a href="/about">About</a>
+</body></html>"##,
+        count = items.len()
+    )
+    .into_response()
+}
+
+#[derive(Deserialize)]
+struct ItemForm {
+    name: String,
+    priority: String,
+    urgent: Option<String>,
+    action: Option<String>,
+}
+
+async fn create(State(items): State<Items>, Form(form): Form<ItemForm>) -> Response {
+    let mut items = items.lock().unwrap();
+    let id = items.len() as i64 + 1;
+    let mut details = vec![form.priority];
+    details.extend(form.urgent.map(|_| "urgent".to_string()));
+    // The button is only submitted when the form is submitted by clicking it
+    details.extend(form.action);
+    let name = format!("{} ({})", form.name, details.join(", "));
+    items.push((id, name.clone()));
+    let body = format!(
+        r#"{}
+<div hx-swap-oob="innerHTML:#last">{}</div>
+<div hx-swap-oob="true">Skipped, since it has no id</div>"#,
+        item_html(id, &name),
+        name
+    );
+    HtmxResponse::new(body)
+        .trigger("ItemsChanged")
+        .into_response()
+}
+
+async fn remove(State(items): State<Items>, Path(id): Path<i64>) -> Response {
+    items.lock().unwrap().retain(|(item, _)| *item != id);
+    HtmxResponse::new("")
+        .trigger("ItemsChanged")
+        .into_response()
+}
+
+#[derive(Deserialize)]
+struct ClearForm {
+    confirm: bool,
+}
+
+async fn clear(State(items): State<Items>, Form(form): Form<ClearForm>) -> Response {
+    assert!(form.confirm);
+    items.lock().unwrap().clear();
+    HtmxResponse::new("").redirect("/about").into_response()
+}
+
+async fn count(State(items): State<Items>) -> String {
+    items.lock().unwrap().len().to_string()
+}
+
+async fn about() -> &'static str {
+    "<h1>About</h1>"
+}
+
+async fn app() -> TestApp {
+    TestApp::new(|_| {
+        Router::new()
+            .route("/", get(index))
+            .route("/items", post(create).delete(clear))
+            .route("/items/:id", delete(remove))
+            .route("/count", get(count))
+            .route("/about", get(about))
+            .with_state(Items::default())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_page_flow() {
+    let app = app().await;
+    let mut page = app.get("/").user(UserId(1)).open().await;
+    page.dom()
+        .assert_text("#count", "0")
+        .assert_count("#items li", 0);
+
+    page.fill("form [name=name]", "Milk")
+        .fill("form [name=priority]", "high");
+    page.submit("form")
+        .await
+        .assert_ok()
+        .assert_triggered("ItemsChanged");
+    page.dom()
+        .assert_text("#items li:last-child", "Milk (high) Delete")
+        // Out of band swap
+        .assert_text("#last", "Milk (high)")
+        .assert_count("div[hx-swap-oob]", 0)
+        // Refreshed by the ItemsChanged listener
+        .assert_text("#count", "1")
+        // The form isn't reset, so the filled in values are kept
+        .assert_form_value("form", "name", "Milk");
+
+    page.fill("form [name=name]", "Eggs")
+        .fill("form [name=priority]", "low")
+        .check("form [name=urgent]", true);
+    page.click("form button[type=submit]").await.assert_ok();
+    page.dom()
+        .assert_count("#items li", 2)
+        .assert_text("#items li:last-child", "Eggs (low, urgent, add) Delete")
+        .assert_text("#count", "2");
+
+    page.click("#item-1 button").await.assert_ok();
+    page.dom()
+        .assert_missing("#item-1")
+        .assert_count("#items li", 1)
+        .assert_text("#count", "1");
+}
+
+#[tokio::test]
+async fn test_page_navigation() {
+    let app = app().await;
+    let mut page = app.get("/").open().await;
+
+    // The clear button redirects, and its parent listening for ItemsChanged isn't reached
+    page.click("#clear").await;
+    assert_eq!(page.url(), "/about");
+    page.dom().assert_text("h1", "About");
+
+    page.visit("/").await;
+    page.click("a").await;
+    assert_eq!(page.url(), "/about");
+}
+
+#[tokio::test]
+#[should_panic(expected = "doesn't send a request")]
+async fn test_page_click_without_request() {
+    let app = app().await;
+    let mut page = app.get("/").open().await;
+    page.click("#last").await;
+}
@@ -0,0 +1,190 @@
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, State},
+    http::request::Parts,
+    routing::get,
+    Router,
+};
+use break_stack::errors::*;
+use break_stack::models::{DBConn, DBPool};
+use break_stack::queries::{self, assert_max_queries, QueryLog};
+use break_stack::testing::{TestApp, TestResponse};
+
+#[derive(Clone)]
+struct AppState {
+    db_pool: DBPool,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for DBConn {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let mut conn: DBConn = state.db_pool.acquire().await?.into();
+        queries::attach(parts, &mut conn).await?;
+        Ok(conn)
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct List {
+    id: i64,
+    name: String,
+}
+
+async fn lists_n_plus_one(mut conn: DBConn) -> AppResult<String> {
+    let lists: Vec<List> = sqlx::query_as("SELECT * FROM lists ORDER BY id")
+        .fetch_all(&mut *conn)
+        .await?;
+    let mut html = String::new();
+    for list in lists {
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM items WHERE list = ?")
+            .bind(list.id)
+            .fetch_one(&mut *conn)
+            .await?;
+        html.push_str(&format!("<li>{} ({})</li>", list.name, count));
+    }
+    Ok(html)
+}
+
+async fn lists_joined(State(state): State<AppState>) -> AppResult<String> {
+    let counts: Vec<(String, i64)> = sqlx::query_as(
+        "SELECT lists.name, COUNT(items.id) FROM lists LEFT JOIN items ON items.list = lists.id \
+         GROUP BY lists.id ORDER BY lists.id",
+    )
+    .fetch_all(&state.db_pool)
+    .await?;
+    Ok(counts
+        .into_iter()
+        .map(|(name, count)| format!("<li>{} ({})</li>", name, count))
+        .collect())
+}
+
+async fn test_app() -> TestApp {
+    let app = TestApp::new(|db_pool| {
+        Router::new()
+            .route("/n-plus-one", get(lists_n_plus_one))
+            .route("/joined", get(lists_joined))
+            .layer(axum::middleware::from_fn(queries::log_queries))
+            .with_state(AppState { db_pool })
+    })
+    .await;
+    let mut conn = app.conn().await;
+    for sql in [
+        "CREATE TABLE lists (id INTEGER PRIMARY KEY, name TEXT NOT NULL)",
+        "CREATE TABLE items (id INTEGER PRIMARY KEY, list INTEGER NOT NULL)",
+        "INSERT INTO lists (name) VALUES ('Groceries'), ('Chores'), ('Books')",
+        "INSERT INTO items (list) VALUES (1), (1), (2)",
+    ] {
+        sqlx::query(sql).execute(&mut *conn).await.unwrap();
+    }
+    app
+}
+
+#[tokio::test]
+async fn test_response_queries() {
+    let app = test_app().await;
+
+    let response = assert_max_queries!(4, app.get("/n-plus-one").send().await);
+    response
+        .assert_ok()
+        .assert_contains("<li>Groceries (2)</li>")
+        .assert_contains("<li>Books (0)</li>");
+    assert_eq!(response.queries().len(), 4);
+    assert_eq!(
+        response.queries()[1].expanded,
+        "SELECT COUNT(*) FROM items WHERE list = 1"
+    );
+    assert_eq!(
+        queries::repeated(response.queries()),
+        vec![("SELECT COUNT(*) FROM items WHERE list = ?".to_string(), 3)]
+    );
+
+    // Only the statements of the request
+    let response = assert_max_queries!(1, app.get("/joined").send().await);
+    response
+        .assert_ok()
+        .assert_contains("<li>Chores (1)</li>")
+        .assert_no_repeated_queries();
+}
+
+#[tokio::test]
+#[should_panic(
+    expected = "expected at most 2 queries for app.get(\"/n-plus-one\").send().await, got 4"
+)]
+async fn test_assert_max_queries_fails() {
+    let app = test_app().await;
+    assert_max_queries!(2, app.get("/n-plus-one").send().await);
+}
+
+#[tokio::test]
+#[should_panic(expected = "statements executed several times with different parameters")]
+async fn test_assert_no_repeated_queries_fails() {
+    let app = test_app().await;
+    app.get("/n-plus-one")
+        .send()
+        .await
+        .assert_no_repeated_queries();
+}
+
+#[tokio::test]
+async fn test_query_log() {
+    let app = test_app().await;
+    let mut conn = app.conn().await;
+
+    let log = QueryLog::new();
+    let guard = log.attach(&mut conn).await.unwrap();
+    // The same statement with the same parameters isn't an N+1 query
+    for _ in 0..2 {
+        sqlx::query("SELECT name FROM lists WHERE id = ?")
+            .bind(1)
+            .fetch_one(&mut *conn)
+            .await
+            .unwrap();
+    }
+    assert_max_queries!(2, &log);
+    assert!(queries::repeated(&log.queries()).is_empty());
+
+    log.clear();
+    assert!(log.is_empty());
+
+    // Nothing is recorded once the guard is dropped
+    drop(guard);
+    sqlx::query("SELECT 1").execute(&mut *conn).await.unwrap();
+    assert!(log.is_empty());
+}
+
+#[tokio::test]
+async fn test_log_queries_middleware() {
+    let app = test_app().await;
+    let router = Router::new()
+        .route("/n-plus-one", get(lists_n_plus_one))
+        .layer(axum::middleware::from_fn(queries::log_queries))
+        .with_state(AppState {
+            db_pool: app.db_pool().clone(),
+        });
+    let response = axum::response::IntoResponse::into_response(
+        tower::ServiceExt::oneshot(
+            router,
+            axum::http::Request::get("/n-plus-one")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap(),
+    );
+    let log = response.extensions().get::<QueryLog>().unwrap().clone();
+    let response = TestResponse::from_response(response).await;
+    // The statements of the request, without the ones recorded by the `TestApp`
+    assert_max_queries!(4, response.assert_ok());
+    assert_eq!(response.queries().len(), 4);
+
+    // The log is detached when the connection of the request goes back to the pool, so the
+    // statements run on it later aren't added to the log of the request
+    let mut conn = app.conn().await;
+    sqlx::query("SELECT 1").execute(&mut *conn).await.unwrap();
+    assert_eq!(log.len(), 4);
+}
@@ -0,0 +1,164 @@
+use axum::{extract::FromRequestParts, http::request::Parts};
+use break_stack::auth::UserId;
+use break_stack::components::*;
+use break_stack::errors::*;
+use break_stack::models::*;
+use break_stack::routes::resource;
+
+pub struct TestModel {
+    id: i64,
+}
+impl Model for TestModel {
+    type ID = i64;
+    type Conn = DBConn;
+
+    const MODEL_NAME: &'static str = "Test";
+}
+impl ModelRead for TestModel {
+    async fn read(_conn: &mut DBConn, id: i64) -> Result<Option<Self>, ModelError> {
+        Ok(Some(Self { id }))
+    }
+}
+impl ModelWrite for TestModel {
+    type Write = i64;
+
+    async fn write(_conn: &mut DBConn, id: i64, _data: i64) -> Result<Option<Self>, ModelError> {
+        Ok(Some(Self { id }))
+    }
+}
+impl ModelCreate for TestModel {
+    type Create = i64;
+
+    async fn create(_conn: &mut DBConn, data: i64) -> Result<Self, ModelError> {
+        Ok(Self { id: data })
+    }
+}
+impl ModelDelete for TestModel {
+    async fn delete(_conn: &mut DBConn, id: i64) -> Result<Self, ModelError> {
+        Ok(Self { id })
+    }
+}
+impl AuthModelRead for TestModel {
+    async fn can_read(
+        _conn: &mut DBConn,
+        _id: i64,
+        _user_id: Option<UserId>,
+    ) -> Result<(), AuthError> {
+        Ok(())
+    }
+}
+impl AuthModelWrite for TestModel {
+    async fn can_write(
+        _conn: &mut DBConn,
+        _id: i64,
+        _user_id: Option<UserId>,
+        _data: &i64,
+    ) -> Result<(), AuthError> {
+        Ok(())
+    }
+}
+impl AuthModelCreate for TestModel {
+    async fn can_create(
+        _conn: &mut DBConn,
+        _user_id: Option<UserId>,
+        _data: &i64,
+    ) -> Result<(), AuthError> {
+        Ok(())
+    }
+}
+impl AuthModelDelete for TestModel {
+    async fn can_delete(
+        _conn: &mut DBConn,
+        _id: i64,
+        _user_id: Option<UserId>,
+    ) -> Result<(), AuthError> {
+        Ok(())
+    }
+}
+impl AuthModelList for TestModel {
+    async fn list(_conn: &mut DBConn, _user_id: Option<UserId>) -> Result<Vec<Self>, AuthError> {
+        Ok(vec![Self { id: 1 }, Self { id: 2 }])
+    }
+}
+
+#[derive(Component)]
+#[template(source = r#"{{ id }}"#, ext = "html")]
+pub struct TestViewComponent {
+    id: i64,
+}
+impl From<TestModel> for TestViewComponent {
+    fn from(m: TestModel) -> Self {
+        Self { id: m.id }
+    }
+}
+
+#[derive(Component, Default)]
+#[template(source = r#"new"#, ext = "html")]
+pub struct TestNewComponent {}
+
+#[derive(Component)]
+#[template(source = r#"{{ count }}"#, ext = "html")]
+pub struct TestListComponent {
+    count: usize,
+}
+impl From<Vec<TestModel>> for TestListComponent {
+    fn from(items: Vec<TestModel>) -> Self {
+        Self { count: items.len() }
+    }
+}
+
+pub async fn custom_delete() -> &'static str {
+    "deleted"
+}
+
+#[derive(Clone)]
+pub struct AppState;
+
+#[axum::async_trait]
+impl FromRequestParts<AppState> for DBConn {
+    type Rejection = AppError;
+
+    async fn from_request_parts(_parts: &mut Parts, _state: &AppState) -> AppResult<Self> {
+        Err(AppError::Internal("no database in test".to_string()))
+    }
+}
+
+#[axum::async_trait]
+impl FromRequestParts<AppState> for UserId {
+    type Rejection = AppError;
+
+    async fn from_request_parts(_parts: &mut Parts, _state: &AppState) -> AppResult<Self> {
+        Err(AppError::Auth(AuthError::Unauthenticated))
+    }
+}
+
+resource! {
+    AppState, items, "/items", TestModel,
+    view: TestViewComponent,
+    edit: TestViewComponent,
+    new: TestNewComponent,
+    list: TestListComponent,
+}
+
+resource! {
+    AppState, other_items, "/other/items", TestModel,
+    view: TestViewComponent,
+    edit: TestViewComponent,
+    new: TestNewComponent,
+    list: TestListComponent,
+    overrides {
+        delete: custom_delete,
+        new: custom_delete,
+    }
+}
+
+#[test]
+fn test_resource_route_paths() {
+    assert_eq!(items::route_paths::index(), "/items");
+    assert_eq!(items::route_paths::new(), "/items/new");
+    assert_eq!(items::route_paths::id(&3), "/items/3");
+    assert_eq!(items::route_paths::edit(&3), "/items/3/edit");
+    assert_eq!(other_items::route_paths::edit(&3), "/other/items/3/edit");
+
+    let _: axum::Router<AppState> = items::router().merge(other_items::router());
+}
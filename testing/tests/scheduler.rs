@@ -0,0 +1,146 @@
+use axum::{body::Body, http::Request, http::StatusCode, Router};
+use break_stack::errors::*;
+use break_stack::models::DBConn;
+use break_stack::scheduler::*;
+use tower::ServiceExt;
+
+// 2023-11-14 22:13:20 UTC
+const NOW: i64 = 1_700_000_000;
+// 2023-11-14 23:00:00 UTC
+const NEXT_HOUR: i64 = 1_700_002_800;
+
+async fn setup(pool: &sqlx::Pool<sqlx::Sqlite>) -> DBConn {
+    let mut conn: DBConn = pool.acquire().await.unwrap().into();
+    break_stack::migrations::run(&mut conn).await.unwrap();
+    sqlx::query("CREATE TABLE task_runs (name TEXT NOT NULL)")
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+    conn
+}
+
+async fn log_run(mut conn: DBConn, name: &str) -> AppResult<()> {
+    sqlx::query("INSERT INTO task_runs (name) VALUES (?)")
+        .bind(name)
+        .execute(&mut *conn)
+        .await?;
+    Ok(())
+}
+
+async fn runs(conn: &mut DBConn, name: &str) -> i64 {
+    sqlx::query_scalar("SELECT COUNT(*) FROM task_runs WHERE name = ?")
+        .bind(name)
+        .fetch_one(&mut **conn)
+        .await
+        .unwrap()
+}
+
+fn scheduler(pool: &sqlx::Pool<sqlx::Sqlite>) -> Scheduler {
+    Scheduler::new(pool.clone())
+        .task("hourly", "0 0 * * * *", |conn| log_run(conn, "hourly"))
+        .task("failing", "0 0 * * * *", |conn| async move {
+            log_run(conn, "failing").await?;
+            Err(AppError::Internal("task failed".to_string()))
+        })
+}
+
+#[sqlx::test]
+async fn test_scheduler_runs_due_tasks(pool: sqlx::Pool<sqlx::Sqlite>) {
+    let mut conn = setup(&pool).await;
+    let scheduler = scheduler(&pool);
+
+    // New tasks are scheduled from when they are first seen
+    assert_eq!(scheduler.run_due_at(NOW).await.unwrap(), 0);
+    assert_eq!(scheduler.run_due_at(NEXT_HOUR - 1).await.unwrap(), 0);
+    assert_eq!(scheduler.run_due_at(NEXT_HOUR).await.unwrap(), 2);
+    assert_eq!(scheduler.run_due_at(NEXT_HOUR + 1).await.unwrap(), 0);
+    assert_eq!(runs(&mut conn, "hourly").await, 1);
+
+    // Missed runs are only run once
+    assert_eq!(scheduler.run_due_at(NEXT_HOUR + 5 * 3600).await.unwrap(), 2);
+    assert_eq!(runs(&mut conn, "hourly").await, 2);
+    assert_eq!(runs(&mut conn, "failing").await, 2);
+
+    let last_error: Option<String> = sqlx::query_scalar(
+        "SELECT last_error FROM break_stack_scheduled_tasks WHERE name = 'failing'",
+    )
+    .fetch_one(&mut *conn)
+    .await
+    .unwrap();
+    assert!(last_error.unwrap().contains("task failed"));
+}
+
+#[sqlx::test]
+async fn test_scheduler_restart_does_not_run_again(pool: sqlx::Pool<sqlx::Sqlite>) {
+    let mut conn = setup(&pool).await;
+
+    assert_eq!(scheduler(&pool).run_due_at(NOW).await.unwrap(), 0);
+    assert_eq!(scheduler(&pool).run_due_at(NEXT_HOUR).await.unwrap(), 2);
+    assert_eq!(scheduler(&pool).run_due_at(NEXT_HOUR + 1).await.unwrap(), 0);
+    assert_eq!(runs(&mut conn, "hourly").await, 1);
+}
+
+#[sqlx::test]
+async fn test_scheduler_run_now(pool: sqlx::Pool<sqlx::Sqlite>) {
+    let mut conn = setup(&pool).await;
+    let scheduler = scheduler(&pool);
+
+    scheduler.run_now("hourly").await.unwrap();
+    assert_eq!(runs(&mut conn, "hourly").await, 1);
+    assert!(scheduler.run_now("failing").await.is_err());
+    assert_eq!(runs(&mut conn, "failing").await, 1);
+    assert!(matches!(
+        scheduler.run_now("unknown").await,
+        Err(AppError::NotFound)
+    ));
+}
+
+#[sqlx::test]
+async fn test_scheduler_router(pool: sqlx::Pool<sqlx::Sqlite>) {
+    let mut conn = setup(&pool).await;
+    let app: Router = Router::new().nest(
+        "/scheduler",
+        scheduler_router(scheduler(&pool), |parts| {
+            parts
+                .headers
+                .get("X-Admin")
+                .is_some_and(|value| value == "1")
+        }),
+    );
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::post("/scheduler/hourly")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(runs(&mut conn, "hourly").await, 0);
+
+    let request = |uri: &str| {
+        Request::post(uri.to_string())
+            .header("X-Admin", "1")
+            .body(Body::empty())
+            .unwrap()
+    };
+    let response = app
+        .clone()
+        .oneshot(request("/scheduler/hourly"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(runs(&mut conn, "hourly").await, 1);
+
+    let response = app.oneshot(request("/scheduler/unknown")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+#[should_panic(expected = "invalid schedule for task 'invalid'")]
+async fn test_scheduler_invalid_schedule() {
+    let pool = sqlx::Pool::<sqlx::Sqlite>::connect_lazy("sqlite::memory:").unwrap();
+    let _ = Scheduler::new(pool).task("invalid", "every hour", |_| async { Ok(()) });
+}
@@ -0,0 +1,72 @@
+use break_stack::assert_component_snapshot;
+use break_stack::components::*;
+use break_stack::testing::{assert_snapshot, next_snapshot_name};
+
+#[derive(Component)]
+#[template(
+    source = r#"<li id="item-{{ id }}" class="item">
+    <span>{{ description }}</span>
+    <button hx-delete="/items/{{ id }}" hx-target="closest li">Delete</button>
+</li>"#,
+    ext = "html"
+)]
+struct ItemViewComponent {
+    id: i64,
+    description: String,
+}
+
+#[test]
+fn test_component_snapshot() {
+    let item = ItemViewComponent {
+        id: 1,
+        description: "Buy milk".to_string(),
+    };
+    assert_component_snapshot!(&item);
+    assert_component_snapshot!(ItemViewComponentRef::new(2, "Buy   eggs"));
+    assert_component_snapshot!("item_view_named", ItemViewComponentRef::from(&item));
+}
+
+#[test]
+fn test_snapshot_names() {
+    assert_eq!(
+        next_snapshot_name("snapshot"),
+        "snapshot__test_snapshot_names"
+    );
+    assert_eq!(
+        next_snapshot_name("snapshot"),
+        "snapshot__test_snapshot_names-2"
+    );
+}
+
+#[test]
+fn test_snapshot_written_and_compared() {
+    // The only test with missing snapshots, so changing `CI` doesn't affect the others
+    let ci = std::env::var_os("CI");
+    std::env::remove_var("CI");
+    let dir = tempfile::tempdir().unwrap();
+    assert_snapshot(dir.path().join("new"), "a", "<p>\n  a\n</p>\n");
+    assert_eq!(
+        std::fs::read_to_string(dir.path().join("new/a.html")).unwrap(),
+        "<p>\n  a\n</p>\n"
+    );
+    assert_snapshot(dir.path().join("new"), "a", "<p>\n  a\n</p>\n");
+
+    let result = std::panic::catch_unwind(|| {
+        assert_snapshot(dir.path().join("new"), "a", "<p>\n  b\n</p>\n");
+    });
+    let message = *result.unwrap_err().downcast::<String>().unwrap();
+    assert!(message.contains("-   a\n+   b"), "{}", message);
+
+    // Missing snapshots fail on CI
+    std::env::set_var("CI", "true");
+    let result = std::panic::catch_unwind(|| {
+        assert_snapshot(dir.path().join("new"), "b", "<p>b</p>");
+    });
+    match ci {
+        Some(ci) => std::env::set_var("CI", ci),
+        None => std::env::remove_var("CI"),
+    }
+    let message = *result.unwrap_err().downcast::<String>().unwrap();
+    assert!(message.contains("is missing"), "{}", message);
+    assert!(!dir.path().join("new/b.html").exists());
+}
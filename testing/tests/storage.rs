@@ -0,0 +1,518 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{FromRequest, Multipart, Path},
+    http::{header, Request},
+    response::{IntoResponse, Response},
+};
+use break_stack::auth::*;
+use break_stack::controllers::*;
+use break_stack::errors::*;
+use break_stack::htmx::HtmxRequest;
+use break_stack::models::*;
+use break_stack::storage::*;
+use break_stack::testing::TestResponse;
+use serde::Deserialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const BOUNDARY: &str = "boundary";
+
+/// Builds a multipart form from `(name, filename, content type, data)`, fields without a
+/// filename are text fields.
+async fn multipart(parts: &[(&str, Option<&str>, &str, &str)]) -> Multipart {
+    let mut body = String::new();
+    for (name, filename, content_type, data) in parts {
+        body.push_str(&format!("--{}\r\n", BOUNDARY));
+        match filename {
+            Some(filename) => body.push_str(&format!(
+                "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+                name, filename, content_type
+            )),
+            None => body.push_str(&format!(
+                "Content-Disposition: form-data; name=\"{}\"\r\n\r\n",
+                name
+            )),
+        }
+        body.push_str(data);
+        body.push_str("\r\n");
+    }
+    body.push_str(&format!("--{}--\r\n", BOUNDARY));
+
+    let request = Request::post("/")
+        .header(
+            header::CONTENT_TYPE,
+            format!("multipart/form-data; boundary={}", BOUNDARY),
+        )
+        .body(Body::from(body))
+        .unwrap();
+    Multipart::from_request(request, &()).await.unwrap()
+}
+
+#[derive(Debug, Deserialize)]
+struct TestCreate {
+    name: String,
+    #[serde(default)]
+    done: bool,
+}
+
+#[tokio::test]
+async fn test_local_storage() {
+    let dir = tempfile::tempdir().unwrap();
+    let storage = Storage::local(dir.path().join("files"));
+
+    assert_eq!(storage.get("key").await.unwrap(), None);
+    storage.put("key", "data".into()).await.unwrap();
+    assert_eq!(storage.get("key").await.unwrap().unwrap(), "data");
+    storage.delete("key").await.unwrap();
+    assert_eq!(storage.get("key").await.unwrap(), None);
+    storage.delete("key").await.unwrap();
+
+    assert!(storage.put("../key", "data".into()).await.is_err());
+    assert!(storage.get("").await.is_err());
+}
+
+#[tokio::test]
+async fn test_multipart_form() {
+    let form = MultipartForm::<TestCreate>::parse(
+        multipart(&[
+            ("name", None, "", "test"),
+            ("done", None, "", "true"),
+            ("file", Some("a.txt"), "text/plain", "content"),
+            ("file", Some(""), "application/octet-stream", ""),
+        ])
+        .await,
+        &UploadLimits::DEFAULT,
+    )
+    .await
+    .unwrap();
+    assert_eq!(form.data.name, "test");
+    assert!(form.data.done);
+    assert_eq!(form.uploads.len(), 1);
+    assert_eq!(form.uploads[0].field, "file");
+    assert_eq!(form.uploads[0].filename, "a.txt");
+    assert_eq!(form.uploads[0].content_type, "text/plain");
+    assert_eq!(form.uploads[0].data, "content");
+
+    for (case, parts, limits) in [
+        (
+            "Missing field",
+            vec![("file", Some("a.txt"), "text/plain", "content")],
+            UploadLimits::DEFAULT,
+        ),
+        (
+            "Too large",
+            vec![
+                ("name", None, "", "test"),
+                ("file", Some("a.txt"), "text/plain", "content"),
+            ],
+            UploadLimits {
+                max_file_size: 3,
+                ..UploadLimits::DEFAULT
+            },
+        ),
+        (
+            "Too many files",
+            vec![
+                ("name", None, "", "test"),
+                ("file", Some("a.txt"), "text/plain", "content"),
+                ("file", Some("b.txt"), "text/plain", "content"),
+            ],
+            UploadLimits {
+                max_files: 1,
+                ..UploadLimits::DEFAULT
+            },
+        ),
+        (
+            "Content type not allowed",
+            vec![
+                ("name", None, "", "test"),
+                ("file", Some("a.txt"), "text/plain", "content"),
+            ],
+            UploadLimits {
+                allowed_content_types: &["image/*", "application/pdf"],
+                ..UploadLimits::DEFAULT
+            },
+        ),
+    ] {
+        println!("Running test case '{}'", case);
+        let form = MultipartForm::<TestCreate>::parse(multipart(&parts).await, &limits).await;
+        assert!(matches!(form, Err(AppError::BadRequest(_))));
+    }
+
+    let form = MultipartForm::<TestCreate>::parse(
+        multipart(&[
+            ("name", None, "", "test"),
+            ("file", Some("a.png"), "image/png", "content"),
+        ])
+        .await,
+        &UploadLimits {
+            allowed_content_types: &["image/*"],
+            ..UploadLimits::DEFAULT
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(form.uploads.len(), 1);
+}
+
+struct TestModel {
+    id: i64,
+    name: String,
+}
+impl Model for TestModel {
+    type ID = i64;
+    type Conn = DBConn;
+
+    const MODEL_NAME: &'static str = "Test";
+}
+impl ModelCreate for TestModel {
+    type Create = TestCreate;
+
+    async fn create(_conn: &mut DBConn, data: TestCreate) -> Result<Self, ModelError> {
+        Ok(Self {
+            id: 1,
+            name: data.name,
+        })
+    }
+}
+static DELETES: AtomicUsize = AtomicUsize::new(0);
+
+impl ModelDelete for TestModel {
+    async fn delete(_conn: &mut DBConn, id: i64) -> Result<Self, ModelError> {
+        DELETES.fetch_add(1, Ordering::SeqCst);
+        Ok(Self {
+            id,
+            name: String::new(),
+        })
+    }
+}
+impl AuthModelCreate for TestModel {
+    async fn can_create(
+        _conn: &mut DBConn,
+        user_id: Option<UserId>,
+        _data: &TestCreate,
+    ) -> Result<(), AuthError> {
+        user_id.map(|_| ()).ok_or(AuthError::Unauthenticated)
+    }
+}
+impl ModelRead for TestModel {
+    async fn read(_conn: &mut DBConn, id: i64) -> Result<Option<Self>, ModelError> {
+        Ok(Some(Self {
+            id,
+            name: String::new(),
+        }))
+    }
+}
+impl AuthModelRead for TestModel {
+    async fn can_read(
+        _conn: &mut DBConn,
+        _id: i64,
+        user_id: Option<UserId>,
+    ) -> Result<(), AuthError> {
+        match user_id {
+            None => Err(AuthError::Unauthenticated),
+            Some(UserId(0)) => Ok(()),
+            Some(_) => Err(AuthError::Unauthorized),
+        }
+    }
+}
+
+struct OtherModel;
+impl Model for OtherModel {
+    type ID = i64;
+    type Conn = DBConn;
+
+    const MODEL_NAME: &'static str = "Other";
+}
+impl ModelRead for OtherModel {
+    async fn read(_conn: &mut DBConn, _id: i64) -> Result<Option<Self>, ModelError> {
+        Ok(Some(Self))
+    }
+}
+impl AuthModelRead for OtherModel {
+    async fn can_read(
+        _conn: &mut DBConn,
+        _id: i64,
+        _user_id: Option<UserId>,
+    ) -> Result<(), AuthError> {
+        Ok(())
+    }
+}
+
+struct TestModelController;
+impl ModelController for TestModelController {
+    type Model = TestModel;
+
+    async fn build_response(
+        conn: &mut DBConn,
+        _user_id: Option<UserId>,
+        m: Self::Model,
+    ) -> AppResult<Response> {
+        let attachments = Attachment::all_for::<TestModel>(conn, m.id).await?;
+        Ok(format!("{}:{}:{}", m.id, m.name, attachments.len()).into_response())
+    }
+}
+impl UploadController for TestModelController {
+    fn model_id(m: &TestModel) -> i64 {
+        m.id
+    }
+}
+
+#[sqlx::test]
+async fn test_model_controller_create_multipart(pool: sqlx::Pool<sqlx::Sqlite>) {
+    break_stack::migrations::run(&mut pool.acquire().await.unwrap().into())
+        .await
+        .unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let storage = Storage::local(dir.path());
+
+    let response = model_controller_create_multipart::<TestModelController>(
+        pool.acquire().await.unwrap().into(),
+        Some(UserId(0)),
+        HtmxRequest::default(),
+        storage.clone(),
+        multipart(&[
+            ("name", None, "", "test"),
+            ("file", Some("résumé 1.txt"), "text/plain", "content"),
+        ])
+        .await,
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        response.headers().get("HX-Trigger").unwrap(),
+        &TestModel::event_created()
+    );
+    assert_eq!(
+        TestResponse::from_response(response).await.text(),
+        "1:test:1"
+    );
+
+    let attachments =
+        Attachment::all_for::<TestModel>(&mut pool.acquire().await.unwrap().into(), 1)
+            .await
+            .unwrap();
+    assert_eq!(attachments.len(), 1);
+    let attachment = &attachments[0];
+    assert_eq!(attachment.model_name, "Test");
+    assert_eq!(attachment.model_id, "1");
+    assert_eq!(attachment.size, 7);
+
+    let download = |user_id: Option<i64>| {
+        let pool = pool.clone();
+        let storage = storage.clone();
+        let id = attachment.id;
+        async move {
+            attachment_controller_download::<TestModel>(
+                pool.acquire().await.unwrap().into(),
+                Path(id),
+                user_id.map(UserId),
+                storage,
+            )
+            .await
+        }
+    };
+
+    let response = download(Some(0)).await.unwrap();
+    assert_eq!(
+        response.headers().get(header::CONTENT_TYPE).unwrap(),
+        "text/plain"
+    );
+    assert_eq!(
+        response.headers().get(header::CONTENT_DISPOSITION).unwrap(),
+        "attachment; filename=\"r_sum_ 1.txt\""
+    );
+    assert_eq!(
+        TestResponse::from_response(response).await.text(),
+        "content"
+    );
+
+    assert!(matches!(
+        download(None).await,
+        Err(AppError::Auth(AuthError::Unauthenticated))
+    ));
+    assert!(matches!(
+        download(Some(1)).await,
+        Err(AppError::Auth(AuthError::Unauthorized))
+    ));
+    // Attachments can only be downloaded through the model they are attached to
+    assert!(matches!(
+        attachment_controller_download::<OtherModel>(
+            pool.acquire().await.unwrap().into(),
+            Path(attachment.id),
+            Some(UserId(0)),
+            storage.clone(),
+        )
+        .await,
+        Err(AppError::NotFound)
+    ));
+
+    // Files aren't stored if the user can't create the object
+    let output = model_controller_create_multipart::<TestModelController>(
+        pool.acquire().await.unwrap().into(),
+        None,
+        HtmxRequest::default(),
+        storage.clone(),
+        multipart(&[
+            ("name", None, "", "test"),
+            ("file", Some("a.txt"), "text/plain", "content"),
+        ])
+        .await,
+    )
+    .await;
+    assert!(matches!(
+        output,
+        Err(AppError::Auth(AuthError::Unauthenticated))
+    ));
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+    Attachment::delete_all_for::<TestModel>(&mut pool.acquire().await.unwrap().into(), &storage, 1)
+        .await
+        .unwrap();
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+}
+
+/// Fails in `after_create`, and allows a single file larger than axum's default body limit.
+struct FailingController;
+impl ModelController for FailingController {
+    type Model = TestModel;
+
+    async fn build_response(
+        conn: &mut DBConn,
+        user_id: Option<UserId>,
+        m: Self::Model,
+    ) -> AppResult<Response> {
+        TestModelController::build_response(conn, user_id, m).await
+    }
+
+    async fn after_create(
+        _conn: &mut DBConn,
+        _user_id: Option<UserId>,
+        _item: &TestModel,
+    ) -> AppResult<()> {
+        Err(AppError::Internal("rejected by after_create".to_string()))
+    }
+}
+impl UploadController for FailingController {
+    const UPLOAD_LIMITS: UploadLimits = UploadLimits {
+        max_file_size: 3 * 1024 * 1024,
+        max_files: 1,
+        ..UploadLimits::DEFAULT
+    };
+
+    fn model_id(m: &TestModel) -> i64 {
+        m.id
+    }
+}
+
+#[sqlx::test]
+async fn test_model_controller_create_multipart_cleanup(pool: sqlx::Pool<sqlx::Sqlite>) {
+    break_stack::migrations::run(&mut pool.acquire().await.unwrap().into())
+        .await
+        .unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let storage = Storage::local(dir.path());
+
+    let output = model_controller_create_multipart::<FailingController>(
+        pool.acquire().await.unwrap().into(),
+        Some(UserId(0)),
+        HtmxRequest::default(),
+        storage.clone(),
+        multipart(&[
+            ("name", None, "", "test"),
+            ("file", Some("a.txt"), "text/plain", "content"),
+        ])
+        .await,
+    )
+    .await;
+    assert!(matches!(output, Err(AppError::Internal(_))));
+    // The files stored for the request are deleted
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    let attachments =
+        Attachment::all_for::<TestModel>(&mut pool.acquire().await.unwrap().into(), 1)
+            .await
+            .unwrap();
+    assert!(attachments.is_empty());
+}
+
+/// Backend that can't store anything.
+struct FailingStorage;
+#[axum::async_trait]
+impl StorageBackend for FailingStorage {
+    async fn put(&self, _key: &str, _data: Bytes) -> AppResult<()> {
+        Err(AppError::Internal("storage is down".to_string()))
+    }
+
+    async fn get(&self, _key: &str) -> AppResult<Option<Bytes>> {
+        Ok(None)
+    }
+
+    async fn delete(&self, _key: &str) -> AppResult<()> {
+        Ok(())
+    }
+}
+
+#[sqlx::test]
+async fn test_model_controller_create_multipart_storage_failure(pool: sqlx::Pool<sqlx::Sqlite>) {
+    break_stack::migrations::run(&mut pool.acquire().await.unwrap().into())
+        .await
+        .unwrap();
+
+    let deletes = DELETES.load(Ordering::SeqCst);
+    let output = model_controller_create_multipart::<TestModelController>(
+        pool.acquire().await.unwrap().into(),
+        Some(UserId(0)),
+        HtmxRequest::default(),
+        Storage::new(FailingStorage),
+        multipart(&[
+            ("name", None, "", "test"),
+            ("file", Some("a.txt"), "text/plain", "content"),
+        ])
+        .await,
+    )
+    .await;
+    assert!(matches!(output, Err(AppError::Internal(_))));
+    // The created object is deleted again
+    assert_eq!(DELETES.load(Ordering::SeqCst), deletes + 1);
+    let attachments =
+        Attachment::all_for::<TestModel>(&mut pool.acquire().await.unwrap().into(), 1)
+            .await
+            .unwrap();
+    assert!(attachments.is_empty());
+}
+
+#[tokio::test]
+async fn test_upload_body_limit() {
+    let app: axum::Router = axum::Router::new().route(
+        "/",
+        axum::routing::post(|multipart: Multipart| async move {
+            MultipartForm::<TestCreate>::parse(multipart, &FailingController::UPLOAD_LIMITS)
+                .await
+                .map(|form| form.data.name)
+        })
+        .layer(upload_body_limit::<FailingController>()),
+    );
+    let request = |size: usize| {
+        let body = format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"name\"\r\n\r\n{}\r\n--{b}--\r\n",
+            "a".repeat(size),
+            b = BOUNDARY
+        );
+        Request::post("/")
+            .header(
+                header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={}", BOUNDARY),
+            )
+            .body(Body::from(body))
+            .unwrap()
+    };
+
+    let response = tower::ServiceExt::oneshot(app.clone(), request(3 * 1024 * 1024))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let limit = FailingController::UPLOAD_LIMITS.max_body_size();
+    let response = tower::ServiceExt::oneshot(app, request(limit + 1))
+        .await
+        .unwrap();
+    assert!(response.status().is_client_error());
+}
@@ -0,0 +1,163 @@
+use break_stack::components::*;
+use break_stack::template_reload::{self, ReloadableTemplate, TemplateSource};
+use std::fmt;
+
+fn shout(name: &str) -> String {
+    name.to_uppercase()
+}
+
+#[derive(Component)]
+#[template(path = "greeting.html")]
+struct GreetingComponent {
+    name: String,
+    items: Vec<String>,
+}
+
+#[derive(Component)]
+#[template(
+    source = r#"<div>{% include "reload_test.html" %}</div>"#,
+    ext = "html"
+)]
+struct ReloadComponent {
+    text: String,
+}
+
+#[derive(Component)]
+#[template(
+    source = r#"<div>{%-include "reload_test.html" -%}</div>"#,
+    ext = "html"
+)]
+struct TrimmedReloadComponent {
+    text: String,
+}
+
+/// Doesn't implement `Serialize`
+pub struct Label(&'static str);
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+#[derive(Component)]
+#[template(
+    source = r#"<div>{% include "reload_test.html" %}</div>"#,
+    ext = "html"
+)]
+struct LabelComponent {
+    text: Label,
+}
+
+#[derive(Component)]
+#[template(source = "<b>{{ text }}</b>", ext = "html")]
+struct InlineComponent {
+    text: String,
+}
+
+/// Restores a template of `testing/templates` changed by a test.
+struct RestoreTemplate {
+    path: String,
+    source: String,
+}
+
+impl RestoreTemplate {
+    fn write(name: &str, source: &str) -> Self {
+        let path = format!("{}/{}", ReloadComponentRef::TEMPLATES_DIR, name);
+        let restore = RestoreTemplate {
+            source: std::fs::read_to_string(&path).unwrap(),
+            path,
+        };
+        std::fs::write(&restore.path, source).unwrap();
+        restore
+    }
+}
+
+impl Drop for RestoreTemplate {
+    fn drop(&mut self) {
+        std::fs::write(&self.path, &self.source).unwrap();
+    }
+}
+
+#[test]
+fn test_template_source() {
+    assert!(matches!(
+        GreetingComponentRef::<std::slice::Iter<String>>::SOURCE,
+        TemplateSource::Path("greeting.html")
+    ));
+    assert!(matches!(
+        InlineComponentRef::SOURCE,
+        TemplateSource::Source {
+            source: "<b>{{ text }}</b>",
+            ext: "html"
+        }
+    ));
+    assert_eq!(InlineComponentRef::NAME, "InlineComponent");
+}
+
+#[test]
+fn test_render_from_disk() {
+    template_reload::set_enabled(true);
+    template_reload::add_function("crate::shout", |name: String| shout(&name));
+
+    let greeting = GreetingComponent {
+        name: "<Ann>".into(),
+        items: vec!["Milk".into(), "Eggs".into()],
+    };
+    let expected = "<p>Hello &lt;Ann&gt;, &lt;ANN&gt;</p><li>Milk</li><li>Eggs</li>";
+    assert_eq!(
+        askama::Template::render(&greeting.as_ref()).unwrap(),
+        expected
+    );
+    assert_eq!(
+        template_reload::render(&greeting.as_ref()).unwrap(),
+        expected
+    );
+    assert!(greeting.as_ref().context().is_some());
+
+    let reload = ReloadComponent {
+        text: "Milk".into(),
+    };
+    let label = LabelComponent {
+        text: Label("Eggs"),
+    };
+    assert!(label.as_ref().context().is_none());
+    assert_eq!(
+        template_reload::render(&reload.as_ref()).unwrap(),
+        "<div><p>Milk</p></div>"
+    );
+
+    {
+        let _restore = RestoreTemplate::write("reload_test.html", "<p>{{ text }}!</p>");
+        assert_eq!(
+            template_reload::render(&reload.as_ref()).unwrap(),
+            "<div><p>Milk!</p></div>"
+        );
+        // Also with whitespace control, and without a space before the tag name
+        let trimmed = TrimmedReloadComponent { text: "Tea".into() };
+        assert_eq!(
+            template_reload::render(&trimmed.as_ref()).unwrap(),
+            "<div><p>Tea!</p></div>"
+        );
+        // Without `Serialize` fields the compiled template is used
+        assert_eq!(
+            template_reload::render(&label.as_ref()).unwrap(),
+            "<div><p>Eggs</p></div>"
+        );
+
+        // Templates minijinja can't render also fall back to the compiled template
+        let _broken =
+            RestoreTemplate::write("reload_test.html", "<p>{{ text|unknown_filter }}</p>");
+        assert_eq!(
+            template_reload::render(&reload.as_ref()).unwrap(),
+            "<div><p>Milk</p></div>"
+        );
+    }
+
+    template_reload::set_enabled(false);
+    let _restore = RestoreTemplate::write("reload_test.html", "<p>{{ text }}!</p>");
+    assert_eq!(
+        template_reload::render(&reload.as_ref()).unwrap(),
+        "<div><p>Milk</p></div>"
+    );
+}
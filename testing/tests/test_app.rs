@@ -0,0 +1,204 @@
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Form, Router,
+};
+use break_stack::auth::UserId;
+use break_stack::errors::*;
+use break_stack::htmx::{HtmxRequest, HtmxResponse};
+use break_stack::models::{DBConn, DBPool};
+use break_stack::session::SessionId;
+use break_stack::testing::TestApp;
+use serde::Deserialize;
+
+#[derive(Clone)]
+struct AppState {
+    db_pool: DBPool,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for DBConn {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        _parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        Ok(state.db_pool.acquire().await?.into())
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for UserId {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<UserId>()
+            .copied()
+            .ok_or(AppError::Auth(AuthError::Unauthenticated))
+    }
+}
+
+#[derive(Deserialize)]
+struct ItemForm {
+    name: String,
+}
+
+async fn list_items(mut conn: DBConn, user_id: UserId) -> AppResult<Response> {
+    let names: Vec<String> =
+        sqlx::query_scalar("SELECT name FROM items WHERE user_id = ? ORDER BY id")
+            .bind(*user_id)
+            .fetch_all(&mut *conn)
+            .await?;
+    let items: String = names
+        .iter()
+        .map(|name| format!("<li>{}</li>\n", name))
+        .collect();
+    Ok(format!("<ul>\n  {}</ul>", items).into_response())
+}
+
+async fn create_item(
+    mut conn: DBConn,
+    user_id: UserId,
+    htmx: HtmxRequest,
+    Form(form): Form<ItemForm>,
+) -> AppResult<Response> {
+    if form.name.is_empty() {
+        return Err(AppError::BadRequest("name is required".to_string()));
+    }
+    sqlx::query("INSERT INTO items (user_id, name) VALUES (?, ?)")
+        .bind(*user_id)
+        .bind(&form.name)
+        .execute(&mut *conn)
+        .await?;
+    let response = HtmxResponse::new(format!("<li>{}</li>", form.name)).trigger("ItemCreated");
+    if htmx.is_htmx {
+        response.try_into_response()
+    } else {
+        response.redirect("/items").try_into_response()
+    }
+}
+
+async fn session(session: SessionId) -> Response {
+    session.apply(session.as_str().to_string().into_response())
+}
+
+async fn app() -> TestApp {
+    let app = TestApp::new(|db_pool| {
+        Router::new()
+            .route("/items", get(list_items).post(create_item))
+            .route("/session", get(session))
+            .with_state(AppState { db_pool })
+    })
+    .await;
+    sqlx::query(
+        "CREATE TABLE items (id INTEGER PRIMARY KEY, user_id INTEGER NOT NULL, name TEXT NOT NULL)",
+    )
+    .execute(&mut *app.conn().await)
+    .await
+    .unwrap();
+    app
+}
+
+#[tokio::test]
+async fn test_app_requests() {
+    let app = app().await;
+
+    app.get("/items")
+        .send()
+        .await
+        .assert_status(StatusCode::UNAUTHORIZED);
+
+    app.post("/items")
+        .user(UserId(1))
+        .htmx()
+        .form(&[("name", "Milk")])
+        .send()
+        .await
+        .assert_ok()
+        .assert_triggered("ItemCreated")
+        .assert_not_triggered("ItemDeleted")
+        .assert_contains("<li>Milk</li>");
+
+    // Not sent by htmx, so the response redirects to the list, which is followed
+    let response = app
+        .post("/items")
+        .user(UserId(1))
+        .form(&[("name", "Eggs")])
+        .send()
+        .await;
+    assert_eq!(response.uri(), "/items");
+    response
+        .assert_ok()
+        .assert_not_triggered("ItemCreated")
+        .assert_contains("<ul><li>Milk</li> <li>Eggs</li></ul>");
+    response.dom().assert_count("ul li", 2);
+
+    let response = app
+        .post("/items")
+        .user(UserId(1))
+        .form(&[("name", "Bread")])
+        .no_follow()
+        .send()
+        .await;
+    assert_eq!(response.hx_redirect(), Some("/items"));
+    assert_eq!(response.triggers(), vec!["ItemCreated".to_string()]);
+
+    app.get("/items")
+        .user(UserId(2))
+        .send()
+        .await
+        .assert_ok()
+        .assert_not_contains("Milk");
+
+    app.post("/items")
+        .user(UserId(2))
+        .htmx()
+        .form(&[("name", "")])
+        .send()
+        .await
+        .assert_status(StatusCode::BAD_REQUEST)
+        .assert_contains("Bad request");
+
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM items")
+        .fetch_one(&mut *app.conn().await)
+        .await
+        .unwrap();
+    assert_eq!(count, 3);
+}
+
+#[tokio::test]
+async fn test_app_cookies() {
+    let app = app().await;
+
+    let first = app.get("/session").send().await;
+    assert!(first.header("set-cookie").is_some());
+    let second = app.get("/session").send().await;
+    assert!(second.header("set-cookie").is_none());
+    assert_eq!(first.text(), second.text());
+
+    app.clear_cookies();
+    let third = app.get("/session").send().await;
+    assert_ne!(first.text(), third.text());
+
+    // The break_stack tables are created
+    sqlx::query("SELECT * FROM break_stack_wizard_drafts")
+        .execute(&mut *app.conn().await)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+#[should_panic(expected = "unexpected status")]
+async fn test_app_assert_status() {
+    let app = app().await;
+    app.get("/items").send().await.assert_ok();
+}
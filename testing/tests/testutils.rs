@@ -0,0 +1,229 @@
+use axum::{
+    extract::Path,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Form,
+};
+use break_stack::auth::*;
+use break_stack::controllers::*;
+use break_stack::errors::*;
+use break_stack::etag::IfNoneMatch;
+use break_stack::htmx::HtmxRequest;
+use break_stack::models::testutils::*;
+use break_stack::models::*;
+
+#[derive(Debug, Clone)]
+struct NoteModel {
+    id: i64,
+    owner: i64,
+    text: String,
+}
+impl Model for NoteModel {
+    type ID = i64;
+    type Conn = MockConn;
+
+    const MODEL_NAME: &'static str = "Note";
+}
+impl ModelRead for NoteModel {
+    async fn read(conn: &mut MockConn, id: i64) -> Result<Option<Self>, ModelError> {
+        Ok(conn.rows::<Self>().iter().find(|m| m.id == id).cloned())
+    }
+}
+impl ModelWrite for NoteModel {
+    type Write = String;
+
+    async fn write(conn: &mut MockConn, id: i64, data: String) -> Result<Option<Self>, ModelError> {
+        let Some(note) = conn.rows::<Self>().iter_mut().find(|m| m.id == id) else {
+            return Ok(None);
+        };
+        note.text = data;
+        Ok(Some(note.clone()))
+    }
+}
+impl ModelCreate for NoteModel {
+    type Create = (i64, String);
+
+    async fn create(conn: &mut MockConn, (owner, text): (i64, String)) -> Result<Self, ModelError> {
+        let rows = conn.rows::<Self>();
+        let id = rows.iter().map(|m| m.id).max().unwrap_or(0) + 1;
+        rows.push(Self { id, owner, text });
+        Ok(rows[rows.len() - 1].clone())
+    }
+}
+impl ModelDelete for NoteModel {
+    async fn delete(conn: &mut MockConn, id: i64) -> Result<Self, ModelError> {
+        let rows = conn.rows::<Self>();
+        let index = rows
+            .iter()
+            .position(|m| m.id == id)
+            .ok_or(ModelError::NotFound)?;
+        Ok(rows.remove(index))
+    }
+}
+impl WithOwnerModel for NoteModel {
+    async fn owner(conn: &mut MockConn, id: i64) -> Result<Option<i64>, ModelError> {
+        Ok(Self::read(conn, id).await?.map(|m| m.owner))
+    }
+    async fn all_for_owner(conn: &mut MockConn, user_id: i64) -> Result<Vec<Self>, ModelError> {
+        Ok(conn
+            .rows::<Self>()
+            .iter()
+            .filter(|m| m.owner == user_id)
+            .cloned()
+            .collect())
+    }
+}
+impl OwnerAuthModelRead for NoteModel {}
+impl OwnerAuthModelWrite for NoteModel {}
+impl OwnerAuthModelCreate for NoteModel {
+    async fn will_be_owner(_conn: &mut MockConn, data: &(i64, String)) -> Result<i64, ModelError> {
+        Ok(data.0)
+    }
+}
+impl OwnerAuthModelDelete for NoteModel {}
+impl OwnerAuthModelList for NoteModel {}
+
+struct NoteController;
+
+impl ModelController for NoteController {
+    type Model = NoteModel;
+
+    async fn build_response(
+        _conn: &mut MockConn,
+        _user_id: Option<UserId>,
+        m: Self::Model,
+    ) -> AppResult<Response> {
+        Ok(format!("<p>{}</p>", m.text).into_response())
+    }
+}
+
+fn conn() -> MockConn {
+    MockConn::new().with_rows([
+        NoteModel {
+            id: 1,
+            owner: 1,
+            text: "Milk".to_string(),
+        },
+        NoteModel {
+            id: 2,
+            owner: 1,
+            text: "Eggs".to_string(),
+        },
+        NoteModel {
+            id: 3,
+            owner: 2,
+            text: "Bread".to_string(),
+        },
+    ])
+}
+
+#[tokio::test]
+async fn test_model_test_cases() {
+    let mut conn = conn();
+    let created = NoteModel::create(&mut conn, (2, "Butter".to_string()))
+        .await
+        .unwrap();
+
+    model_read_test_cases!(NoteModel, &mut conn, [
+        "existing note": 1 => Some(NoteModel { owner: 1, .. }),
+        "id from a created note": created.id => Some(NoteModel { ref text, .. }) if text == "Butter",
+        "missing note": 10 => None,
+    ]);
+    model_write_test_cases!(NoteModel, &mut conn, [
+        "existing note": (1, "Oat milk".to_string()) => Some(NoteModel { ref text, .. }) if text == "Oat milk",
+        "missing note": (10, "Oat milk".to_string()) => None,
+    ]);
+    model_create_test_cases!(NoteModel, &mut conn, [
+        "new note": (1, "Jam".to_string()) => NoteModel { id: 5, owner: 1, .. },
+    ]);
+    model_owner_test_cases!(NoteModel, &mut conn, [
+        "own note": 1 => Some(1),
+        "missing note": 10 => None,
+    ]);
+    model_all_for_owner_test_cases!(NoteModel, &mut conn, [
+        "user with notes": 2 => [NoteModel { id: 3, .. }, NoteModel { id: 4, .. }],
+        "user without notes": 3 => [],
+    ]);
+    auth_model_read_test_cases!(NoteModel, &mut conn, [
+        "owner": (1, Some(UserId(1))) => Ok(()),
+        "other user": (1, Some(UserId(2))) => Err(AuthError::Unauthorized),
+        "no user": (1, None) => Err(AuthError::Unauthenticated),
+    ]);
+    auth_model_write_test_cases!(NoteModel, &mut conn, [
+        "owner": (1, Some(UserId(1)), &"Tea".to_string()) => Ok(()),
+        "other user": (1, Some(UserId(2)), &"Tea".to_string()) => Err(AuthError::Unauthorized),
+    ]);
+    auth_model_create_test_cases!(NoteModel, &mut conn, [
+        "for self": (Some(UserId(1)), &(1, "Tea".to_string())) => Ok(()),
+        "for other user": (Some(UserId(1)), &(2, "Tea".to_string())) => Err(AuthError::Unauthorized),
+    ]);
+    auth_model_delete_test_cases!(NoteModel, &mut conn, [
+        "owner": (1, Some(UserId(1))) => Ok(()),
+        "other user": (1, Some(UserId(2))) => Err(AuthError::Unauthorized),
+        "missing note": (10, Some(UserId(1))) => Err(AuthError::Unauthorized),
+    ]);
+    auth_model_list_test_cases!(NoteModel, &mut conn, [
+        "user": Some(UserId(1)) => Ok([_, _, _]),
+        "no user": None => Err(AuthError::Unauthenticated),
+    ]);
+    model_delete_test_cases!(NoteModel, &mut conn, [
+        "existing note": 1 => Ok(NoteModel { id: 1, .. }),
+        "deleted note": 1 => Err(ModelError::NotFound),
+    ]);
+}
+
+#[tokio::test]
+async fn test_controller_test_cases() {
+    controller_test_cases!([
+        "read": model_controller_read::<NoteController>(
+            conn(), Path(1), Some(UserId(1)), HtmxRequest::default(), IfNoneMatch::default(),
+        ) => |response| response.assert_ok().assert_contains("<p>Milk</p>"),
+        "read other user's note": model_controller_read::<NoteController>(
+            conn(), Path(3), Some(UserId(1)), HtmxRequest::default(), IfNoneMatch::default(),
+        ) => |response| response.assert_status(StatusCode::FORBIDDEN),
+        "write": model_controller_write::<NoteController>(
+            conn(), Path(1), Some(UserId(1)), HtmxRequest::default(), Form("Tea".to_string()),
+        ) => |response| response
+            .assert_ok()
+            .assert_contains("<p>Tea</p>")
+            .assert_triggered("NoteUpdated"),
+        "create": model_controller_create::<NoteController>(
+            conn(), Some(UserId(2)), HtmxRequest::default(), Form((2, "Tea".to_string())),
+        ) => |response| response.assert_ok().assert_triggered("NoteCreated"),
+        "delete": model_controller_delete::<NoteController>(
+            conn(), Path(2), Some(UserId(1)), HtmxRequest::default(),
+        ) => |response| response.assert_ok().assert_triggered("NoteDeleted"),
+        "delete without user": model_controller_delete::<NoteController>(
+            conn(), Path(2), None, HtmxRequest::default(),
+        ) => |response| response
+            .assert_status(StatusCode::UNAUTHORIZED)
+            .assert_not_triggered("NoteDeleted"),
+    ]);
+}
+
+#[tokio::test]
+#[should_panic(expected = "2 of 3 cases of model_read_test_cases failed\n\n\
+    Case 'wrong owner'\nOutput of ModelRead::read didn't match expected output:\n\
+    Expected output to match: Some(NoteModel { owner: 2, .. })\n")]
+async fn test_test_cases_report_all_failures() {
+    let mut conn = conn();
+    model_read_test_cases!(NoteModel, &mut conn, [
+        "wrong owner": 1 => Some(NoteModel { owner: 2, .. }),
+        "existing note": 2 => Some(_),
+        "missing note": 10 => Some(_),
+    ]);
+}
+
+#[tokio::test]
+#[should_panic(expected = "1 of 2 cases of controller_test_cases failed\n\n\
+    Case 'wrong status'\nassertion `left == right` failed: unexpected status")]
+async fn test_controller_test_cases_report_failures() {
+    controller_test_cases!([
+        "wrong status": model_controller_delete::<NoteController>(
+            conn(), Path(2), None, HtmxRequest::default(),
+        ) => |response| response.assert_ok(),
+        "passing": model_controller_delete::<NoteController>(
+            conn(), Path(2), Some(UserId(1)), HtmxRequest::default(),
+        ) => |response| response.assert_ok(),
+    ]);
+}
@@ -0,0 +1,313 @@
+use axum::{
+    extract::{OriginalUri, Path},
+    http::Uri,
+    response::{IntoResponse, Response},
+};
+use break_stack::auth::*;
+use break_stack::controllers::*;
+use break_stack::errors::*;
+use break_stack::htmx::HtmxRequest;
+use break_stack::models::*;
+use break_stack::testing::TestResponse;
+use break_stack::undo;
+use std::time::Duration;
+
+#[derive(Debug, sqlx::FromRow)]
+struct TestModel {
+    id: i64,
+    name: String,
+}
+impl Model for TestModel {
+    type ID = i64;
+    type Conn = DBConn;
+
+    const MODEL_NAME: &'static str = "Test";
+}
+impl ModelRead for TestModel {
+    async fn read(conn: &mut DBConn, id: i64) -> Result<Option<Self>, ModelError> {
+        Ok(
+            sqlx::query_as("SELECT id, name FROM items WHERE id = ? AND NOT hidden")
+                .bind(id)
+                .fetch_optional(&mut **conn)
+                .await?,
+        )
+    }
+}
+impl ModelDelete for TestModel {
+    async fn delete(conn: &mut DBConn, id: i64) -> Result<Self, ModelError> {
+        Ok(
+            sqlx::query_as("DELETE FROM items WHERE id = ? RETURNING id, name")
+                .bind(id)
+                .fetch_one(&mut **conn)
+                .await?,
+        )
+    }
+}
+impl ModelSoftDelete for TestModel {
+    async fn hide(conn: &mut DBConn, id: i64) -> Result<Self, ModelError> {
+        Ok(sqlx::query_as(
+            "UPDATE items SET hidden = TRUE WHERE id = ? AND NOT hidden RETURNING id, name",
+        )
+        .bind(id)
+        .fetch_one(&mut **conn)
+        .await?)
+    }
+
+    async fn restore(conn: &mut DBConn, id: i64) -> Result<Option<Self>, ModelError> {
+        Ok(sqlx::query_as(
+            "UPDATE items SET hidden = FALSE WHERE id = ? AND hidden RETURNING id, name",
+        )
+        .bind(id)
+        .fetch_optional(&mut **conn)
+        .await?)
+    }
+}
+impl AuthModelDelete for TestModel {
+    async fn can_delete(
+        _conn: &mut DBConn,
+        _id: i64,
+        user_id: Option<UserId>,
+    ) -> Result<(), AuthError> {
+        match user_id {
+            None => Err(AuthError::Unauthenticated),
+            Some(UserId(0)) => Ok(()),
+            Some(_) => Err(AuthError::Unauthorized),
+        }
+    }
+}
+
+struct TestModelController;
+impl ModelController for TestModelController {
+    type Model = TestModel;
+
+    const FLASH_DELETED: Option<&'static str> = Some("Item <deleted>");
+    const UNDO_WINDOW: Duration = Duration::from_secs(60);
+
+    async fn build_response(
+        _conn: &mut DBConn,
+        _user_id: Option<UserId>,
+        m: Self::Model,
+    ) -> AppResult<Response> {
+        Ok(format!("{}:{}", m.id, m.name).into_response())
+    }
+
+    async fn after_delete(
+        conn: &mut DBConn,
+        user_id: Option<UserId>,
+        item: &Self::Model,
+    ) -> AppResult<()> {
+        sqlx::query("INSERT INTO purged (id, user_id) VALUES (?, ?)")
+            .bind(item.id)
+            .bind(user_id.map(|user_id| *user_id))
+            .execute(&mut **conn)
+            .await?;
+        Ok(())
+    }
+}
+
+async fn setup(pool: &sqlx::Pool<sqlx::Sqlite>) {
+    let mut conn: DBConn = pool.acquire().await.unwrap().into();
+    break_stack::migrations::run(&mut conn).await.unwrap();
+    sqlx::raw_sql(
+        "CREATE TABLE items (id INTEGER PRIMARY KEY NOT NULL, name TEXT NOT NULL, hidden BOOLEAN NOT NULL DEFAULT FALSE);
+        CREATE TABLE purged (id INTEGER NOT NULL, user_id INTEGER);
+        INSERT INTO items (id, name) VALUES (1, 'first'), (2, 'second');",
+    )
+    .execute(&mut *conn)
+    .await
+    .unwrap();
+}
+
+async fn delete(
+    pool: &sqlx::Pool<sqlx::Sqlite>,
+    id: i64,
+    user_id: Option<i64>,
+) -> AppResult<Response> {
+    model_controller_delete_undoable::<TestModelController>(
+        pool.acquire().await.unwrap().into(),
+        Path(id),
+        user_id.map(UserId),
+        HtmxRequest::default(),
+        OriginalUri(Uri::try_from(format!("/items/{}", id)).unwrap()),
+    )
+    .await
+}
+
+async fn restore(
+    pool: &sqlx::Pool<sqlx::Sqlite>,
+    id: i64,
+    user_id: Option<i64>,
+) -> AppResult<Response> {
+    model_controller_restore::<TestModelController>(
+        pool.acquire().await.unwrap().into(),
+        Path(id),
+        user_id.map(UserId),
+        HtmxRequest::default(),
+    )
+    .await
+}
+
+async fn read(pool: &sqlx::Pool<sqlx::Sqlite>, id: i64) -> Option<TestModel> {
+    TestModel::read(&mut pool.acquire().await.unwrap().into(), id)
+        .await
+        .unwrap()
+}
+
+#[sqlx::test]
+async fn test_delete_undo(pool: sqlx::Pool<sqlx::Sqlite>) {
+    setup(&pool).await;
+
+    assert!(matches!(
+        delete(&pool, 1, None).await,
+        Err(AppError::Auth(AuthError::Unauthenticated))
+    ));
+    assert!(matches!(
+        delete(&pool, 1, Some(1)).await,
+        Err(AppError::Auth(AuthError::Unauthorized))
+    ));
+    assert!(matches!(
+        delete(&pool, 3, Some(0)).await,
+        Err(AppError::Model(ModelError::NotFound))
+    ));
+    assert!(read(&pool, 1).await.is_some());
+
+    let response = delete(&pool, 1, Some(0)).await.unwrap();
+    assert_eq!(
+        response.headers().get("HX-Trigger").unwrap(),
+        &TestModel::event_deleted()
+    );
+    TestResponse::from_response(response)
+        .await
+        .assert_contains("Item &lt;deleted&gt;")
+        .assert_contains(r#"hx-post="/items/1/restore""#)
+        .assert_contains("setTimeout(() => this.remove(), 60000)");
+    assert!(read(&pool, 1).await.is_none());
+
+    assert!(matches!(
+        restore(&pool, 1, Some(1)).await,
+        Err(AppError::Auth(AuthError::Unauthorized))
+    ));
+    assert!(matches!(
+        restore(&pool, 2, Some(0)).await,
+        Err(AppError::NotFound)
+    ));
+
+    let response = restore(&pool, 1, Some(0)).await.unwrap();
+    assert_eq!(
+        response.headers().get("HX-Trigger").unwrap(),
+        &TestModel::event_restored()
+    );
+    assert_eq!(
+        TestResponse::from_response(response).await.text(),
+        "1:first"
+    );
+    assert!(read(&pool, 1).await.is_some());
+    // The restore can't be repeated, and the restored object isn't purged
+    assert!(matches!(
+        restore(&pool, 1, Some(0)).await,
+        Err(AppError::NotFound)
+    ));
+    sqlx::query("UPDATE break_stack_pending_deletes SET purge_at = 0")
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        undo::purge_expired::<TestModelController>(&mut pool.acquire().await.unwrap().into())
+            .await
+            .unwrap(),
+        0
+    );
+    assert!(read(&pool, 1).await.is_some());
+}
+
+#[sqlx::test]
+async fn test_delete_purge(pool: sqlx::Pool<sqlx::Sqlite>) {
+    setup(&pool).await;
+    delete(&pool, 1, Some(0)).await.unwrap();
+    delete(&pool, 2, Some(0)).await.unwrap();
+
+    // Nothing is purged within the undo window
+    let purge = || async {
+        undo::purge_expired::<TestModelController>(&mut pool.acquire().await.unwrap().into())
+            .await
+            .unwrap()
+    };
+    assert_eq!(purge().await, 0);
+
+    sqlx::query("UPDATE break_stack_pending_deletes SET purge_at = 0 WHERE model_id = '1'")
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert!(matches!(
+        restore(&pool, 1, Some(0)).await,
+        Err(AppError::NotFound)
+    ));
+    assert_eq!(purge().await, 1);
+    assert_eq!(purge().await, 0);
+
+    let items: Vec<(i64, bool)> = sqlx::query_as("SELECT id, hidden FROM items")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(items, vec![(2, true)]);
+    let purged: Vec<(i64, Option<i64>)> = sqlx::query_as("SELECT id, user_id FROM purged")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(purged, vec![(1, Some(0))]);
+
+    restore(&pool, 2, Some(0)).await.unwrap();
+    assert!(read(&pool, 2).await.is_some());
+}
+
+#[sqlx::test]
+async fn test_restore_failure(pool: sqlx::Pool<sqlx::Sqlite>) {
+    setup(&pool).await;
+    delete(&pool, 1, Some(0)).await.unwrap();
+
+    sqlx::query(
+        "CREATE TRIGGER fail_restore BEFORE UPDATE ON items BEGIN SELECT RAISE(ABORT, 'failed'); END",
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    assert!(restore(&pool, 1, Some(0)).await.is_err());
+
+    // The object is still purged when the restore fails
+    sqlx::query("UPDATE break_stack_pending_deletes SET purge_at = 0")
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        undo::purge_expired::<TestModelController>(&mut pool.acquire().await.unwrap().into())
+            .await
+            .unwrap(),
+        1
+    );
+}
+
+#[sqlx::test]
+async fn test_spawn_purge_loop(pool: sqlx::Pool<sqlx::Sqlite>) {
+    setup(&pool).await;
+    delete(&pool, 1, Some(0)).await.unwrap();
+    sqlx::query("UPDATE break_stack_pending_deletes SET purge_at = 0")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let handle =
+        undo::spawn_purge_loop::<TestModelController>(pool.clone(), Duration::from_millis(10));
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while sqlx::query("SELECT id FROM items WHERE id = 1")
+            .fetch_optional(&pool)
+            .await
+            .unwrap()
+            .is_some()
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    handle.abort();
+}
@@ -0,0 +1,336 @@
+use axum::{
+    extract::FromRequestParts,
+    http::{header, Request},
+    response::{IntoResponse, Response},
+};
+use break_stack::auth::*;
+use break_stack::components::Component;
+use break_stack::controllers::*;
+use break_stack::errors::*;
+use break_stack::hot_reload::hot_reload_enabled;
+use break_stack::htmx::HtmxRequest;
+use break_stack::models::*;
+use break_stack::session::*;
+use break_stack::testing::TestResponse;
+use break_stack::wizard::{self, *};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, sqlx::FromRow)]
+struct TestModel {
+    id: i64,
+    name: String,
+    priority: i64,
+}
+impl Model for TestModel {
+    type ID = i64;
+    type Conn = DBConn;
+
+    const MODEL_NAME: &'static str = "Test";
+}
+
+struct TestCreate {
+    name: String,
+    priority: i64,
+}
+impl ModelCreate for TestModel {
+    type Create = TestCreate;
+
+    async fn create(conn: &mut DBConn, data: TestCreate) -> Result<Self, ModelError> {
+        Ok(sqlx::query_as(
+            "INSERT INTO items (name, priority) VALUES (?, ?) RETURNING id, name, priority",
+        )
+        .bind(data.name)
+        .bind(data.priority)
+        .fetch_one(&mut **conn)
+        .await?)
+    }
+}
+impl AuthModelCreate for TestModel {
+    async fn can_create(
+        _conn: &mut DBConn,
+        user_id: Option<UserId>,
+        _data: &TestCreate,
+    ) -> Result<(), AuthError> {
+        user_id.map(|_| ()).ok_or(AuthError::Unauthenticated)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct TestDraft {
+    name: Option<String>,
+    priority: Option<i64>,
+}
+
+/// Renders the step and its state as text, instead of a form
+struct StepComponent(String);
+impl IntoResponse for StepComponent {
+    fn into_response(self) -> Response {
+        self.0.into_response()
+    }
+}
+impl Component for StepComponent {}
+
+#[derive(Deserialize)]
+struct NameStep {
+    name: String,
+}
+impl WizardStep<TestDraft> for NameStep {
+    type Component = StepComponent;
+
+    fn component(draft: &TestDraft, context: WizardStepContext) -> StepComponent {
+        StepComponent(format!(
+            "name {}/{} {:?} {:?}",
+            context.step + 1,
+            context.steps,
+            draft.name,
+            context.errors
+        ))
+    }
+
+    fn validate(&self, _draft: &TestDraft) -> Result<(), Vec<String>> {
+        if self.name.trim().is_empty() {
+            return Err(vec!["Name is required".to_string()]);
+        }
+        Ok(())
+    }
+
+    fn apply(self, draft: &mut TestDraft) {
+        draft.name = Some(self.name);
+    }
+}
+
+#[derive(Deserialize)]
+struct PriorityStep {
+    priority: i64,
+}
+impl WizardStep<TestDraft> for PriorityStep {
+    type Component = StepComponent;
+
+    fn component(draft: &TestDraft, context: WizardStepContext) -> StepComponent {
+        StepComponent(format!(
+            "priority {}/{} {:?} {:?}",
+            context.step + 1,
+            context.steps,
+            draft.priority,
+            context.errors
+        ))
+    }
+
+    fn validate(&self, _draft: &TestDraft) -> Result<(), Vec<String>> {
+        if !(1..=5).contains(&self.priority) {
+            return Err(vec!["Priority must be between 1 and 5".to_string()]);
+        }
+        Ok(())
+    }
+
+    fn apply(self, draft: &mut TestDraft) {
+        draft.priority = Some(self.priority);
+    }
+}
+
+struct TestWizard;
+impl ModelController for TestWizard {
+    type Model = TestModel;
+
+    async fn build_response(
+        _conn: &mut DBConn,
+        _user_id: Option<UserId>,
+        m: Self::Model,
+    ) -> AppResult<Response> {
+        Ok(format!("created {}:{}:{}", m.id, m.name, m.priority).into_response())
+    }
+}
+impl Wizard for TestWizard {
+    const NAME: &'static str = "test";
+    type Draft = TestDraft;
+
+    fn steps() -> Vec<WizardStepHandler<TestDraft>> {
+        vec![
+            wizard::step::<TestDraft, NameStep>(),
+            wizard::step::<TestDraft, PriorityStep>(),
+        ]
+    }
+
+    fn create_data(draft: &TestDraft) -> Result<TestCreate, Vec<String>> {
+        match (&draft.name, draft.priority) {
+            (Some(name), Some(priority)) => Ok(TestCreate {
+                name: name.clone(),
+                priority,
+            }),
+            _ => Err(vec!["Incomplete".to_string()]),
+        }
+    }
+}
+
+async fn setup(pool: &sqlx::Pool<sqlx::Sqlite>) {
+    let mut conn: DBConn = pool.acquire().await.unwrap().into();
+    break_stack::migrations::run(&mut conn).await.unwrap();
+    sqlx::query("CREATE TABLE items (id INTEGER PRIMARY KEY NOT NULL, name TEXT NOT NULL, priority INTEGER NOT NULL)")
+        .execute(&mut *conn)
+        .await
+        .unwrap();
+}
+
+async fn show(pool: &sqlx::Pool<sqlx::Sqlite>, session: &str, user_id: Option<i64>) -> String {
+    let response = wizard_controller_show::<TestWizard>(
+        pool.acquire().await.unwrap().into(),
+        user_id.map(UserId),
+        HtmxRequest::default(),
+        SessionId::existing(session),
+    )
+    .await
+    .unwrap();
+    TestResponse::from_response(response)
+        .await
+        .text()
+        .to_string()
+}
+
+async fn submit(
+    pool: &sqlx::Pool<sqlx::Sqlite>,
+    session: &str,
+    user_id: Option<i64>,
+    form: &'static str,
+) -> AppResult<String> {
+    let response = wizard_controller_submit::<TestWizard>(
+        pool.acquire().await.unwrap().into(),
+        user_id.map(UserId),
+        HtmxRequest::default(),
+        SessionId::existing(session),
+        form.into(),
+    )
+    .await?;
+    Ok(TestResponse::from_response(response)
+        .await
+        .text()
+        .to_string())
+}
+
+#[sqlx::test]
+async fn test_wizard(pool: sqlx::Pool<sqlx::Sqlite>) {
+    setup(&pool).await;
+
+    assert_eq!(show(&pool, "a", Some(0)).await, "name 1/2 None []");
+    assert_eq!(
+        submit(&pool, "a", Some(0), "name=&wizard_step=0")
+            .await
+            .unwrap(),
+        r#"name 1/2 None ["Name is required"]"#
+    );
+    assert!(submit(&pool, "a", Some(0), "other=1&wizard_step=0")
+        .await
+        .unwrap()
+        .starts_with(r#"name 1/2 None ["Invalid form: "#));
+    assert_eq!(
+        submit(&pool, "a", Some(0), "name=first&wizard_step=0")
+            .await
+            .unwrap(),
+        "priority 2/2 None []"
+    );
+    // The draft is kept between requests
+    assert_eq!(show(&pool, "a", Some(0)).await, "priority 2/2 None []");
+    // Forms of other steps, or without the step, aren't applied
+    for form in ["name=again&wizard_step=0", "priority=1"] {
+        assert_eq!(
+            submit(&pool, "a", Some(0), form).await.unwrap(),
+            r#"priority 2/2 None ["This form is out of date, please fill in this step"]"#
+        );
+    }
+    assert_eq!(
+        submit(&pool, "a", Some(0), "priority=9&wizard_step=1")
+            .await
+            .unwrap(),
+        r#"priority 2/2 None ["Priority must be between 1 and 5"]"#
+    );
+    assert_eq!(
+        submit(
+            &pool,
+            "a",
+            Some(0),
+            "priority=9&wizard_back=1&wizard_step=1"
+        )
+        .await
+        .unwrap(),
+        r#"name 1/2 Some("first") []"#
+    );
+    assert_eq!(
+        submit(&pool, "a", Some(0), "name=second&wizard_step=0")
+            .await
+            .unwrap(),
+        "priority 2/2 None []"
+    );
+
+    // Drafts are per session and user
+    assert_eq!(show(&pool, "b", Some(0)).await, "name 1/2 None []");
+    assert_eq!(show(&pool, "a", Some(1)).await, "name 1/2 None []");
+
+    assert_eq!(
+        submit(&pool, "a", Some(0), "priority=2&wizard_step=1")
+            .await
+            .unwrap(),
+        "created 1:second:2"
+    );
+    assert_eq!(show(&pool, "a", Some(0)).await, "name 1/2 None []");
+}
+
+#[sqlx::test]
+async fn test_wizard_auth(pool: sqlx::Pool<sqlx::Sqlite>) {
+    setup(&pool).await;
+
+    submit(&pool, "a", None, "name=first&wizard_step=0")
+        .await
+        .unwrap();
+    assert!(matches!(
+        submit(&pool, "a", None, "priority=1&wizard_step=1").await,
+        Err(AppError::Auth(AuthError::Unauthenticated))
+    ));
+    // The draft is kept, so the last step can be submitted again
+    assert_eq!(show(&pool, "a", None).await, "priority 2/2 Some(1) []");
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM items")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(count, 0);
+
+    wizard::discard::<TestWizard>(
+        &mut pool.acquire().await.unwrap().into(),
+        &SessionId::existing("a"),
+    )
+    .await
+    .unwrap();
+    assert_eq!(show(&pool, "a", None).await, "name 1/2 None []");
+}
+
+#[tokio::test]
+async fn test_session_id() {
+    let extract = |cookie: Option<&'static str>| async move {
+        let mut request = Request::get("/");
+        if let Some(cookie) = cookie {
+            request = request.header(header::COOKIE, cookie);
+        }
+        let (mut parts, _) = request.body(()).unwrap().into_parts();
+        SessionId::from_request_parts(&mut parts, &())
+            .await
+            .unwrap()
+    };
+
+    let session = extract(Some("other=1; break_stack_session=0a-1b")).await;
+    assert!(!session.is_new());
+    assert_eq!(session.as_str(), "0a-1b");
+    let response = session.apply("".into_response());
+    assert!(response.headers().get(header::SET_COOKIE).is_none());
+
+    for cookie in [None, Some("other=1"), Some("break_stack_session=<script>")] {
+        let session = extract(cookie).await;
+        assert!(session.is_new());
+        let response = session.apply("".into_response());
+        assert_eq!(
+            response.headers().get(header::SET_COOKIE).unwrap(),
+            &format!(
+                "break_stack_session={}; Path=/; HttpOnly; SameSite=Lax{}",
+                session.as_str(),
+                if hot_reload_enabled() { "" } else { "; Secure" }
+            )
+        );
+    }
+}